@@ -0,0 +1,79 @@
+//! A benchmark of the rule matching on a synthetic large document.
+//! Run with `cargo bench -p dom`.
+
+use dom::{css, html, style::RuleIndex};
+use std::time::{Duration, Instant};
+
+const ITERATIONS: u32 = 5;
+
+/// `document` generates `n` sections with a paragraph and a list each.
+fn document(n: usize) -> String {
+    let sections: String = (0..n)
+        .map(|i| {
+            format!(
+                r#"<div class="section-{}" id="s{}"><p class="text">section {}</p><ul><li>a</li><li>b</li></ul></div>"#,
+                i % 50,
+                i,
+                i
+            )
+        })
+        .collect();
+    format!("<body>{}</body>", sections)
+}
+
+/// `stylesheet` generates rules for each class, id and tag used in `document(n)`.
+fn stylesheet(n: usize) -> String {
+    let mut css = String::from("body, div, p, ul { display: block; } li { display: list-item; } ");
+    for i in 0..50 {
+        css += &format!(".section-{} {{ margin: {}px; }} ", i, i);
+    }
+    for i in (0..n).step_by(10) {
+        css += &format!("#s{} {{ padding: 1px; }} ", i);
+    }
+    css
+}
+
+fn measure(name: &str, mut f: impl FnMut() -> usize) {
+    let mut total = Duration::ZERO;
+    let mut matched = 0;
+    for _ in 0..ITERATIONS {
+        let start = Instant::now();
+        matched = f();
+        total += start.elapsed();
+    }
+    println!(
+        "{:<10} {:>10.2?}/iter ({} matches)",
+        name,
+        total / ITERATIONS,
+        matched
+    );
+}
+
+fn main() {
+    let n = 1000;
+    let node = html::parse(&document(n)).unwrap();
+    let stylesheet = css::parse(&stylesheet(n)).unwrap();
+    println!(
+        "{} nodes, {} rules",
+        node.node_count(),
+        stylesheet.rules.len()
+    );
+
+    measure("naive", || {
+        node.descendants()
+            .map(|node| stylesheet.rules.iter().filter(|r| r.matches(node)).count())
+            .sum()
+    });
+    measure("indexed", || {
+        let index = RuleIndex::new(&stylesheet);
+        node.descendants()
+            .map(|node| {
+                index
+                    .candidates(node)
+                    .into_iter()
+                    .filter(|r| r.matches(node))
+                    .count()
+            })
+            .sum()
+    });
+}
@@ -0,0 +1,169 @@
+//! This module includes the color model shared by CSS parsing, style computation and painting.
+
+use serde::{Deserialize, Serialize};
+
+/// `NAMED_COLORS` are the basic named colors and their RGB channels.
+/// See https://www.w3.org/TR/css-color-4/#named-colors
+const NAMED_COLORS: &[(&str, (u8, u8, u8))] = &[
+    ("black", (0, 0, 0)),
+    ("silver", (192, 192, 192)),
+    ("gray", (128, 128, 128)),
+    ("white", (255, 255, 255)),
+    ("maroon", (128, 0, 0)),
+    ("red", (255, 0, 0)),
+    ("purple", (128, 0, 128)),
+    ("fuchsia", (255, 0, 255)),
+    ("green", (0, 128, 0)),
+    ("lime", (0, 255, 0)),
+    ("olive", (128, 128, 0)),
+    ("yellow", (255, 255, 0)),
+    ("navy", (0, 0, 128)),
+    ("blue", (0, 0, 255)),
+    ("teal", (0, 128, 128)),
+    ("aqua", (0, 255, 255)),
+    ("orange", (255, 165, 0)),
+];
+
+/// `Color` is an sRGB color with an alpha channel.
+/// See https://www.w3.org/TR/css-color-4/#rgb-functions
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    /// `a` is the alpha channel ranging from 0 (transparent) to 1 (opaque).
+    pub a: f64,
+}
+
+impl Color {
+    /// `from_rgb` returns the opaque color of the channels.
+    pub fn from_rgb(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b, a: 1. }
+    }
+
+    /// `from_hex` parses `#rgb`, `#rgba`, `#rrggbb` or `#rrggbbaa`.
+    /// See https://www.w3.org/TR/css-color-4/#hex-notation
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        let digits = hex.strip_prefix('#')?;
+        if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        let channel = |s: &str| u8::from_str_radix(s, 16).ok();
+        // a shorthand digit is doubled, e.g. `f` is `ff`
+        let short = |i: usize| channel(&digits[i..i + 1].repeat(2));
+        let long = |i: usize| channel(&digits[i * 2..i * 2 + 2]);
+        let (r, g, b, a) = match digits.len() {
+            3 => (short(0)?, short(1)?, short(2)?, 255),
+            4 => (short(0)?, short(1)?, short(2)?, short(3)?),
+            6 => (long(0)?, long(1)?, long(2)?, 255),
+            8 => (long(0)?, long(1)?, long(2)?, long(3)?),
+            _ => return None,
+        };
+        Some(Self {
+            r,
+            g,
+            b,
+            a: a as f64 / 255.,
+        })
+    }
+
+    /// `from_named` returns the color of a named color like `red` in any case.
+    /// `transparent` is also accepted.
+    pub fn from_named(name: &str) -> Option<Self> {
+        if name.eq_ignore_ascii_case("transparent") {
+            return Some(Self {
+                r: 0,
+                g: 0,
+                b: 0,
+                a: 0.,
+            });
+        }
+        NAMED_COLORS
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, (r, g, b))| Self::from_rgb(*r, *g, *b))
+    }
+
+    /// `to_css_string` formats the color as `rgb(r, g, b)`, or `rgba(r, g, b, a)` if it is not opaque.
+    pub fn to_css_string(&self) -> String {
+        if self.a >= 1. {
+            format!("rgb({}, {}, {})", self.r, self.g, self.b)
+        } else {
+            format!("rgba({}, {}, {}, {})", self.r, self.g, self.b, self.a)
+        }
+    }
+
+    /// `to_canvas_style` formats the color for `fillStyle` and `strokeStyle` of a canvas.
+    /// The alpha channel is always written so that the previous style never leaks.
+    pub fn to_canvas_style(&self) -> String {
+        format!("rgba({}, {}, {}, {})", self.r, self.g, self.b, self.a)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_rgb() {
+        assert_eq!(
+            Color::from_rgb(1, 2, 3),
+            Color {
+                r: 1,
+                g: 2,
+                b: 3,
+                a: 1.
+            }
+        );
+    }
+
+    #[test]
+    fn test_from_hex() {
+        assert_eq!(Color::from_hex("#ff0000"), Some(Color::from_rgb(255, 0, 0)));
+        assert_eq!(Color::from_hex("#0F0"), Some(Color::from_rgb(0, 255, 0)));
+        assert_eq!(
+            Color::from_hex("#0000ff80"),
+            Some(Color {
+                r: 0,
+                g: 0,
+                b: 255,
+                a: 128. / 255.
+            })
+        );
+        assert_eq!(
+            Color::from_hex("#0000"),
+            Some(Color {
+                r: 0,
+                g: 0,
+                b: 0,
+                a: 0.
+            })
+        );
+        assert_eq!(Color::from_hex("ff0000"), None);
+        assert_eq!(
+            Color::from_hex("#ff00"),
+            Some(Color::from_hex("#ffff0000").unwrap())
+        );
+        assert_eq!(Color::from_hex("#ff000"), None);
+        assert_eq!(Color::from_hex("#gg0000"), None);
+    }
+
+    #[test]
+    fn test_from_named() {
+        assert_eq!(Color::from_named("red"), Some(Color::from_rgb(255, 0, 0)));
+        assert_eq!(Color::from_named("Navy"), Some(Color::from_rgb(0, 0, 128)));
+        assert_eq!(Color::from_named("transparent").map(|c| c.a), Some(0.));
+        assert_eq!(Color::from_named("solid"), None);
+    }
+
+    #[test]
+    fn test_to_string() {
+        let red = Color::from_rgb(255, 0, 0);
+        assert_eq!(red.to_css_string(), "rgb(255, 0, 0)");
+        assert_eq!(red.to_canvas_style(), "rgba(255, 0, 0, 1)");
+
+        let translucent = Color { a: 0.5, ..red };
+        assert_eq!(translucent.to_css_string(), "rgba(255, 0, 0, 0.5)");
+        assert_eq!(translucent.to_canvas_style(), "rgba(255, 0, 0, 0.5)");
+    }
+}
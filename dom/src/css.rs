@@ -1,24 +1,119 @@
+pub use crate::color::Color;
 use crate::dom::{Node, NodeType};
+use crate::parse_error;
 use anyhow::Result;
 use combine::{
-    choice,
+    attempt, choice, eof,
     error::StreamError,
-    many1, optional,
-    parser::char::{self, char, letter, spaces, string},
-    sep_by, sep_end_by, ParseError, Parser, Stream,
+    look_ahead, many, many1, optional,
+    parser::char::{self, alpha_num, char, digit, letter, space, spaces, string},
+    satisfy, sep_by, sep_by1, sep_end_by,
+    stream::position,
+    EasyParser, ParseError, Parser, Stream, StreamOnce,
 };
 use serde::{Deserialize, Serialize};
 
+type StreamErrorFor<Input> = <<Input as StreamOnce>::Error as ParseError<
+    char,
+    <Input as StreamOnce>::Range,
+    <Input as StreamOnce>::Position,
+>>::StreamError;
+
 /// `Stylesheet` represents a single stylesheet.
 /// It consists of multiple rules, which are called "rule-list" in the standard (https://www.w3.org/TR/css-syntax-3/).
 #[derive(Debug, PartialEq)]
 pub struct Stylesheet {
     pub rules: Vec<Rule>,
+    /// `imports` are the URLs of `@import` in source order.
+    /// They are not fetched by the parser; the caller loads them and puts their rules before `rules`.
+    pub imports: Vec<String>,
 }
 
 impl Stylesheet {
     pub fn new(rules: Vec<Rule>) -> Self {
-        Stylesheet { rules: rules }
+        Stylesheet {
+            rules,
+            imports: vec![],
+        }
+    }
+
+    /// `merge` appends the rules and the imports of `other` after the ones of `self`.
+    /// Since later rules win at equal specificity, `other` takes precedence over `self`.
+    pub fn merge(mut self, other: Stylesheet) -> Stylesheet {
+        self.rules.extend(other.rules);
+        self.imports.extend(other.imports);
+        self
+    }
+
+    /// `rules_for` returns the rules which have a selector matching `node`, in source order.
+    /// `node` is matched as a root since its parent is unknown.
+    pub fn rules_for<'a>(&'a self, node: &Node) -> Vec<&'a Rule> {
+        self.rules
+            .iter()
+            .filter(|rule| rule.matches(node))
+            .collect()
+    }
+
+    /// `normalize` merges each rule into the preceding rule with the same set of selectors, and drops rules without declarations.
+    /// A rule is merged only if no rule in between declares an overlapping property, so that the cascade is not changed.
+    /// Within a merged rule, the last value of each property wins.
+    pub fn normalize(&mut self) {
+        let rules = std::mem::take(&mut self.rules);
+        for rule in rules {
+            if rule.declarations.is_empty() {
+                continue;
+            }
+            let target = self
+                .rules
+                .iter()
+                .rposition(|r| r.has_same_selectors(&rule))
+                .filter(|&i| {
+                    self.rules[i + 1..]
+                        .iter()
+                        .all(|between| !between.overlaps(&rule))
+                });
+            let Some(i) = target else {
+                self.rules.push(rule);
+                continue;
+            };
+            let declarations = &mut self.rules[i].declarations;
+            for declaration in rule.declarations {
+                declarations.retain(|d| d.name != declaration.name);
+                declarations.push(declaration);
+            }
+        }
+    }
+
+    /// `to_css_string` serializes the stylesheet so that `parse` gives back an equal stylesheet.
+    /// Consecutive rules under the same media condition are written in one `@media` block.
+    pub fn to_css_string(&self) -> String {
+        let mut blocks: Vec<String> = self
+            .imports
+            .iter()
+            .map(|url| format!("@import {};", quote(url)))
+            .collect();
+        let mut i = 0;
+        while i < self.rules.len() {
+            let media = &self.rules[i].media;
+            let end = self.rules[i..]
+                .iter()
+                .position(|rule| &rule.media != media)
+                .map_or(self.rules.len(), |n| i + n);
+            let rules = self.rules[i..end].iter().map(Rule::to_css_string);
+            match media {
+                Some(media) => blocks.push(format!(
+                    "@media {} {{\n{}\n}}",
+                    media.to_css_string(),
+                    rules
+                        .map(|rule| format!("  {}", rule))
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                )),
+                None => blocks.extend(rules),
+            }
+            i = end;
+        }
+        blocks.join("\n")
     }
 }
 
@@ -27,20 +122,171 @@ impl Stylesheet {
 pub struct Rule {
     pub selectors: Vec<Selector>,
     pub declarations: Vec<Declaration>,
+    /// `media` is the condition of the `@media` block the rule is written in, if any.
+    pub media: Option<MediaQuery>,
+}
+
+/// `MediaQuery` is the condition of an `@media` rule like `(min-width: 600px) and (max-width: 800px)`.
+/// Only `min-width` and `max-width` in px are supported.
+/// See https://www.w3.org/TR/mediaqueries-4/#mq-min-max
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MediaQuery {
+    pub min_width: Option<f64>,
+    pub max_width: Option<f64>,
+}
+
+impl MediaQuery {
+    pub fn matches(&self, viewport_width: f64) -> bool {
+        self.min_width.is_none_or(|min| min <= viewport_width)
+            && self.max_width.is_none_or(|max| viewport_width <= max)
+    }
+
+    /// `to_css_string` formats the conditions like `(min-width: 600px) and (max-width: 800px)`.
+    pub fn to_css_string(&self) -> String {
+        let conditions = [("min-width", self.min_width), ("max-width", self.max_width)];
+        conditions
+            .iter()
+            .filter_map(|(name, width)| Some(format!("({}: {}px)", name, (*width)?)))
+            .collect::<Vec<_>>()
+            .join(" and ")
+    }
 }
 
 impl Rule {
-    pub fn matches(&self, node: &Box<Node>) -> bool {
-        self.selectors.iter().any(|s| s.matches(node))
+    /// `to_css_string` formats the rule like `h1, p { color: red; }` regardless of its media condition.
+    pub fn to_css_string(&self) -> String {
+        let selectors = self
+            .selectors
+            .iter()
+            .map(SimpleSelector::to_css_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        let declarations = self
+            .declarations
+            .iter()
+            .map(|d| format!(" {};", d.to_css_string()))
+            .collect::<String>();
+        format!("{} {{{} }}", selectors, declarations)
+    }
+
+    /// `has_same_selectors` tells whether the rules have the same selectors regardless of the order, under the same media condition.
+    fn has_same_selectors(&self, other: &Rule) -> bool {
+        self.media == other.media
+            && self.selectors.len() == other.selectors.len()
+            && self.selectors.iter().all(|s| other.selectors.contains(s))
+    }
+
+    /// `overlaps` tells whether the rules declare a common property.
+    /// A shorthand like `margin` overlaps its longhands like `margin-top`.
+    fn overlaps(&self, other: &Rule) -> bool {
+        let overlap = |a: &str, b: &str| {
+            a == b
+                || a.strip_prefix(b).is_some_and(|rest| rest.starts_with('-'))
+                || b.strip_prefix(a).is_some_and(|rest| rest.starts_with('-'))
+        };
+        self.declarations
+            .iter()
+            .any(|d| other.declarations.iter().any(|e| overlap(&d.name, &e.name)))
+    }
+
+    pub fn matches(&self, node: &Node) -> bool {
+        self.matches_in(node, &MatchingContext::default())
+    }
+
+    /// `matches_in` is `matches` for a node placed at `context` in the tree.
+    pub fn matches_in(&self, node: &Node, context: &MatchingContext) -> bool {
+        self.selectors.iter().any(|s| s.matches_in(node, context))
+    }
+
+    /// `specificity_in` returns the highest specificity of the selectors matching `node`, or `None` if none matches.
+    /// A rule applies with the specificity of the most specific selector that matches the node.
+    /// See https://www.w3.org/TR/selectors-3/#specificity
+    pub fn specificity_in(&self, node: &Node, context: &MatchingContext) -> Option<Specificity> {
+        self.selectors
+            .iter()
+            .filter(|s| s.matches_in(node, context))
+            .map(SimpleSelector::specificity)
+            .max()
+    }
+
+    /// `pseudo_element_specificity_in` is `specificity_in` for `pseudo_element` of `node`.
+    pub fn pseudo_element_specificity_in(
+        &self,
+        node: &Node,
+        context: &MatchingContext,
+        pseudo_element: PseudoElement,
+    ) -> Option<Specificity> {
+        self.selectors
+            .iter()
+            .filter(|s| s.matches_pseudo_element_in(node, context, pseudo_element))
+            .map(SimpleSelector::specificity)
+            .max()
+    }
+}
+
+/// `Specificity` is the number of the id, class-like and type selectors in a selector, compared in this order.
+/// See https://www.w3.org/TR/selectors-3/#specificity
+pub type Specificity = (u32, u32, u32);
+
+/// `MatchingContext` tells where the node being matched is placed in the tree.
+/// It forms a chain up to the root through `parent`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MatchingContext<'a> {
+    /// `parent` is the parent node and its own context. `None` for the root.
+    pub parent: Option<(&'a Node, &'a MatchingContext<'a>)>,
+    /// `index` is the position of the node in the children of the parent.
+    pub index: usize,
+}
+
+impl<'a> MatchingContext<'a> {
+    /// `child` returns the context of the `index`-th child of `node` placed at `self`.
+    pub fn child(&'a self, node: &'a Node, index: usize) -> MatchingContext<'a> {
+        MatchingContext {
+            parent: Some((node, self)),
+            index,
+        }
+    }
+
+    /// `previous_element_sibling` returns the element just before the node among its siblings with its context.
+    fn previous_element_sibling(&self) -> Option<(&'a Node, MatchingContext<'a>)> {
+        let (parent, context) = self.parent?;
+        let index = parent.children[..self.index]
+            .iter()
+            .rposition(|n| matches!(n.node_type, NodeType::Element(_)))?;
+        Some((&parent.children[index], context.child(parent, index)))
+    }
+
+    /// `parent_node` returns the parent with its own context.
+    fn parent_node(&self) -> Option<(&'a Node, MatchingContext<'a>)> {
+        self.parent.map(|(parent, context)| (parent, *context))
+    }
+
+    /// `element_siblings` returns the element siblings before and after the node.
+    fn element_siblings(&self) -> (usize, usize) {
+        let Some((parent, _)) = self.parent else {
+            return (0, 0);
+        };
+        let count_elements = |nodes: &[Box<Node>]| {
+            nodes
+                .iter()
+                .filter(|n| matches!(n.node_type, NodeType::Element(_)))
+                .count()
+        };
+        (
+            count_elements(&parent.children[..self.index]),
+            count_elements(&parent.children[self.index + 1..]),
+        )
     }
 }
 
 /// NOTE: This is not compliant to the standard for simplicity.
 ///
-/// In the standard, *a selector* is *a chain* of one or more sequences of simple selectors separated by combinators,
-/// where a sequence of simple selectors is a chain of simple selectors that are not separated by a combinator.
-/// Hence `Selector` is in fact something like `Vec<Vec<SimpleSelector>>`.
-pub type Selector = SimpleSelector;
+/// In the standard, *a complex selector* is *a chain* of one or more compound selectors separated by combinators,
+/// and a rule has a comma-separated list of complex selectors.
+/// Here a complex selector is a `SimpleSelector` nesting `CombinedSelector`s, so a rule has `Vec<ComplexSelector>`.
+pub type ComplexSelector = SimpleSelector;
+
+pub type Selector = ComplexSelector;
 
 /// `SimpleSelector` represents a simple selector defined in the following standard:
 /// https://www.w3.org/TR/selectors-3/#selector-syntax
@@ -50,6 +296,7 @@ pub enum SimpleSelector {
     TypeSelector {
         tag_name: String,
     },
+    /// `tag_name` is `*` when the selector applies to any element.
     AttributeSelector {
         tag_name: String,
         op: AttributeSelectorOp,
@@ -59,16 +306,115 @@ pub enum SimpleSelector {
     ClassSelector {
         class_name: String,
     },
+    IdSelector {
+        id: String,
+    },
+    /// `PseudoClassSelector` qualifies `base` with a pseudo-class like `li:first-child`.
+    PseudoClassSelector {
+        base: Box<SimpleSelector>,
+        pseudo_class: PseudoClass,
+    },
+    /// `PseudoElementSelector` selects `pseudo_element` of the elements matching `base`, like `p::after`.
+    /// It never matches an element itself.
+    PseudoElementSelector {
+        base: Box<SimpleSelector>,
+        pseudo_element: PseudoElement,
+    },
+    /// `CombinedSelector` matches `subject` related to an element matching `previous` by `combinator`, like `h1 + p`.
+    CombinedSelector {
+        previous: Box<SimpleSelector>,
+        combinator: Combinator,
+        subject: Box<SimpleSelector>,
+    },
     // TODO (enhancement): support multiple attribute selectors like `a[href=bar][ping=foo]`
     // TODO (enhancement): support more attribute selectors
 }
 
 impl SimpleSelector {
-    fn matches(&self, node: &Box<Node>) -> bool {
+    /// `specificity` counts attribute selectors and pseudo-classes as classes.
+    pub fn specificity(&self) -> Specificity {
+        match self {
+            SimpleSelector::UniversalSelector => (0, 0, 0),
+            SimpleSelector::TypeSelector { .. } => (0, 0, 1),
+            SimpleSelector::AttributeSelector { tag_name, .. } => {
+                (0, 1, if tag_name == "*" { 0 } else { 1 })
+            }
+            SimpleSelector::ClassSelector { .. } => (0, 1, 0),
+            SimpleSelector::IdSelector { .. } => (1, 0, 0),
+            SimpleSelector::PseudoClassSelector { base, .. } => {
+                let (a, b, c) = base.specificity();
+                (a, b + 1, c)
+            }
+            SimpleSelector::PseudoElementSelector { base, .. } => {
+                let (a, b, c) = base.specificity();
+                (a, b, c + 1)
+            }
+            SimpleSelector::CombinedSelector {
+                previous, subject, ..
+            } => {
+                let (a, b, c) = previous.specificity();
+                let (d, e, f) = subject.specificity();
+                (a + d, b + e, c + f)
+            }
+        }
+    }
+
+    /// `to_css_string` formats the selector in the syntax accepted by `parse_selectors`.
+    pub fn to_css_string(&self) -> String {
+        match self {
+            SimpleSelector::UniversalSelector => "*".to_string(),
+            SimpleSelector::TypeSelector { tag_name } => tag_name.clone(),
+            SimpleSelector::AttributeSelector {
+                tag_name,
+                op,
+                attribute,
+                value,
+            } => {
+                let tag_name = if tag_name == "*" { "" } else { tag_name };
+                match op {
+                    AttributeSelectorOp::Exists => format!("{}[{}]", tag_name, attribute),
+                    _ => format!("{}[{}{}{}]", tag_name, attribute, op.as_str(), value),
+                }
+            }
+            SimpleSelector::ClassSelector { class_name } => format!(".{}", class_name),
+            SimpleSelector::IdSelector { id } => format!("#{}", id),
+            SimpleSelector::PseudoClassSelector { base, pseudo_class } => {
+                format!("{}:{}", base.to_css_string(), pseudo_class.as_str())
+            }
+            SimpleSelector::PseudoElementSelector {
+                base,
+                pseudo_element,
+            } => format!("{}::{}", base.to_css_string(), pseudo_element.as_str()),
+            SimpleSelector::CombinedSelector {
+                previous,
+                combinator,
+                subject,
+            } => {
+                let combinator = match combinator {
+                    Combinator::Descendant => " ",
+                    Combinator::Child => " > ",
+                    Combinator::NextSibling => " + ",
+                };
+                format!(
+                    "{}{}{}",
+                    previous.to_css_string(),
+                    combinator,
+                    subject.to_css_string()
+                )
+            }
+        }
+    }
+
+    pub fn matches(&self, node: &Node) -> bool {
+        self.matches_in(node, &MatchingContext::default())
+    }
+
+    pub fn matches_in(&self, node: &Node, context: &MatchingContext) -> bool {
         match self {
             SimpleSelector::UniversalSelector => true,
             SimpleSelector::TypeSelector { tag_name } => match &node.node_type {
-                NodeType::Element(node) => node.tag_name == *tag_name,
+                // tag names are ASCII case-insensitive in HTML documents
+                NodeType::Element(node) => node.tag_name.eq_ignore_ascii_case(tag_name),
                 _ => false,
             },
             // class="hoge fuga"
@@ -82,15 +428,23 @@ impl SimpleSelector {
                 let NodeType::Element(e) = &node.node_type else {
                     return false;
                 };
-                if e.tag_name != *tag_name {
+                if tag_name != "*" && !e.tag_name.eq_ignore_ascii_case(tag_name) {
                     return false;
                 }
                 match op {
+                    AttributeSelectorOp::Exists => e.has_attribute(attribute),
                     AttributeSelectorOp::Eq => e.attributes.get(attribute) == Some(value),
                     AttributeSelectorOp::Contain => e
                         .attributes
                         .get(attribute)
-                        .map_or(false, |v| v.split_whitespace().any(|v| v == value)),
+                        .is_some_and(|v| v.split_whitespace().any(|v| v == value)),
+                    AttributeSelectorOp::DashMatch => {
+                        e.attributes.get(attribute).is_some_and(|v| {
+                            v == value
+                                || v.strip_prefix(value.as_str())
+                                    .is_some_and(|rest| rest.starts_with('-'))
+                        })
+                    }
                 }
             }
             SimpleSelector::ClassSelector { class_name } => {
@@ -99,6 +453,112 @@ impl SimpleSelector {
                 };
                 e.attributes.get("class") == Some(class_name)
             }
+            SimpleSelector::IdSelector { id } => {
+                let NodeType::Element(e) = &node.node_type else {
+                    return false;
+                };
+                e.id() == Some(id)
+            }
+            SimpleSelector::PseudoClassSelector { base, pseudo_class } => {
+                base.matches_in(node, context) && pseudo_class.matches(context)
+            }
+            SimpleSelector::PseudoElementSelector { .. } => false,
+            SimpleSelector::CombinedSelector {
+                previous,
+                combinator,
+                subject,
+            } => subject.matches_in(node, context) && combinator.relates(previous, context),
+        }
+    }
+
+    /// `matches_pseudo_element_in` tells whether the selector selects `pseudo_element` of `node`.
+    pub fn matches_pseudo_element_in(
+        &self,
+        node: &Node,
+        context: &MatchingContext,
+        pseudo_element: PseudoElement,
+    ) -> bool {
+        match self {
+            SimpleSelector::PseudoElementSelector {
+                base,
+                pseudo_element: p,
+            } => *p == pseudo_element && base.matches_in(node, context),
+            SimpleSelector::CombinedSelector {
+                previous,
+                combinator,
+                subject,
+            } => {
+                subject.matches_pseudo_element_in(node, context, pseudo_element)
+                    && combinator.relates(previous, context)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// `Combinator` tells how the elements matched by the both sides of a combined selector are related.
+/// See https://www.w3.org/TR/selectors-3/#combinators
+#[derive(Debug, PartialEq)]
+pub enum Combinator {
+    Descendant,  // whitespace
+    Child,       // >
+    NextSibling, // +
+}
+
+impl Combinator {
+    /// `relates` tells whether an element matching `previous` is related by the combinator to the node placed at `context`.
+    fn relates(&self, previous: &SimpleSelector, context: &MatchingContext) -> bool {
+        match self {
+            Combinator::NextSibling => context
+                .previous_element_sibling()
+                .is_some_and(|(sibling, context)| previous.matches_in(sibling, &context)),
+            Combinator::Child => context
+                .parent_node()
+                .is_some_and(|(parent, context)| previous.matches_in(parent, &context)),
+            Combinator::Descendant => {
+                std::iter::successors(context.parent_node(), |(_, context)| context.parent_node())
+                    .any(|(ancestor, context)| previous.matches_in(ancestor, &context))
+            }
+        }
+    }
+}
+
+/// `PseudoElement` is a pseudo-element generating content defined at https://www.w3.org/TR/css-pseudo-4/#generated-content
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PseudoElement {
+    Before, // ::before
+    After,  // ::after
+}
+
+impl PseudoElement {
+    fn as_str(&self) -> &'static str {
+        match self {
+            PseudoElement::Before => "before",
+            PseudoElement::After => "after",
+        }
+    }
+}
+
+/// `PseudoClass` is a structural pseudo-class defined at https://www.w3.org/TR/selectors-3/#structural-pseudos
+#[derive(Debug, PartialEq)]
+pub enum PseudoClass {
+    FirstChild, // :first-child
+    LastChild,  // :last-child
+}
+
+impl PseudoClass {
+    fn as_str(&self) -> &'static str {
+        match self {
+            PseudoClass::FirstChild => "first-child",
+            PseudoClass::LastChild => "last-child",
+        }
+    }
+
+    fn matches(&self, context: &MatchingContext) -> bool {
+        let (before, after) = context.element_siblings();
+        match self {
+            PseudoClass::FirstChild => before == 0,
+            PseudoClass::LastChild => after == 0,
         }
     }
 }
@@ -107,8 +567,21 @@ impl SimpleSelector {
 /// See https://www.w3.org/TR/selectors-3/#attribute-selectors to check the full list of available operators.
 #[derive(Debug, PartialEq)]
 pub enum AttributeSelectorOp {
-    Eq,      // =
-    Contain, // ~=
+    Exists,    // [attribute]
+    Eq,        // =
+    Contain,   // ~=
+    DashMatch, // |=
+}
+
+impl AttributeSelectorOp {
+    fn as_str(&self) -> &'static str {
+        match self {
+            AttributeSelectorOp::Exists => "",
+            AttributeSelectorOp::Eq => "=",
+            AttributeSelectorOp::Contain => "~=",
+            AttributeSelectorOp::DashMatch => "|=",
+        }
+    }
 }
 
 /// `Declaration` represents a CSS declaration defined at [CSS Syntax Module Level 3](https://www.w3.org/TR/css-syntax-3/#declaration)
@@ -122,20 +595,142 @@ pub enum AttributeSelectorOp {
 pub struct Declaration {
     pub name: String,
     pub value: CSSValue,
-    // TODO (enhancement): add a field for `!important`
+    /// `important` tells whether the declaration is followed by `!important`.
+    pub important: bool,
+}
+
+impl Declaration {
+    /// `to_css_string` formats the declaration like `margin: 0px 4px !important` without the trailing `;`.
+    pub fn to_css_string(&self) -> String {
+        let important = if self.important { " !important" } else { "" };
+        format!("{}: {}{}", self.name, self.value.to_css_string(), important)
+    }
 }
 
 /// `CSSValue` represents some of *component value types* defined at [CSS Values and Units Module Level 3](https://www.w3.org/TR/css-values-3/#component-types).
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub enum CSSValue {
     Keyword(String),
+    /// `String` is a quoted string like `"Times New Roman"`. The quotes are not included.
+    String(String),
+    /// `Number` is a unitless number like the multiplier of `line-height: 1.5`.
+    Number(f64),
+    Length(f64, Unit),
+    /// `Percentage` is a percentage like `50%`, which is resolved against another value such as the width of the containing block.
+    Percentage(f64),
+    /// `Multiple` is a space-separated sequence of values like `10px 20px`.
+    Multiple(Vec<CSSValue>),
+    /// `List` is a comma-separated list of values like `Helvetica, Arial, sans-serif`.
+    List(Vec<CSSValue>),
+    Color(Color),
+}
+
+impl CSSValue {
+    /// `to_color` returns the value as a color if it is a color or a named color.
+    pub fn to_color(&self) -> Option<Color> {
+        match self {
+            CSSValue::Color(c) => Some(*c),
+            CSSValue::Keyword(k) => Color::from_named(k),
+            _ => None,
+        }
+    }
+
+    /// `to_css_string` formats the value in the syntax accepted by the parser.
+    pub fn to_css_string(&self) -> String {
+        let join = |values: &[CSSValue], sep: &str| {
+            values
+                .iter()
+                .map(CSSValue::to_css_string)
+                .collect::<Vec<_>>()
+                .join(sep)
+        };
+        match self {
+            CSSValue::Keyword(keyword) => keyword.clone(),
+            CSSValue::String(s) => quote(s),
+            CSSValue::Number(n) => n.to_string(),
+            CSSValue::Length(l, Unit::Px) => format!("{}px", l),
+            CSSValue::Length(l, Unit::Em) => format!("{}em", l),
+            CSSValue::Percentage(p) => format!("{}%", p),
+            CSSValue::Multiple(values) => join(values, " "),
+            CSSValue::List(values) => join(values, ", "),
+            CSSValue::Color(color) => color.to_css_string(),
+        }
+    }
+}
+
+/// `quote` surrounds `s` with double quotes, or with single quotes if `s` contains a double quote,
+/// since `string_value` does not support escapes.
+fn quote(s: &str) -> String {
+    if s.contains('"') {
+        format!("'{}'", s)
+    } else {
+        format!("\"{}\"", s)
+    }
+}
+
+/// `Unit` is a unit of `CSSValue::Length` defined at [CSS Values and Units Module Level 3](https://www.w3.org/TR/css-values-3/#lengths).
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub enum Unit {
+    Px,
+    /// `Em` is relative to the font size of the element.
+    Em,
 }
 
 pub fn parse(raw: &str) -> Result<Stylesheet> {
-    rules()
-        .parse(raw)
-        .map(|(rules, _)| Stylesheet::new(rules))
-        .map_err(|e| e.into())
+    (
+        spaces(),
+        many::<Vec<_>, _, _>((import_rule(), spaces()).map(|(url, _)| url)),
+        rules(),
+        eof(),
+    )
+        .easy_parse(position::Stream::new(raw))
+        .map(|((_, imports, rules, _), _)| Stylesheet { rules, imports })
+        .map_err(parse_error)
+}
+
+/// `import_rule` consumes `@import "url";` or `@import url(url);` and returns the URL.
+/// `@import` is only allowed before the rules (https://www.w3.org/TR/css-cascade-5/#at-import).
+fn import_rule<Input>() -> impl Parser<Input, Output = String>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    let url_function = (
+        string("url("),
+        spaces(),
+        choice((
+            string_value(),
+            many1(satisfy(|c: char| c != ')' && !c.is_whitespace())),
+        )),
+        spaces(),
+        char(')'),
+    )
+        .map(|(_, _, url, _, _)| url);
+    (
+        attempt(string("@import")),
+        spaces(),
+        choice((string_value(), url_function)),
+        spaces(),
+        char(';'),
+    )
+        .map(|(_, _, url, _, _)| url)
+}
+
+/// `parse_selectors` parses a comma-separated selector list like the argument of `querySelector`.
+pub fn parse_selectors(raw: &str) -> Result<Vec<Selector>> {
+    (spaces(), selectors(), eof())
+        .easy_parse(position::Stream::new(raw))
+        .map(|((_, selectors, _), _)| selectors)
+        .map_err(parse_error)
+}
+
+/// `selector_matches` parses a single selector and tells whether it matches `node`.
+/// `node` is matched as a root since its parent is unknown.
+pub fn selector_matches(selector_str: &str, node: &Node) -> Result<bool> {
+    (spaces(), selector(), spaces(), eof())
+        .easy_parse(position::Stream::new(selector_str))
+        .map(|((_, selector, _, _), _)| selector.matches(node))
+        .map_err(parse_error)
 }
 
 fn rules<Input>() -> impl Parser<Input, Output = Vec<Rule>>
@@ -143,8 +738,79 @@ where
     Input: Stream<Token = char>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
 {
-    // TODO: 末尾に空白とか改行があるとエラーになる
-    (spaces(), sep_by(rule(), spaces()), spaces()).map(|(_, rules, _)| rules)
+    (
+        spaces(),
+        sep_end_by::<Vec<Vec<Rule>>, _, _, _>(
+            choice((media_rule(), rule().map(|rule| vec![rule]))),
+            spaces(),
+        ),
+    )
+        .map(|(_, rules)| rules.into_iter().flatten().collect())
+}
+
+/// `media_rule` consumes `@media condition { rules }` and returns the rules qualified by the condition.
+fn media_rule<Input>() -> impl Parser<Input, Output = Vec<Rule>>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    (
+        string("@media"),
+        spaces(),
+        media_query(),
+        spaces(),
+        char('{'),
+        spaces(),
+        sep_end_by::<Vec<_>, _, _, _>(rule(), spaces()),
+        char('}'),
+    )
+        .map(|(_, _, media, _, _, _, rules, _)| {
+            rules
+                .into_iter()
+                .map(|rule| Rule {
+                    media: Some(media.clone()),
+                    ..rule
+                })
+                .collect()
+        })
+}
+
+/// `media_query` consumes conditions like `(max-width: 600px)` joined by `and`.
+fn media_query<Input>() -> impl Parser<Input, Output = MediaQuery>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    let condition = (
+        char('('),
+        spaces(),
+        many1::<String, _, _>(letter().or(char('-'))),
+        spaces(),
+        char(':'),
+        spaces(),
+        number(),
+        string("px"),
+        spaces(),
+        char(')'),
+    )
+        .map(|(_, _, name, _, _, _, width, _, _, _)| (name, width));
+    sep_by1::<Vec<_>, _, _, _>(condition, attempt((spaces(), string("and"), spaces()))).and_then(
+        |conditions| {
+            let mut query = MediaQuery::default();
+            for (name, width) in conditions {
+                match name.as_str() {
+                    "min-width" => query.min_width = Some(width),
+                    "max-width" => query.max_width = Some(width),
+                    _ => {
+                        return Err(StreamErrorFor::<Input>::message_static_message(
+                            "unsupported media feature",
+                        ))
+                    }
+                }
+            }
+            Ok(query)
+        },
+    )
 }
 
 fn rule<Input>() -> impl Parser<Input, Output = Rule>
@@ -164,6 +830,7 @@ where
         .map(|(selectors, _, _, _, declarations, _, _)| Rule {
             selectors,
             declarations,
+            media: None,
         })
 }
 
@@ -173,120 +840,286 @@ where
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
 {
     sep_by(
-        (simple_selector(), spaces()).map(|(s, _)| s),
+        (selector(), spaces()).map(|(s, _)| s),
         (char(','), spaces()),
     )
 }
 
-fn universal_selector<Input>() -> impl Parser<Input, Output = SimpleSelector>
+/// `selector` consumes compound selectors combined by combinators like `h1 + p` or `.a .b`.
+fn selector<Input>() -> impl Parser<Input, Output = Selector>
 where
     Input: Stream<Token = char>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
 {
-    char('*').map(|_| SimpleSelector::UniversalSelector)
+    (
+        compound_selector(),
+        many::<Vec<_>, _, _>(attempt((combinator(), compound_selector()))),
+    )
+        .map(|(first, rest)| {
+            rest.into_iter()
+                .fold(first, |previous, (combinator, subject)| {
+                    SimpleSelector::CombinedSelector {
+                        previous: Box::new(previous),
+                        combinator,
+                        subject: Box::new(subject),
+                    }
+                })
+        })
 }
 
-fn class_selector<Input>() -> impl Parser<Input, Output = SimpleSelector>
+/// `combinator` consumes `+` or `>` with optional spaces around, or spaces alone as the descendant combinator.
+fn combinator<Input>() -> impl Parser<Input, Output = Combinator>
 where
     Input: Stream<Token = char>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
 {
-    (char('.'), many1(letter())).map(|(_, class_name)| SimpleSelector::ClassSelector { class_name })
+    choice((
+        attempt((
+            spaces(),
+            choice((
+                char('+').map(|_| Combinator::NextSibling),
+                char('>').map(|_| Combinator::Child),
+            )),
+            spaces(),
+        ))
+        .map(|(_, combinator, _)| combinator),
+        many1::<String, _, _>(space()).map(|_| Combinator::Descendant),
+    ))
 }
 
-fn selector_op<Input>() -> impl Parser<Input, Output = Result<AttributeSelectorOp, <Input::Error as combine::error::ParseError<
-                    char,
-                    Input::Range,
-                    Input::Position,
-                >>::StreamError>>
+/// `compound_selector` consumes a simple selector qualified by pseudo-classes like `li:first-child`.
+fn compound_selector<Input>() -> impl Parser<Input, Output = SimpleSelector>
 where
     Input: Stream<Token = char>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
 {
-    choice((string("="), string("~="))).map(|op| match op {
-        "=" => Ok(AttributeSelectorOp::Eq),
-        "~=" => Ok(AttributeSelectorOp::Contain),
-        _ => Err(<Input::Error as combine::error::ParseError<
-            char,
-            Input::Range,
-            Input::Position,
-        >>::StreamError::message_static_message(
-            "invalid attribute selector op",
+    (
+        choice((
+            (
+                simple_selector(),
+                many::<Vec<_>, _, _>(attempt(pseudo_class())),
+            ),
+            // a bare pseudo-class like `:first-child` or a bare pseudo-element like `::before` implies `*`
+            many1::<Vec<_>, _, _>(attempt(pseudo_class()))
+                .map(|p| (SimpleSelector::UniversalSelector, p)),
+            look_ahead(string("::")).map(|_| (SimpleSelector::UniversalSelector, vec![])),
         )),
-    })
+        optional(pseudo_element()),
+    )
+        .map(|((base, pseudo_classes), pseudo_element)| {
+            let base = pseudo_classes.into_iter().fold(base, |base, pseudo_class| {
+                SimpleSelector::PseudoClassSelector {
+                    base: Box::new(base),
+                    pseudo_class,
+                }
+            });
+            match pseudo_element {
+                Some(pseudo_element) => SimpleSelector::PseudoElementSelector {
+                    base: Box::new(base),
+                    pseudo_element,
+                },
+                None => base,
+            }
+        })
 }
 
-fn type_or_attribute_selector<Input>() -> impl Parser<Input, Output = SimpleSelector>
+/// `pseudo_element` consumes `::before` or `::after`.
+fn pseudo_element<Input>() -> impl Parser<Input, Output = PseudoElement>
 where
     Input: Stream<Token = char>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
 {
-    (
-        many1(letter()),
-        spaces(),
-        optional((
-            char('['),
-            spaces(),
-            many1(letter()),
-            selector_op(),
-            many1(letter()),
-            char(']'),
-        )),
-    )
-        .and_then(|(tag_name, _, opt)| {
-            let Some((_, _, attribute, op, value, _)) = opt else {
-                return Ok(SimpleSelector::TypeSelector { tag_name });
-            };
-            let Ok(op) = op else {
-                return Err(<Input::Error as combine::error::ParseError<
-                    char,
-                    Input::Range,
-                    Input::Position,
-                >>::StreamError::message_static_message(
-                    "invalid attribute selector"
-                ));
-            };
-            Ok(SimpleSelector::AttributeSelector {
-                tag_name,
-                op,
-                attribute,
-                value,
-            })
-        })
+    (string("::"), many1::<String, _, _>(letter().or(char('-')))).and_then(|(_, name)| {
+        match name.as_str() {
+            "before" => Ok(PseudoElement::Before),
+            "after" => Ok(PseudoElement::After),
+            _ => Err(StreamErrorFor::<Input>::message_static_message(
+                "unsupported pseudo-element",
+            )),
+        }
+    })
 }
 
-fn simple_selector<Input>() -> impl Parser<Input, Output = SimpleSelector>
+fn pseudo_class<Input>() -> impl Parser<Input, Output = PseudoClass>
 where
     Input: Stream<Token = char>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
 {
-    choice((
-        universal_selector(),
-        class_selector(),
-        type_or_attribute_selector(),
-    ))
+    (char(':'), many1::<String, _, _>(letter().or(char('-')))).and_then(|(_, name)| {
+        match name.as_str() {
+            "first-child" => Ok(PseudoClass::FirstChild),
+            "last-child" => Ok(PseudoClass::LastChild),
+            _ => Err(StreamErrorFor::<Input>::message_static_message(
+                "unsupported pseudo-class",
+            )),
+        }
+    })
 }
 
-fn declarations<Input>() -> impl Parser<Input, Output = Vec<Declaration>>
+fn universal_selector<Input>() -> impl Parser<Input, Output = SimpleSelector>
 where
     Input: Stream<Token = char>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
 {
-    sep_end_by(
-        (declaration(), spaces()).map(|(v, _)| v),
-        (char(';'), spaces()).map(|(v, _)| v),
+    (
+        char('*'),
+        optional(choice((
+            class_selector(),
+            id_selector(),
+            any_attribute_selector(),
+        ))),
     )
+        // `*` qualified by a class, an id or an attribute is the same as the qualifier itself
+        .map(|(_, qualified)| qualified.unwrap_or(SimpleSelector::UniversalSelector))
 }
 
-fn declaration<Input>() -> impl Parser<Input, Output = Declaration>
+fn class_selector<Input>() -> impl Parser<Input, Output = SimpleSelector>
 where
     Input: Stream<Token = char>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
 {
-    (many1(letter()), spaces(), char(':'), spaces(), css_value()).map(|v| Declaration {
-        name: v.0,
-        value: v.4,
-    })
+    (char('.'), name()).map(|(_, class_name)| SimpleSelector::ClassSelector { class_name })
+}
+
+fn id_selector<Input>() -> impl Parser<Input, Output = SimpleSelector>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    (char('#'), name()).map(|(_, id)| SimpleSelector::IdSelector { id })
+}
+
+/// `name` consumes a class name or an id like `col-6` or `main_content`.
+/// It starts with a letter, `-` or `_`, followed by letters, digits, `-` or `_`.
+fn name<Input>() -> impl Parser<Input, Output = String>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    (
+        choice((letter(), char('-'), char('_'))),
+        many::<String, _, _>(choice((alpha_num(), char('-'), char('_')))),
+    )
+        .map(|(first, rest)| format!("{}{}", first, rest))
+}
+
+fn selector_op<Input>() -> impl Parser<Input, Output = AttributeSelectorOp>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    choice((
+        string("=").map(|_| AttributeSelectorOp::Eq),
+        string("~=").map(|_| AttributeSelectorOp::Contain),
+        string("|=").map(|_| AttributeSelectorOp::DashMatch),
+    ))
+}
+
+/// `attribute_selector_part` consumes `[attribute op value]` or `[attribute]` and returns `(attribute, op, value)`.
+/// The value is empty for `[attribute]`.
+fn attribute_selector_part<Input>(
+) -> impl Parser<Input, Output = (String, AttributeSelectorOp, String)>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    (
+        char('['),
+        spaces(),
+        name(),
+        optional((selector_op(), many1(letter()))),
+        char(']'),
+    )
+        .map(|(_, _, attribute, op_value, _)| match op_value {
+            Some((op, value)) => (attribute, op, value),
+            None => (attribute, AttributeSelectorOp::Exists, String::new()),
+        })
+}
+
+/// `any_attribute_selector` consumes an attribute selector without a tag name like `[disabled]`, which applies to any element.
+fn any_attribute_selector<Input>() -> impl Parser<Input, Output = SimpleSelector>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    attribute_selector_part().map(|(attribute, op, value)| SimpleSelector::AttributeSelector {
+        tag_name: "*".to_string(),
+        op,
+        attribute,
+        value,
+    })
+}
+
+fn type_or_attribute_selector<Input>() -> impl Parser<Input, Output = SimpleSelector>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    (
+        (letter(), many::<String, _, _>(alpha_num()))
+            .map(|(first, rest)| format!("{}{}", first, rest)),
+        // spaces are left for the descendant combinator unless an attribute follows them
+        optional(attempt(
+            (spaces(), attribute_selector_part()).map(|(_, part)| part),
+        )),
+    )
+        .map(|(tag_name, opt)| {
+            let Some((attribute, op, value)) = opt else {
+                return SimpleSelector::TypeSelector { tag_name };
+            };
+            SimpleSelector::AttributeSelector {
+                tag_name,
+                op,
+                attribute,
+                value,
+            }
+        })
+}
+
+fn simple_selector<Input>() -> impl Parser<Input, Output = SimpleSelector>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    choice((
+        universal_selector(),
+        class_selector(),
+        id_selector(),
+        any_attribute_selector(),
+        type_or_attribute_selector(),
+    ))
+}
+
+fn declarations<Input>() -> impl Parser<Input, Output = Vec<Declaration>>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    sep_end_by(
+        (declaration(), spaces()).map(|(v, _)| v),
+        (char(';'), spaces()).map(|(v, _)| v),
+    )
+}
+
+fn declaration<Input>() -> impl Parser<Input, Output = Declaration>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    (
+        many1(letter().or(char('-'))),
+        spaces(),
+        char(':'),
+        spaces(),
+        css_value(),
+        // `attempt` leaves trailing spaces for the caller when `!important` does not follow them
+        optional(attempt((spaces(), char('!'))).with((spaces(), string("important")))),
+    )
+        .map(|v| Declaration {
+            name: v.0,
+            value: v.4,
+            important: v.5.is_some(),
+        })
 }
 
 fn css_value<Input>() -> impl Parser<Input, Output = CSSValue>
@@ -294,12 +1127,169 @@ where
     Input: Stream<Token = char>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
 {
-    many1(letter()).map(|v| CSSValue::Keyword(v))
+    (
+        space_separated_value(),
+        many::<Vec<_>, _, _>(attempt(
+            (spaces(), char(','), spaces(), space_separated_value()).map(|(_, _, _, v)| v),
+        )),
+    )
+        .map(|(first, mut rest)| {
+            if rest.is_empty() {
+                first
+            } else {
+                rest.insert(0, first);
+                CSSValue::List(rest)
+            }
+        })
+}
+
+fn space_separated_value<Input>() -> impl Parser<Input, Output = CSSValue>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    (
+        component_value(),
+        // `attempt` leaves trailing spaces for the caller when no value follows them
+        many::<Vec<_>, _, _>(attempt(
+            (many1::<String, _, _>(space()), component_value()).map(|(_, v)| v),
+        )),
+    )
+        .map(|(first, mut rest)| {
+            if rest.is_empty() {
+                first
+            } else {
+                rest.insert(0, first);
+                CSSValue::Multiple(rest)
+            }
+        })
+}
+
+fn component_value<Input>() -> impl Parser<Input, Output = CSSValue>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    choice((
+        attempt(numeric()),
+        string_value().map(CSSValue::String),
+        attempt(color_function()),
+        hex_color(),
+        many1(letter().or(char('-'))).map(CSSValue::Keyword),
+    ))
+}
+
+fn number<Input>() -> impl Parser<Input, Output = f64>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    (
+        optional(char('-')),
+        many1::<String, _, _>(digit()),
+        optional((char('.'), many1::<String, _, _>(digit()))),
+    )
+        .map(|(sign, int, frac)| {
+            let mut s = String::new();
+            if sign.is_some() {
+                s.push('-');
+            }
+            s += &int;
+            if let Some((_, frac)) = frac {
+                s.push('.');
+                s += &frac;
+            }
+            s.parse().unwrap()
+        })
+}
+
+/// `string_value` parses a single- or double-quoted string.
+fn string_value<Input>() -> impl Parser<Input, Output = String>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    choice((
+        (char('"'), many(satisfy(|c| c != '"')), char('"')),
+        (char('\''), many(satisfy(|c| c != '\'')), char('\'')),
+    ))
+    .map(|(_, s, _)| s)
+}
+
+/// `color_function` parses `rgb(r, g, b)` and `rgba(r, g, b, a)`.
+/// Out-of-range channels are clamped.
+fn color_function<Input>() -> impl Parser<Input, Output = CSSValue>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    let channel = |v: f64| v.clamp(0., 255.).round() as u8;
+    (
+        many1::<String, _, _>(letter()),
+        char('('),
+        spaces(),
+        sep_by::<Vec<_>, _, _, _>((number(), spaces()).map(|(n, _)| n), (char(','), spaces())),
+        char(')'),
+    )
+        .and_then(move |(name, _, _, args, _)| {
+            let (r, g, b, a) = match (name.as_str(), args.as_slice()) {
+                ("rgb", &[r, g, b]) => (r, g, b, 1.),
+                ("rgba", &[r, g, b, a]) => (r, g, b, a),
+                ("rgb" | "rgba", _) => {
+                    return Err(StreamErrorFor::<Input>::message_static_message(
+                        "wrong number of arguments",
+                    ))
+                }
+                _ => {
+                    return Err(StreamErrorFor::<Input>::message_static_message(
+                        "unknown function",
+                    ))
+                }
+            };
+            Ok(CSSValue::Color(Color {
+                r: channel(r),
+                g: channel(g),
+                b: channel(b),
+                a: a.clamp(0., 1.),
+            }))
+        })
+}
+
+/// `hex_color` parses a hex color like `#ff0000`.
+fn hex_color<Input>() -> impl Parser<Input, Output = CSSValue>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    (char('#'), many1::<String, _, _>(alpha_num())).and_then(|(_, digits)| {
+        Color::from_hex(&format!("#{}", digits))
+            .map(CSSValue::Color)
+            .ok_or_else(|| StreamErrorFor::<Input>::message_static_message("invalid hex color"))
+    })
+}
+
+/// `numeric` parses a number optionally followed by a unit.
+fn numeric<Input>() -> impl Parser<Input, Output = CSSValue>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    (number(), many::<String, _, _>(letter().or(char('%')))).and_then(|(n, unit)| {
+        match unit.as_str() {
+            "" => Ok(CSSValue::Number(n)),
+            "px" => Ok(CSSValue::Length(n, Unit::Px)),
+            "em" => Ok(CSSValue::Length(n, Unit::Em)),
+            "%" => Ok(CSSValue::Percentage(n)),
+            _ => Err(StreamErrorFor::<Input>::message_static_message(
+                "unknown unit",
+            )),
+        }
+    })
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::dom::Element;
+    use crate::dom::{AttrMap, Element};
 
     use super::*;
 
@@ -310,6 +1300,7 @@ mod tests {
             Ok((
                 vec![
                     Rule {
+                        media: None,
                         selectors: vec![SimpleSelector::AttributeSelector {
                             tag_name: "test".to_string(),
                             attribute: "foo".to_string(),
@@ -319,27 +1310,36 @@ mod tests {
                         declarations: vec![
                             Declaration {
                                 name: "aa".to_string(),
-                                value: CSSValue::Keyword("bb".to_string())
+                                value: CSSValue::Keyword("bb".to_string()),
+                                important: false,
                             },
                             Declaration {
                                 name: "cc".to_string(),
                                 value: CSSValue::Keyword("dd".to_string()),
+                                important: false,
                             }
                         ]
                     },
                     Rule {
+                        media: None,
                         selectors: vec![SimpleSelector::TypeSelector {
                             tag_name: "rule".to_string(),
                         }],
                         declarations: vec![Declaration {
                             name: "ee".to_string(),
-                            value: CSSValue::Keyword("dd".to_string())
+                            value: CSSValue::Keyword("dd".to_string()),
+                            important: false,
                         }]
                     },
                 ],
                 ""
             ))
         );
+
+        // whitespace after the last rule, like the newline at the end of a file, is allowed
+        let stylesheet = parse("p { display: block; }\n\ndiv { display: none; }\n").unwrap();
+        assert_eq!(stylesheet.rules.len(), 2);
+        assert_eq!(parse("  \n").unwrap().rules.len(), 0);
     }
 
     #[test]
@@ -348,6 +1348,7 @@ mod tests {
             rule().parse("test [foo=bar] {}"),
             Ok((
                 Rule {
+                    media: None,
                     selectors: vec![SimpleSelector::AttributeSelector {
                         tag_name: "test".to_string(),
                         attribute: "foo".to_string(),
@@ -364,6 +1365,7 @@ mod tests {
             rule().parse("test [foo=bar], testtest[piyo~=guoo] {}"),
             Ok((
                 Rule {
+                    media: None,
                     selectors: vec![
                         SimpleSelector::AttributeSelector {
                             tag_name: "test".to_string(),
@@ -388,6 +1390,7 @@ mod tests {
             rule().parse("test [foo=bar] { aa: bb; cc: dd; }"),
             Ok((
                 Rule {
+                    media: None,
                     selectors: vec![SimpleSelector::AttributeSelector {
                         tag_name: "test".to_string(),
                         attribute: "foo".to_string(),
@@ -397,11 +1400,13 @@ mod tests {
                     declarations: vec![
                         Declaration {
                             name: "aa".to_string(),
-                            value: CSSValue::Keyword("bb".to_string())
+                            value: CSSValue::Keyword("bb".to_string()),
+                            important: false,
                         },
                         Declaration {
                             name: "cc".to_string(),
                             value: CSSValue::Keyword("dd".to_string()),
+                            important: false,
                         }
                     ]
                 },
@@ -418,11 +1423,13 @@ mod tests {
                 vec![
                     Declaration {
                         name: "foo".to_string(),
-                        value: CSSValue::Keyword("bar".to_string())
+                        value: CSSValue::Keyword("bar".to_string()),
+                        important: false,
                     },
                     Declaration {
                         name: "piyo".to_string(),
-                        value: CSSValue::Keyword("piyopiyo".to_string())
+                        value: CSSValue::Keyword("piyopiyo".to_string()),
+                        important: false,
                     }
                 ],
                 ""
@@ -500,6 +1507,7 @@ mod tests {
                 Declaration {
                     name: "keykey".to_string(),
                     value: CSSValue::Keyword("piyo".to_string()),
+                    important: false,
                 },
                 ""
             ))
@@ -511,6 +1519,7 @@ mod tests {
                 Declaration {
                     name: "keyabc".to_string(),
                     value: CSSValue::Keyword("piyo".to_string()),
+                    important: false,
                 },
                 " "
             ))
@@ -522,11 +1531,53 @@ mod tests {
                 Declaration {
                     name: "keyhello".to_string(),
                     value: CSSValue::Keyword("piyo".to_string()),
+                    important: false,
                 },
                 " "
             ))
         );
 
+        assert_eq!(
+            declaration().parse("color: red ! important ;"),
+            Ok((
+                Declaration {
+                    name: "color".to_string(),
+                    value: CSSValue::Keyword("red".to_string()),
+                    important: true,
+                },
+                " ;"
+            ))
+        );
+
+        assert_eq!(
+            declaration().parse(r#"content: "a b c""#),
+            Ok((
+                Declaration {
+                    name: "content".to_string(),
+                    value: CSSValue::String("a b c".to_string()),
+                    important: false,
+                },
+                ""
+            ))
+        );
+
+        assert_eq!(
+            declaration().parse(r#"font-family: "Times New Roman", 'Noto Sans', serif"#),
+            Ok((
+                Declaration {
+                    name: "font-family".to_string(),
+                    value: CSSValue::List(vec![
+                        CSSValue::String("Times New Roman".to_string()),
+                        CSSValue::String("Noto Sans".to_string()),
+                        CSSValue::Keyword("serif".to_string()),
+                    ]),
+                    important: false,
+                },
+                ""
+            ))
+        );
+
+        assert!(declaration().parse(r#"content: "unterminated"#).is_err());
         assert!(declaration().parse("aaaaa").is_err())
     }
 
@@ -543,7 +1594,7 @@ mod tests {
             .collect(),
             vec![],
         );
-        assert_eq!(SimpleSelector::UniversalSelector.matches(e), true);
+        assert!(SimpleSelector::UniversalSelector.matches(e));
     }
 
     #[test]
@@ -560,21 +1611,15 @@ mod tests {
             vec![],
         );
 
-        assert_eq!(
-            (SimpleSelector::TypeSelector {
-                tag_name: "p".into(),
-            })
-            .matches(e),
-            true
-        );
+        assert!((SimpleSelector::TypeSelector {
+            tag_name: "p".into(),
+        })
+        .matches(e));
 
-        assert_eq!(
-            (SimpleSelector::TypeSelector {
-                tag_name: "invalid".into(),
-            })
-            .matches(e),
-            false
-        );
+        assert!(!(SimpleSelector::TypeSelector {
+            tag_name: "invalid".into(),
+        })
+        .matches(e));
     }
 
     #[test]
@@ -591,49 +1636,37 @@ mod tests {
             vec![],
         );
 
-        assert_eq!(
-            (SimpleSelector::AttributeSelector {
-                tag_name: "p".into(),
-                attribute: "id".into(),
-                value: "test".into(),
-                op: AttributeSelectorOp::Eq,
-            })
-            .matches(e),
-            true
-        );
+        assert!((SimpleSelector::AttributeSelector {
+            tag_name: "p".into(),
+            attribute: "id".into(),
+            value: "test".into(),
+            op: AttributeSelectorOp::Eq,
+        })
+        .matches(e));
 
-        assert_eq!(
-            (SimpleSelector::AttributeSelector {
-                tag_name: "p".into(),
-                attribute: "id".into(),
-                value: "invalid".into(),
-                op: AttributeSelectorOp::Eq,
-            })
-            .matches(e),
-            false
-        );
+        assert!(!(SimpleSelector::AttributeSelector {
+            tag_name: "p".into(),
+            attribute: "id".into(),
+            value: "invalid".into(),
+            op: AttributeSelectorOp::Eq,
+        })
+        .matches(e));
 
-        assert_eq!(
-            (SimpleSelector::AttributeSelector {
-                tag_name: "p".into(),
-                attribute: "invalid".into(),
-                value: "test".into(),
-                op: AttributeSelectorOp::Eq,
-            })
-            .matches(e),
-            false
-        );
+        assert!(!(SimpleSelector::AttributeSelector {
+            tag_name: "p".into(),
+            attribute: "invalid".into(),
+            value: "test".into(),
+            op: AttributeSelectorOp::Eq,
+        })
+        .matches(e));
 
-        assert_eq!(
-            (SimpleSelector::AttributeSelector {
-                tag_name: "invalid".into(),
-                attribute: "id".into(),
-                value: "test".into(),
-                op: AttributeSelectorOp::Eq,
-            })
-            .matches(e),
-            false
-        );
+        assert!(!(SimpleSelector::AttributeSelector {
+            tag_name: "invalid".into(),
+            attribute: "id".into(),
+            value: "test".into(),
+            op: AttributeSelectorOp::Eq,
+        })
+        .matches(e));
     }
 
     #[test]
@@ -650,20 +1683,608 @@ mod tests {
             vec![],
         );
 
+        assert!((SimpleSelector::ClassSelector {
+            class_name: "testclass".into(),
+        })
+        .matches(e));
+
+        assert!(!(SimpleSelector::ClassSelector {
+            class_name: "invalid".into(),
+        })
+        .matches(e));
+    }
+
+    #[test]
+    fn test_css_value() {
         assert_eq!(
-            (SimpleSelector::ClassSelector {
-                class_name: "testclass".into(),
-            })
-            .matches(e),
-            true
+            css_value().parse("10px"),
+            Ok((CSSValue::Length(10., Unit::Px), ""))
+        );
+        assert_eq!(
+            css_value().parse("50%"),
+            Ok((CSSValue::Percentage(50.), ""))
+        );
+        assert_eq!(
+            css_value().parse("1.5em"),
+            Ok((CSSValue::Length(1.5, Unit::Em), ""))
+        );
+        assert_eq!(
+            css_value().parse("-1.5px"),
+            Ok((CSSValue::Length(-1.5, Unit::Px), ""))
+        );
+        assert_eq!(
+            css_value().parse("10px 20px ;"),
+            Ok((
+                CSSValue::Multiple(vec![
+                    CSSValue::Length(10., Unit::Px),
+                    CSSValue::Length(20., Unit::Px)
+                ]),
+                " ;"
+            ))
+        );
+        assert_eq!(css_value().parse("1.5"), Ok((CSSValue::Number(1.5), "")));
+        assert_eq!(
+            css_value().parse("Helvetica, Arial ,sans-serif;"),
+            Ok((
+                CSSValue::List(vec![
+                    CSSValue::Keyword("Helvetica".to_string()),
+                    CSSValue::Keyword("Arial".to_string()),
+                    CSSValue::Keyword("sans-serif".to_string()),
+                ]),
+                ";"
+            ))
+        );
+        assert_eq!(
+            css_value().parse("1px solid, 2px"),
+            Ok((
+                CSSValue::List(vec![
+                    CSSValue::Multiple(vec![
+                        CSSValue::Length(1., Unit::Px),
+                        CSSValue::Keyword("solid".to_string()),
+                    ]),
+                    CSSValue::Length(2., Unit::Px),
+                ]),
+                ""
+            ))
+        );
+        assert!(css_value().parse("10xx").is_err());
+    }
+
+    #[test]
+    fn test_selector_matches() {
+        let node = crate::html::parse(r#"<p class="inline">hello</p>"#).unwrap();
+        assert!(selector_matches("p", &node).unwrap());
+        assert!(selector_matches(" .inline ", &node).unwrap());
+        assert!(selector_matches("p:first-child", &node).unwrap());
+        assert!(!selector_matches("div", &node).unwrap());
+        assert!(!selector_matches(".block", &node).unwrap());
+
+        assert!(selector_matches("p[", &node).is_err());
+        assert!(selector_matches("p, div", &node).is_err());
+        assert!(selector_matches("", &node).is_err());
+    }
+
+    #[test]
+    fn test_type_selector_case_insensitive() {
+        let node = crate::html::parse(r#"<div lang="en">hello</div>"#).unwrap();
+        assert!(selector_matches("DIV", &node).unwrap());
+        assert!(selector_matches("Div[lang=en]", &node).unwrap());
+        assert!(!selector_matches("SPAN", &node).unwrap());
+
+        let node = crate::html::parse("<DIV>hello</DIV>").unwrap();
+        assert!(selector_matches("div", &node).unwrap());
+    }
+
+    #[test]
+    fn test_presence_attribute_selector() {
+        let input = |attributes: &str| {
+            crate::html::parse(&format!("<form><input {}></form>", attributes)).unwrap()
+        };
+        let matches = |selector: &str, form: &Node| {
+            let context = MatchingContext::default();
+            parse_selectors(selector).unwrap()[0]
+                .matches_in(&form.children[0], &context.child(form, 0))
+        };
+        // the value does not matter, even if it is empty
+        assert!(matches("[required]", &input(r#"required="""#)));
+        assert!(matches("[required]", &input("required")));
+        assert!(matches("[required]", &input(r#"type="text" required"#)));
+        assert!(matches("[required]", &input(r#"required="required""#)));
+        assert!(matches(
+            "input[required]",
+            &input(r#"type="text" required="false""#)
+        ));
+        assert!(!matches("[required]", &input(r#"type="text""#)));
+    }
+
+    #[test]
+    fn test_bare_attribute_selector() {
+        let element = |attributes: &[(&str, &str)]| {
+            Element::new(
+                "input".to_string(),
+                attributes
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect(),
+                vec![],
+            )
+        };
+        let disabled = element(&[("disabled", "disabled")]);
+        let data = element(&[("data-x", "y")]);
+
+        assert_eq!(
+            parse_selectors("[disabled]").unwrap(),
+            vec![SimpleSelector::AttributeSelector {
+                tag_name: "*".into(),
+                op: AttributeSelectorOp::Exists,
+                attribute: "disabled".into(),
+                value: "".into(),
+            }]
+        );
+        assert!(selector_matches("[disabled]", &disabled).unwrap());
+        assert!(selector_matches("input[disabled]", &disabled).unwrap());
+        assert!(!selector_matches("[disabled]", &data).unwrap());
+
+        assert!(selector_matches("[data-x=y]", &data).unwrap());
+        assert!(!selector_matches("[data-x=z]", &data).unwrap());
+        assert!(!selector_matches("[data-x=y]", &disabled).unwrap());
+        assert!(selector_matches("[data-x]:first-child", &data).unwrap());
+    }
+
+    #[test]
+    fn test_dash_match_attribute_selector() {
+        for (lang, expected) in [
+            ("en", true),
+            ("en-US", true),
+            ("fen", false),
+            ("english", false),
+        ] {
+            let node = crate::html::parse(&format!(r#"<p lang="{}">hello</p>"#, lang)).unwrap();
+            assert_eq!(
+                selector_matches("p[lang|=en]", &node).unwrap(),
+                expected,
+                "{}",
+                lang
+            );
+        }
+    }
+
+    #[test]
+    fn test_class_and_id_names() {
+        assert_eq!(
+            simple_selector().parse(".col-6 {"),
+            Ok((
+                SimpleSelector::ClassSelector {
+                    class_name: "col-6".to_string()
+                },
+                " {"
+            ))
+        );
+        assert_eq!(
+            simple_selector().parse(".btn2"),
+            Ok((
+                SimpleSelector::ClassSelector {
+                    class_name: "btn2".to_string()
+                },
+                ""
+            ))
+        );
+        assert_eq!(
+            simple_selector().parse("#main-content"),
+            Ok((
+                SimpleSelector::IdSelector {
+                    id: "main-content".to_string()
+                },
+                ""
+            ))
+        );
+        assert_eq!(
+            simple_selector().parse("*#main_content"),
+            Ok((
+                SimpleSelector::IdSelector {
+                    id: "main_content".to_string()
+                },
+                ""
+            ))
+        );
+        assert!(simple_selector().parse(".2col").is_err());
+
+        let node =
+            crate::html::parse(r#"<div id="main-content" class="col-6">hello</div>"#).unwrap();
+        assert!(selector_matches("#main-content", &node).unwrap());
+        assert!(selector_matches(".col-6", &node).unwrap());
+        assert!(!selector_matches("#main", &node).unwrap());
+    }
+
+    #[test]
+    fn test_rules_for() {
+        let stylesheet = parse(
+            "p { display: block; } div { display: none; } .inline, span { display: inline; } * { color: red; }",
+        )
+        .unwrap();
+        let node = crate::html::parse(r#"<p class="inline">hello</p>"#).unwrap();
+        assert_eq!(
+            stylesheet.rules_for(&node),
+            vec![
+                &stylesheet.rules[0],
+                &stylesheet.rules[2],
+                &stylesheet.rules[3]
+            ]
+        );
+
+        let node = crate::html::parse("<span>hello</span>").unwrap();
+        assert_eq!(
+            stylesheet.rules_for(&node),
+            vec![&stylesheet.rules[2], &stylesheet.rules[3]]
         );
+    }
+
+    #[test]
+    fn test_normalize() {
+        let mut stylesheet = parse(
+            "p, div { color: red; margin: 0px; } span {} div, p { color: blue; } span { display: inline; }",
+        )
+        .unwrap();
+        stylesheet.normalize();
+        assert_eq!(
+            stylesheet,
+            parse("p, div { margin: 0px; color: blue; } span { display: inline; }").unwrap()
+        );
+
+        // merging across a rule declaring the same property would change the cascade
+        let raw = "p { color: red; } .x { color: blue; } p { color: green; }";
+        let mut stylesheet = parse(raw).unwrap();
+        stylesheet.normalize();
+        assert_eq!(stylesheet, parse(raw).unwrap());
 
+        let raw = "p { margin-top: 1px; } .x { margin: 0px; } p { margin: 2px; }";
+        let mut stylesheet = parse(raw).unwrap();
+        stylesheet.normalize();
+        assert_eq!(stylesheet.rules.len(), 3);
+    }
+
+    #[test]
+    fn test_color_function() {
+        let color = |r, g, b, a| CSSValue::Color(Color { r, g, b, a });
+        assert_eq!(
+            css_value().parse("rgb(255, 0, 0)"),
+            Ok((color(255, 0, 0, 1.), ""))
+        );
+        assert_eq!(
+            css_value().parse("rgba(0,0,0,0.5)"),
+            Ok((color(0, 0, 0, 0.5), ""))
+        );
+        assert_eq!(
+            css_value().parse("rgba(300, -20, 12.4, 2)"),
+            Ok((color(255, 0, 12, 1.), ""))
+        );
+        assert_eq!(
+            css_value().parse("1px solid rgb(1, 2, 3)"),
+            Ok((
+                CSSValue::Multiple(vec![
+                    CSSValue::Length(1., Unit::Px),
+                    CSSValue::Keyword("solid".to_string()),
+                    color(1, 2, 3, 1.),
+                ]),
+                ""
+            ))
+        );
+
+        assert!(color_function().parse("rgb(1, 2)").is_err());
+        assert_eq!(
+            css_value().parse("#f00"),
+            Ok((CSSValue::Color(Color::from_rgb(255, 0, 0)), ""))
+        );
+        assert!(css_value().parse("#ff00000").is_err());
+        assert!(color_function().parse("rgba(1, 2, 3)").is_err());
+        assert!(color_function().parse("rgb(1, 2, 3, 4)").is_err());
+        assert!(color_function().parse("hsl(1, 2, 3)").is_err());
+        assert!(color_function().parse("rgb(1, 2, 3").is_err());
+        assert!(parse("p { color: rgb(1, 2); }").is_err());
+        assert!(parse("p { color: rgb(a, b, c); }").is_err());
+    }
+
+    #[test]
+    fn test_qualified_universal_selector() {
         assert_eq!(
-            (SimpleSelector::ClassSelector {
-                class_name: "invalid".into(),
+            simple_selector().parse("*.inline"),
+            Ok((
+                SimpleSelector::ClassSelector {
+                    class_name: "inline".to_string(),
+                },
+                ""
+            ))
+        );
+        assert_eq!(
+            simple_selector().parse("*[id=test]"),
+            Ok((
+                SimpleSelector::AttributeSelector {
+                    tag_name: "*".to_string(),
+                    attribute: "id".to_string(),
+                    op: AttributeSelectorOp::Eq,
+                    value: "test".to_string()
+                },
+                ""
+            ))
+        );
+
+        let (selector, _) = simple_selector().parse("*.inline").unwrap();
+        let p = Element::new(
+            "p".to_string(),
+            [("class".to_string(), "inline".to_string())]
+                .into_iter()
+                .collect(),
+            vec![],
+        );
+        let span = Element::new(
+            "span".to_string(),
+            [("class".to_string(), "inline".to_string())]
+                .into_iter()
+                .collect(),
+            vec![],
+        );
+        let div = Element::new("div".to_string(), AttrMap::new(), vec![]);
+        assert!(selector.matches(&p));
+        assert!(selector.matches(&span));
+        assert!(!selector.matches(&div));
+
+        let (selector, _) = simple_selector().parse("*[id=test]").unwrap();
+        let div = Element::new(
+            "div".to_string(),
+            [("id".to_string(), "test".to_string())]
+                .into_iter()
+                .collect(),
+            vec![],
+        );
+        assert!(selector.matches(&div));
+        assert!(!selector.matches(&p));
+    }
+
+    #[test]
+    fn test_pseudo_class_selector() {
+        assert_eq!(
+            selector().parse("li:first-child"),
+            Ok((
+                SimpleSelector::PseudoClassSelector {
+                    base: Box::new(SimpleSelector::TypeSelector {
+                        tag_name: "li".to_string(),
+                    }),
+                    pseudo_class: PseudoClass::FirstChild,
+                },
+                ""
+            ))
+        );
+        assert_eq!(
+            selector().parse(":last-child"),
+            Ok((
+                SimpleSelector::PseudoClassSelector {
+                    base: Box::new(SimpleSelector::UniversalSelector),
+                    pseudo_class: PseudoClass::LastChild,
+                },
+                ""
+            ))
+        );
+        assert!(selector().parse("li:hover").is_err());
+    }
+
+    #[test]
+    fn test_pseudo_class_selector_behaviour() {
+        let ul = crate::html::parse("<ul><li>a</li><li>b</li><li>c</li></ul>").unwrap();
+        let root = MatchingContext::default();
+        let (first_child, _) = selector().parse("li:first-child").unwrap();
+        let (last_child, _) = selector().parse("li:last-child").unwrap();
+
+        let matched = |selector: &SimpleSelector| {
+            ul.children
+                .iter()
+                .enumerate()
+                .map(|(i, li)| selector.matches_in(li, &root.child(&ul, i)))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(matched(&first_child), vec![true, false, false]);
+        assert_eq!(matched(&last_child), vec![false, false, true]);
+    }
+
+    #[test]
+    fn test_import_rule() {
+        let stylesheet = parse(r#"@import "a.css"; p{display:block}"#).unwrap();
+        assert_eq!(stylesheet.imports, vec!["a.css"]);
+        assert_eq!(stylesheet.rules.len(), 1);
+
+        let stylesheet =
+            parse("@import url(\"b.css\");\n@import url( c.css );\np { display: block; }").unwrap();
+        assert_eq!(stylesheet.imports, vec!["b.css", "c.css"]);
+
+        // `@import` after a rule is not allowed
+        assert!(parse(r#"p { display: block; } @import "a.css";"#).is_err());
+    }
+
+    #[test]
+    fn test_next_sibling_combinator() {
+        assert_eq!(
+            selector().parse("h1+p"),
+            Ok((
+                SimpleSelector::CombinedSelector {
+                    previous: Box::new(SimpleSelector::TypeSelector {
+                        tag_name: "h1".to_string()
+                    }),
+                    combinator: Combinator::NextSibling,
+                    subject: Box::new(SimpleSelector::TypeSelector {
+                        tag_name: "p".to_string()
+                    }),
+                },
+                ""
+            ))
+        );
+
+        let node = crate::html::parse(
+            "<div><h1>title</h1><p>first</p><p>second</p><h1>again</h1>text<p>third</p></div>",
+        )
+        .unwrap();
+        let stylesheet = parse("h1 + p { display: block; }").unwrap();
+        let root = MatchingContext::default();
+        let matched: Vec<_> = node
+            .children
+            .iter()
+            .enumerate()
+            .map(|(i, child)| stylesheet.rules[0].matches_in(child, &root.child(&node, i)))
+            .collect();
+        // text nodes between the elements do not matter
+        assert_eq!(matched, vec![false, true, false, false, false, true]);
+        assert_eq!(stylesheet.rules[0].selectors[0].specificity(), (0, 0, 2));
+    }
+
+    #[test]
+    fn test_parse_error_position() {
+        let err = parse("p { display: block; }\ndiv { display: ; }").unwrap_err();
+        assert!(err.to_string().starts_with("2:"), "{}", err);
+    }
+
+    #[test]
+    fn test_media_rule() {
+        let stylesheet = parse(
+            "p { display: block; }\n@media (min-width: 400px) and (max-width: 600px) {\n  p { display: inline; }\n  div { display: none; }\n}",
+        )
+        .unwrap();
+        let media = MediaQuery {
+            min_width: Some(400.),
+            max_width: Some(600.),
+        };
+        assert_eq!(
+            stylesheet
+                .rules
+                .iter()
+                .map(|rule| rule.media.clone())
+                .collect::<Vec<_>>(),
+            vec![None, Some(media.clone()), Some(media.clone())]
+        );
+        assert!(!media.matches(399.));
+        assert!(media.matches(500.));
+        assert!(!media.matches(601.));
+
+        assert!(parse("@media (orientation: 1px) { p { display: block; } }").is_err());
+    }
+
+    #[test]
+    fn test_to_css_string() {
+        let raw = r#"@import "base.css";
+@import url(theme.css);
+* { margin: 0px; }
+h1, .title, #main { color: #ff000080; font-family: "Times New Roman", serif; width: 50%; }
+p[lang|=en] + li:first-child { line-height: 1.5 !important; margin: -4px 2px; }
+[disabled] { content: 'say "hi"'; background-color: rgb(0, 0, 255); }
+@media (max-width: 600px) { p { display: none; } div { display: block; } }
+@media (min-width: 300px) and (max-width: 600px) { p { display: inline; } }
+*:last-child { }"#;
+        let stylesheet = parse(raw).unwrap();
+        let serialized = stylesheet.to_css_string();
+        assert_eq!(parse(&serialized).unwrap(), stylesheet, "{}", serialized);
+        assert!(serialized
+            .starts_with("@import \"base.css\";\n@import \"theme.css\";\n* { margin: 0px; }"));
+        assert!(serialized.contains(
+            "@media (max-width: 600px) {\n  p { display: none; }\n  div { display: block; }\n}"
+        ));
+        assert!(serialized.contains("{ line-height: 1.5 !important; margin: -4px 2px; }"));
+    }
+
+    #[test]
+    fn test_complex_selectors() {
+        let class = |class_name: &str| {
+            Box::new(SimpleSelector::ClassSelector {
+                class_name: class_name.to_string(),
             })
-            .matches(e),
-            false
+        };
+        assert_eq!(
+            selectors().parse(".a .b, #c"),
+            Ok((
+                vec![
+                    SimpleSelector::CombinedSelector {
+                        previous: class("a"),
+                        combinator: Combinator::Descendant,
+                        subject: class("b"),
+                    },
+                    SimpleSelector::IdSelector {
+                        id: "c".to_string()
+                    },
+                ],
+                ""
+            ))
+        );
+        let stylesheet = parse("#c > d, div p + p { display: block; }").unwrap();
+        assert_eq!(stylesheet.rules[0].selectors.len(), 2);
+        assert_eq!(
+            stylesheet.to_css_string(),
+            "#c > d, div p + p { display: block; }"
+        );
+
+        let node = crate::html::parse(
+            r#"<div class="a"><p class="b">x</p><section><p class="b">y</p></section></div>"#,
+        )
+        .unwrap();
+        let matches = |selector: &str, node: &Node, context: &MatchingContext| {
+            parse_selectors(selector).unwrap()[0].matches_in(node, context)
+        };
+        let root = MatchingContext::default();
+        let (direct, direct_context) = (&node.children[0], root.child(&node, 0));
+        let section = root.child(&node, 1);
+        let section_node = &node.children[1];
+        let (nested, nested_context) = (&section_node.children[0], section.child(section_node, 0));
+        assert!(matches(".a .b", direct, &direct_context));
+        assert!(matches(".a .b", nested, &nested_context));
+        assert!(matches(".a > .b", direct, &direct_context));
+        assert!(!matches(".a > .b", nested, &nested_context));
+        assert!(matches("div section > p", nested, &nested_context));
+        assert!(!matches("section .b", direct, &direct_context));
+        // the root has no ancestor
+        assert!(!matches("* div", &node, &root));
+    }
+
+    #[test]
+    fn test_pseudo_element() {
+        let p = || {
+            Box::new(SimpleSelector::TypeSelector {
+                tag_name: "p".to_string(),
+            })
+        };
+        assert_eq!(
+            selector().parse("p::after"),
+            Ok((
+                SimpleSelector::PseudoElementSelector {
+                    base: p(),
+                    pseudo_element: PseudoElement::After,
+                },
+                ""
+            ))
+        );
+        assert_eq!(
+            selector().parse("::before"),
+            Ok((
+                SimpleSelector::PseudoElementSelector {
+                    base: Box::new(SimpleSelector::UniversalSelector),
+                    pseudo_element: PseudoElement::Before,
+                },
+                ""
+            ))
+        );
+        assert!(parse("p::marker { content: \"x\"; }").is_err());
+
+        let node = crate::html::parse(r#"<p class="x">hello</p>"#).unwrap();
+        let stylesheet = parse(".x:first-child::before { content: \">\"; }").unwrap();
+        let rule = &stylesheet.rules[0];
+        let root = MatchingContext::default();
+        // a pseudo-element selector never matches the element itself
+        assert!(!rule.matches(&node));
+        assert_eq!(
+            rule.pseudo_element_specificity_in(&node, &root, PseudoElement::Before),
+            Some((0, 2, 1))
+        );
+        assert_eq!(
+            rule.pseudo_element_specificity_in(&node, &root, PseudoElement::After),
+            None
+        );
+        assert_eq!(
+            stylesheet.to_css_string(),
+            ".x:first-child::before { content: \">\"; }"
         );
     }
 }
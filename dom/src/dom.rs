@@ -1,112 +1,1080 @@
-use crate::html;
+use crate::{
+    css::{self, MatchingContext, Selector},
+    html,
+};
+use anyhow::{bail, Result};
+use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicU64, Ordering},
+};
 
-pub type AttrMap = HashMap<String, String>;
+/// `AttrMap` keeps the attributes in the order they are written.
+pub type AttrMap = IndexMap<String, String>;
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+/// `DOM_FORMAT_VERSION` is the version of the binary DOM format written by `Node::to_bin`.
+/// Bump it whenever `Node` (or anything it contains) changes shape.
+///
+/// NOTE: it starts at 2 because a legacy unversioned payload begins with the `NodeType` variant index (0 or 1).
+pub const DOM_FORMAT_VERSION: u32 = 3;
+
+/// `DomFile` is the versioned envelope of a serialized `Node`.
+#[derive(Debug, Serialize, Deserialize)]
+struct DomFile<N> {
+    version: u32,
+    node: N,
+}
+
+/// `DomPatch` is the versioned envelope of a serialized subtree and the path to put it at.
+#[derive(Debug, Serialize, Deserialize)]
+struct DomPatch<N> {
+    version: u32,
+    path: Vec<usize>,
+    node: N,
+}
+
+/// `BLOCK_ELEMENTS` are the elements which are blocks in the default stylesheet of browsers.
+/// `rendered_text` puts their texts on their own lines since styles are not known in the DOM.
+/// See https://html.spec.whatwg.org/multipage/rendering.html#flow-content-3
+const BLOCK_ELEMENTS: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "body",
+    "div",
+    "dl",
+    "dd",
+    "dt",
+    "fieldset",
+    "figure",
+    "footer",
+    "form",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hr",
+    "html",
+    "li",
+    "main",
+    "nav",
+    "ol",
+    "p",
+    "pre",
+    "section",
+    "table",
+    "tr",
+    "ul",
+];
+
+/// `NodeKey` identifies a node while it is alive, independently of its attributes.
+/// It is not a part of the document: it is neither serialized nor compared, and a copy of a node gets a new one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeKey(u64);
+
+impl NodeKey {
+    fn new() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        NodeKey(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Node {
     pub node_type: NodeType,
     pub children: Vec<Box<Node>>,
+    #[serde(skip, default = "NodeKey::new")]
+    key: NodeKey,
+}
+
+impl Clone for Node {
+    fn clone(&self) -> Self {
+        Node {
+            node_type: self.node_type.clone(),
+            children: self.children.clone(),
+            key: NodeKey::new(),
+        }
+    }
+}
+
+impl PartialEq for Node {
+    fn eq(&self, other: &Self) -> bool {
+        self.node_type == other.node_type && self.children == other.children
+    }
 }
 
 impl Node {
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(node_type: NodeType, children: Vec<Box<Node>>) -> Box<Node> {
+        Box::new(Node {
+            node_type,
+            children,
+            key: NodeKey::new(),
+        })
+    }
+
+    pub fn key(&self) -> NodeKey {
+        self.key
+    }
+
     pub fn inner_text(&self) -> String {
         self.children
             .iter()
-            .clone()
-            .into_iter()
             .map(|node| match &node.node_type {
                 NodeType::Text(t) => t.data.clone(),
+                NodeType::Comment(_) => String::new(),
                 _ => node.inner_text(),
             })
             .collect::<Vec<_>>()
             .join("")
     }
 
+    /// `rendered_text` is `inner_text` with the texts of block-level elements on their own lines,
+    /// as `innerText` of browsers. `<br>` also breaks the line.
+    /// See https://html.spec.whatwg.org/multipage/dom.html#rendered-text-collection-steps
+    pub fn rendered_text(&self) -> String {
+        /// `None` is a required line break, which is collapsed with the adjacent ones.
+        fn collect<'a>(node: &'a Node, items: &mut Vec<Option<&'a str>>) {
+            let (is_block, is_line_break) = match &node.node_type {
+                NodeType::Text(t) => {
+                    items.push(Some(&t.data));
+                    return;
+                }
+                NodeType::Comment(_) => return,
+                NodeType::Element(e) => (
+                    BLOCK_ELEMENTS
+                        .iter()
+                        .any(|tag| e.tag_name.eq_ignore_ascii_case(tag)),
+                    e.tag_name.eq_ignore_ascii_case("br"),
+                ),
+            };
+            if is_line_break {
+                items.push(Some("\n"));
+                return;
+            }
+            if is_block {
+                items.push(None);
+            }
+            for child in &node.children {
+                collect(child, items);
+            }
+            if is_block {
+                items.push(None);
+            }
+        }
+
+        let mut items = vec![];
+        for child in &self.children {
+            collect(child, &mut items);
+        }
+        let mut text = String::new();
+        let mut pending_break = false;
+        for item in items {
+            match item {
+                None => pending_break = true,
+                Some(s) => {
+                    if pending_break && !text.is_empty() && !text.ends_with('\n') {
+                        text.push('\n');
+                    }
+                    pending_break = false;
+                    text.push_str(s);
+                }
+            }
+        }
+        text
+    }
+
     pub fn set_inner_text(&mut self, text: &str) {
-        self.children = vec![Box::new(Node {
-            node_type: NodeType::Text(Text {
-                data: text.to_string(),
-            }),
-            children: vec![],
-        })];
+        self.children = vec![Text::new(text.to_string())];
     }
 
     pub fn inner_html(&self) -> String {
+        // the texts of raw text elements like `script` are written as they are
+        let raw = matches!(&self.node_type, NodeType::Element(e) if html::RAW_TEXT_ELEMENTS.iter().any(|name| e.has_tag_name(name)));
         self.children
             .iter()
-            .map(|node| format!("{:?}", node))
+            .map(|node| match &node.node_type {
+                NodeType::Text(t) if raw => t.data.clone(),
+                _ => node.outer_html(),
+            })
             .collect::<Vec<_>>()
             .join("")
     }
 
-    pub fn set_inner_html(&mut self, html: &str) {
-        self.children = html::parse_raw(html.into());
+    /// `outer_html` serializes the node and its descendants into HTML. Attributes are written in the source order.
+    /// `&`, `<` and `>` in texts and `&` and `"` in attribute values are escaped so that the HTML parses back to the same tree.
+    /// See https://html.spec.whatwg.org/multipage/parsing.html#escapingString
+    pub fn outer_html(&self) -> String {
+        match &self.node_type {
+            NodeType::Element(e) => {
+                let attributes = e
+                    .attributes
+                    .iter()
+                    .map(|(k, v)| {
+                        format!(
+                            " {}=\"{}\"",
+                            k,
+                            v.replace('&', "&amp;").replace('"', "&quot;")
+                        )
+                    })
+                    .collect::<String>();
+                format!(
+                    "<{}{}>{}</{}>",
+                    e.tag_name,
+                    attributes,
+                    self.inner_html(),
+                    e.tag_name
+                )
+            }
+            NodeType::Text(t) => t
+                .data
+                .replace('&', "&amp;")
+                .replace('<', "&lt;")
+                .replace('>', "&gt;"),
+            NodeType::Comment(c) => format!("<!--{}-->", c),
+        }
+    }
+
+    /// `set_inner_html` replaces the children with the nodes parsed from `html`.
+    /// Whitespaces are kept as a space since they separate inline contents, and an empty `html` removes the children.
+    pub fn set_inner_html(&mut self, html: &str) -> Result<()> {
+        let options = html::ParseOptions {
+            keep_whitespace: true,
+            ..Default::default()
+        };
+        self.children = html::parse_raw_with(html, &options)?;
+        Ok(())
+    }
+
+    pub fn debug_tree(&self, indent: usize) -> String {
+        let pad = " ".repeat(indent * 2);
+        let mut s = match &self.node_type {
+            NodeType::Element(e) => {
+                let attributes = e
+                    .attributes
+                    .iter()
+                    .map(|(k, v)| format!("{}=\"{}\"", k, v))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                format!("{}- Element({}) [{}]", pad, e.tag_name, attributes)
+            }
+            NodeType::Text(t) => format!("{}- Text({:?})", pad, t.data),
+            NodeType::Comment(c) => format!("{}- Comment({:?})", pad, c),
+        };
+        s += "\n";
+        for child in &self.children {
+            s += child.debug_tree(indent + 1).as_str();
+        }
+        s
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    pub fn from_json(json: &str) -> Result<Node> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    pub fn to_bin(&self) -> Result<Vec<u8>> {
+        Ok(bincode::serialize(&DomFile {
+            version: DOM_FORMAT_VERSION,
+            node: self,
+        })?)
+    }
+
+    pub fn from_bin(bytes: &[u8]) -> Result<Node> {
+        // check the header first so that an incompatible payload is never decoded as `Node`
+        let version: u32 = bincode::deserialize(bytes)?;
+        if version != DOM_FORMAT_VERSION {
+            bail!(
+                "unsupported DOM format version {} (expected {})",
+                version,
+                DOM_FORMAT_VERSION
+            );
+        }
+        let file: DomFile<Node> = bincode::deserialize(bytes)?;
+        Ok(file.node)
+    }
+
+    /// `patch_to_bin` serializes only the element with `id` and its descendants, and where the element is.
+    /// A copy of the tree, e.g. one restored by `from_bin`, is brought up to date by `apply_patch`
+    /// without serializing the whole tree again after a change inside the element.
+    /// It returns `None` if no element has `id`.
+    pub fn patch_to_bin(&self, id: &str) -> Result<Option<Vec<u8>>> {
+        let selector = Selector::IdSelector { id: id.to_string() };
+        let Some(path) = self.find_path(&[selector]) else {
+            return Ok(None);
+        };
+        let Some(node) = self.node_at(&path) else {
+            return Ok(None);
+        };
+        Ok(Some(bincode::serialize(&DomPatch {
+            version: DOM_FORMAT_VERSION,
+            path,
+            node,
+        })?))
+    }
+
+    /// `apply_patch` replaces the node at the path of a patch written by `patch_to_bin` with the node in it.
+    pub fn apply_patch(&mut self, bytes: &[u8]) -> Result<()> {
+        let version: u32 = bincode::deserialize(bytes)?;
+        if version != DOM_FORMAT_VERSION {
+            bail!(
+                "unsupported DOM format version {} (expected {})",
+                version,
+                DOM_FORMAT_VERSION
+            );
+        }
+        let patch: DomPatch<Node> = bincode::deserialize(bytes)?;
+        let Some(node) = self.node_at_mut(&patch.path) else {
+            bail!("no node at {:?} to patch", patch.path);
+        };
+        *node = patch.node;
+        Ok(())
     }
 
     pub fn get_element_by_id<'a>(self: &'a mut Box<Self>, id: &str) -> Option<&'a mut Box<Self>> {
-        match self.node_type {
-            NodeType::Element(ref e) => {
-                if e.id().map(|eid| eid.to_string() == id).unwrap_or(false) {
-                    return Some(self);
-                }
+        if let NodeType::Element(ref e) = self.node_type {
+            if e.id().is_some_and(|eid| eid == id) {
+                return Some(self);
             }
-            _ => (),
+        }
+        self.children
+            .iter_mut()
+            .find_map(|child| child.get_element_by_id(id))
+    }
+
+    /// `get_node_by_key` returns the node of `key` in the subtree, including the node itself.
+    pub fn get_node_by_key(self: &mut Box<Self>, key: NodeKey) -> Option<&mut Box<Self>> {
+        if self.key == key {
+            return Some(self);
+        }
+        self.children
+            .iter_mut()
+            .find_map(|child| child.get_node_by_key(key))
+    }
+
+    /// `query_selector` returns the first element matching `selectors` in document order, starting from the node itself.
+    pub fn query_selector(&self, selectors: &str) -> Result<Option<&Node>> {
+        let selectors = css::parse_selectors(selectors)?;
+        let Some(path) = self.find_path(&selectors) else {
+            return Ok(None);
+        };
+        Ok(self.node_at(&path))
+    }
+
+    /// `query_selector_mut` is the mutable version of `query_selector`.
+    pub fn query_selector_mut(&mut self, selectors: &str) -> Result<Option<&mut Node>> {
+        let selectors = css::parse_selectors(selectors)?;
+        let Some(path) = self.find_path(&selectors) else {
+            return Ok(None);
         };
+        Ok(self.node_at_mut(&path))
+    }
+
+    /// `node_at` returns the descendant reached by following `path`, the child indices from the node.
+    pub fn node_at(&self, path: &[usize]) -> Option<&Node> {
+        path.iter()
+            .try_fold(self, |node, &i| node.children.get(i).map(|c| c.as_ref()))
+    }
+
+    /// `node_at_mut` is the mutable version of `node_at`.
+    pub fn node_at_mut(&mut self, path: &[usize]) -> Option<&mut Node> {
+        path.iter().try_fold(self, |node, &i| {
+            node.children.get_mut(i).map(|c| c.as_mut())
+        })
+    }
+
+    /// `clone_subtree` returns a deep copy of the node and its descendants, which shares nothing with the original, not even the keys.
+    pub fn clone_subtree(&self) -> Box<Node> {
+        Box::new(self.clone())
+    }
+
+    /// `descendants` iterates over the node itself and all of its descendants in pre-order (document order).
+    pub fn descendants(&self) -> impl Iterator<Item = &Node> {
+        let mut stack = vec![self];
+        std::iter::from_fn(move || {
+            let node = stack.pop()?;
+            stack.extend(node.children.iter().rev().map(|child| child.as_ref()));
+            Some(node)
+        })
+    }
+
+    /// `find` returns the first node satisfying `pred` in document order, starting from the node itself.
+    pub fn find(&self, pred: impl Fn(&Node) -> bool) -> Option<&Node> {
+        self.descendants().find(|node| pred(node))
+    }
+
+    /// `find_all` returns the nodes satisfying `pred` in document order, including the node itself.
+    pub fn find_all(&self, pred: impl Fn(&Node) -> bool) -> Vec<&Node> {
+        self.descendants().filter(|node| pred(node)).collect()
+    }
+
+    /// `text_nodes` iterates over the texts in the node and its descendants in document order.
+    pub fn text_nodes(&self) -> impl Iterator<Item = &Text> {
+        self.descendants().filter_map(|node| match &node.node_type {
+            NodeType::Text(text) => Some(text),
+            _ => None,
+        })
+    }
+
+    /// `get_elements_by_tag_name` returns the elements with `tag_name` in document order, including the node itself.
+    pub fn get_elements_by_tag_name(&self, tag_name: &str) -> Vec<&Node> {
+        self.find_all(
+            |node| matches!(&node.node_type, NodeType::Element(e) if e.has_tag_name(tag_name)),
+        )
+    }
+
+    /// `node_count` returns the number of the nodes in the subtree, including the node itself.
+    pub fn node_count(&self) -> usize {
+        1 + self
+            .children
+            .iter()
+            .map(|child| child.node_count())
+            .sum::<usize>()
+    }
+
+    /// `max_depth` returns the number of the nodes on the longest path from the node down to a leaf.
+    /// A node without children has the depth of 1.
+    pub fn max_depth(&self) -> usize {
+        1 + self
+            .children
+            .iter()
+            .map(|child| child.max_depth())
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// `build_id_index` maps each id to the path of the element which `get_element_by_id` finds for it.
+    /// The index is not updated along with the tree; rebuild it after mutating the tree.
+    pub fn build_id_index(&self) -> HashMap<String, Vec<usize>> {
+        fn walk(node: &Node, path: &mut Vec<usize>, index: &mut HashMap<String, Vec<usize>>) {
+            if let NodeType::Element(ref e) = node.node_type {
+                if let Some(id) = e.id() {
+                    // the first element in document order wins
+                    index.entry(id.clone()).or_insert_with(|| path.clone());
+                }
+            }
+            for (i, child) in node.children.iter().enumerate() {
+                path.push(i);
+                walk(child, path, index);
+                path.pop();
+            }
+        }
+
+        let mut index = HashMap::new();
+        walk(self, &mut vec![], &mut index);
+        index
+    }
+
+    /// `find_path` returns the child indices leading to the first element matching any of `selectors`.
+    fn find_path(&self, selectors: &[Selector]) -> Option<Vec<usize>> {
+        fn walk(
+            node: &Node,
+            selectors: &[Selector],
+            context: &MatchingContext,
+            path: &mut Vec<usize>,
+        ) -> bool {
+            if matches!(node.node_type, NodeType::Element(_))
+                && selectors.iter().any(|s| s.matches_in(node, context))
+            {
+                return true;
+            }
+            for (i, child) in node.children.iter().enumerate() {
+                path.push(i);
+                if walk(child, selectors, &context.child(node, i), path) {
+                    return true;
+                }
+                path.pop();
+            }
+            false
+        }
+
+        let mut path = vec![];
+        walk(self, selectors, &MatchingContext::default(), &mut path).then_some(path)
+    }
+
+    /// `insert_child_at` inserts `child` so that it becomes the `index`-th child.
+    /// An out-of-range `index` appends `child` to the end.
+    pub fn insert_child_at(&mut self, index: usize, child: Box<Node>) {
+        let index = index.min(self.children.len());
+        self.children.insert(index, child);
+    }
+
+    /// `normalize` merges adjacent text nodes into the first of them and drops empty text nodes in the subtree,
+    /// as `Node.normalize()` does.
+    /// See https://dom.spec.whatwg.org/#dom-node-normalize
+    pub fn normalize(&mut self) {
+        let children = std::mem::take(&mut self.children);
+        for mut child in children {
+            let NodeType::Text(text) = &child.node_type else {
+                child.normalize();
+                self.children.push(child);
+                continue;
+            };
+            if text.data.is_empty() {
+                continue;
+            }
+            match self.children.last_mut().map(|last| &mut last.node_type) {
+                Some(NodeType::Text(last)) => last.data.push_str(&text.data),
+                _ => self.children.push(child),
+            }
+        }
+    }
+
+    /// `remove_element_by_id` detaches the first descendant element with the given id from its parent and returns it.
+    /// The node itself is never removed since it has no parent to be detached from.
+    pub fn remove_element_by_id(&mut self, id: &str) -> Option<Box<Node>> {
+        let position = self
+            .children
+            .iter()
+            .position(|child| match child.node_type {
+                NodeType::Element(ref e) => e.id().is_some_and(|eid| eid == id),
+                _ => false,
+            });
+        if let Some(position) = position {
+            return Some(self.children.remove(position));
+        }
         self.children
+            .iter_mut()
+            .find_map(|child| child.remove_element_by_id(id))
+    }
+
+    /// `remove_node_by_key` detaches the descendant of `key` from its parent and returns it, as `remove_element_by_id` does.
+    pub fn remove_node_by_key(&mut self, key: NodeKey) -> Option<Box<Node>> {
+        if let Some(position) = self.children.iter().position(|child| child.key == key) {
+            return Some(self.children.remove(position));
+        }
+        self.children
+            .iter_mut()
+            .find_map(|child| child.remove_node_by_key(key))
+    }
+
+    /// `replace_element_by_id` puts `replacement` in place of the first descendant element with the given id.
+    /// It returns `false` if no such element is found. The node itself is never replaced, as in `remove_element_by_id`.
+    pub fn replace_element_by_id(&mut self, id: &str, replacement: Box<Node>) -> bool {
+        match self
+            .children
             .iter_mut()
             .find_map(|child| child.get_element_by_id(id))
+        {
+            Some(slot) => {
+                *slot = replacement;
+                true
+            }
+            None => false,
+        }
     }
 }
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum NodeType {
     Element(Element),
     Text(Text),
+    /// `Comment` is kept in the tree only when the document is parsed with `ParseOptions::preserve_comments`.
+    Comment(String),
 }
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Element {
     pub tag_name: String,
     pub attributes: AttrMap,
 }
 
 impl Element {
+    #[allow(clippy::new_ret_no_self)]
     pub fn new(name: String, attributes: AttrMap, children: Vec<Box<Node>>) -> Box<Node> {
-        Box::new(Node {
-            node_type: NodeType::Element(Element {
+        Node::new(
+            NodeType::Element(Element {
                 tag_name: name,
-                attributes: attributes,
+                attributes,
             }),
             children,
-        })
+        )
+    }
+
+    /// `has_attribute` tells whether the element has the attribute `name` with any value, even an empty one.
+    pub fn has_attribute(&self, name: &str) -> bool {
+        self.attributes.contains_key(name)
     }
 
     pub fn id(&self) -> Option<&String> {
         self.attributes.get("id")
     }
 
+    /// `has_tag_name` compares the tag name ASCII case-insensitively. `*` matches any element.
+    pub fn has_tag_name(&self, tag_name: &str) -> bool {
+        tag_name == "*" || self.tag_name.eq_ignore_ascii_case(tag_name)
+    }
+
     pub fn attributes(&self) -> Vec<(String, String)> {
         self.attributes
             .iter()
-            .clone()
-            .into_iter()
             .map(|(k, v)| (k.to_string(), v.to_string()))
             .collect()
     }
 }
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Text {
     pub data: String,
 }
 
 impl Text {
+    #[allow(clippy::new_ret_no_self)]
     pub fn new(text: String) -> Box<Node> {
-        Box::new(Node {
-            node_type: NodeType::Text(Text { data: text }),
-            children: vec![],
+        Node::new(NodeType::Text(Text { data: text }), vec![])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_json_round_trip() {
+        let node = html::parse(
+            r#"<body><p id="result" class="inline">hello</p><div><p>world</p></div></body>"#,
+        )
+        .unwrap();
+        let json = node.to_json();
+        assert_eq!(Node::from_json(&json).unwrap(), *node);
+    }
+
+    #[test]
+    fn test_debug_tree() {
+        let node = html::parse(
+            r#"<body><p id="result" class="inline">hello</p><div><p>world</p></div></body>"#,
+        )
+        .unwrap();
+        assert_eq!(
+            node.debug_tree(0),
+            r#"- Element(body) []
+  - Element(p) [id="result" class="inline"]
+    - Text("hello")
+  - Element(div) []
+    - Element(p) []
+      - Text("world")
+"#
+        );
+    }
+
+    #[test]
+    fn test_attribute_order() {
+        let raw = r#"<p id="result" class="inline" lang="en">hello</p>"#;
+        let node = html::parse(raw).unwrap();
+        assert_eq!(node.outer_html(), raw);
+        let NodeType::Element(e) = &node.node_type else {
+            unreachable!()
+        };
+        assert_eq!(
+            e.attributes()
+                .into_iter()
+                .map(|(k, _)| k)
+                .collect::<Vec<_>>(),
+            vec!["id", "class", "lang"]
+        );
+        assert_eq!(Node::from_bin(&node.to_bin().unwrap()).unwrap(), *node);
+    }
+
+    #[test]
+    fn test_escape_round_trip() {
+        let raw = r#"<body><p title="a &amp; &quot;b&quot;">x &lt; y &amp;&amp; z &gt; 0</p><script>if (a < b && c) {}</script></body>"#;
+        let node = html::parse(raw).unwrap();
+        assert_eq!(node.children[0].inner_text(), "x < y && z > 0");
+        let NodeType::Element(e) = &node.children[0].node_type else {
+            unreachable!()
+        };
+        assert_eq!(e.attributes["title"], r#"a & "b""#);
+        assert_eq!(node.outer_html(), raw);
+        assert_eq!(*html::parse(&node.outer_html()).unwrap(), *node);
+    }
+
+    #[test]
+    fn test_comment_round_trip() {
+        let raw = r#"<body><!-- header --><p class="inline" id="result">hello<!--inline--></p><div><!----></div></body>"#;
+        let options = html::ParseOptions {
+            preserve_comments: true,
+            ..Default::default()
+        };
+        let node = html::parse_with(raw, &options).unwrap();
+        assert_eq!(
+            node.children[0].node_type,
+            NodeType::Comment(" header ".to_string())
+        );
+        assert_eq!(node.outer_html(), raw);
+        assert_eq!(
+            *html::parse_with(&node.outer_html(), &options).unwrap(),
+            *node
+        );
+        assert_eq!(node.children[1].inner_text(), "hello");
+
+        let bytes = node.to_bin().unwrap();
+        assert_eq!(Node::from_bin(&bytes).unwrap(), *node);
+
+        // comments are dropped by default
+        assert_eq!(
+            html::parse(raw).unwrap().outer_html(),
+            r#"<body><p class="inline" id="result">hello</p><div></div></body>"#
+        );
+    }
+
+    #[test]
+    fn test_from_json_invalid() {
+        assert!(Node::from_json("{").is_err());
+    }
+
+    #[test]
+    fn test_bin_round_trip() {
+        let node = html::parse(r#"<body><p id="result">hello</p></body>"#).unwrap();
+        let bytes = node.to_bin().unwrap();
+        assert_eq!(Node::from_bin(&bytes).unwrap(), *node);
+    }
+
+    #[test]
+    fn test_patch() {
+        let items = (0..100)
+            .map(|i| format!("<li>item {}</li>", i))
+            .collect::<String>();
+        let raw = format!(r#"<body><ul>{}</ul><p id="result">hello</p></body>"#, items);
+        let mut node = html::parse(&raw).unwrap();
+        let mut mirror = Node::from_bin(&node.to_bin().unwrap()).unwrap();
+
+        node.get_element_by_id("result")
+            .unwrap()
+            .set_inner_text("world");
+        let patch = node.patch_to_bin("result").unwrap().unwrap();
+        assert!(patch.len() < node.to_bin().unwrap().len() / 10);
+        mirror.apply_patch(&patch).unwrap();
+        assert_eq!(mirror, *node);
+
+        assert!(node.patch_to_bin("missing").unwrap().is_none());
+        // a patch does not fit a tree of another shape
+        let mut other = html::parse("<body></body>").unwrap();
+        assert!(other.apply_patch(&patch).is_err());
+    }
+
+    #[test]
+    fn test_from_bin_version_mismatch() {
+        let node = html::parse(r#"<body><p id="result">hello</p></body>"#).unwrap();
+        let bytes = bincode::serialize(&DomFile {
+            version: DOM_FORMAT_VERSION - 1,
+            node: &node,
         })
+        .unwrap();
+        let err = Node::from_bin(&bytes).unwrap_err();
+        assert!(err.to_string().contains("unsupported DOM format version"));
+
+        // a legacy payload without the version header
+        let bytes = bincode::serialize(&node).unwrap();
+        assert!(Node::from_bin(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_remove_element_by_id() {
+        let mut node =
+            html::parse(r#"<body><div><span id="result">hello</span><p>world</p></div></body>"#)
+                .unwrap();
+        let removed = node.remove_element_by_id("result").unwrap();
+        assert_eq!(removed.inner_text(), "hello");
+        assert!(node.get_element_by_id("result").is_none());
+        assert_eq!(node.children[0].children.len(), 1);
+        assert_eq!(node.inner_text(), "world");
+        assert!(node.remove_element_by_id("result").is_none());
+    }
+
+    #[test]
+    fn test_set_inner_html() {
+        let mut node = html::parse("<p>hello<b>world</b></p>").unwrap();
+        node.set_inner_html("").unwrap();
+        assert!(node.children.is_empty());
+
+        node.set_inner_html("a b").unwrap();
+        assert_eq!(node.inner_text(), "a b");
+        node.set_inner_html(" ").unwrap();
+        assert_eq!(node.inner_html(), " ");
+        node.set_inner_html("a  <b>b</b>\n <i>c</i>").unwrap();
+        assert_eq!(node.inner_html(), "a <b>b</b> <i>c</i>");
+        // the whitespaces around the children of an element are kept too
+        node.set_inner_html("<b> b </b>\n<i>\n</i>").unwrap();
+        assert_eq!(node.inner_html(), "<b> b </b> <i> </i>");
+    }
+
+    #[test]
+    fn test_find() {
+        let node = html::parse(
+            r#"<body><p>hello</p><div lang="en"><p class="inline">world</p></div><p class="inline">:)</p></body>"#,
+        )
+        .unwrap();
+        let is_p =
+            |node: &Node| matches!(&node.node_type, NodeType::Element(e) if e.tag_name == "p");
+        assert_eq!(node.find(is_p).unwrap().inner_text(), "hello");
+        assert_eq!(
+            node.find_all(is_p)
+                .iter()
+                .map(|node| node.inner_text())
+                .collect::<Vec<_>>(),
+            vec!["hello", "world", ":)"]
+        );
+
+        let has_class = |node: &Node| matches!(&node.node_type, NodeType::Element(e) if e.attributes.get("class").is_some_and(|c| c == "inline"));
+        assert_eq!(node.find(has_class).unwrap().inner_text(), "world");
+        assert_eq!(node.find_all(has_class).len(), 2);
+        assert!(node
+            .find(|node| matches!(&node.node_type, NodeType::Element(e) if e.attributes.contains_key("href")))
+            .is_none());
+        // the node itself is included
+        assert_eq!(node.find_all(|_| true).len(), node.node_count());
+    }
+
+    #[test]
+    fn test_text_nodes() {
+        let node = html::parse(
+            r#"<body>
+    <p>hello</p>
+    <p class="inline">world</p>
+    <p class="inline">:)</p>
+    <div class="none"><p>this should not be shown</p></div>
+</body>"#,
+        )
+        .unwrap();
+        assert_eq!(
+            node.text_nodes()
+                .map(|text| text.data.as_str())
+                .collect::<Vec<_>>(),
+            vec!["hello", "world", ":)", "this should not be shown"]
+        );
+        assert_eq!(Text::new("a".to_string()).text_nodes().count(), 1);
+    }
+
+    #[test]
+    fn test_rendered_text() {
+        let node = html::parse(r#"<div><p>hello</p><p>world</p></div>"#).unwrap();
+        assert_eq!(node.inner_text(), "helloworld");
+        assert_eq!(node.rendered_text(), "hello\nworld");
+
+        let node = html::parse(
+            r#"<body><header>title</header><p>a<b>bold</b><br>next</p><div><div>nested</div></div>tail</body>"#,
+        )
+        .unwrap();
+        assert_eq!(node.rendered_text(), "title\nabold\nnext\nnested\ntail");
+    }
+
+    #[test]
+    fn test_insert_child_at() {
+        let mut node = html::parse(r#"<ul><li>b</li></ul>"#).unwrap();
+        let li = |text: &str| {
+            Element::new(
+                "li".to_string(),
+                AttrMap::new(),
+                vec![Text::new(text.to_string())],
+            )
+        };
+        node.insert_child_at(0, li("a"));
+        node.insert_child_at(100, li("c"));
+        node.insert_child_at(2, li("bc"));
+        assert_eq!(
+            node.inner_html(),
+            "<li>a</li><li>b</li><li>bc</li><li>c</li>"
+        );
+    }
+
+    #[test]
+    fn test_normalize() {
+        let mut node = html::parse(r#"<div><p>a</p></div>"#).unwrap();
+        node.children.extend([
+            Text::new("b".to_string()),
+            Text::new(String::new()),
+            Text::new("c".to_string()),
+            Text::new("d".to_string()),
+        ]);
+        node.children[0]
+            .children
+            .insert(0, Text::new(String::new()));
+        node.normalize();
+        assert_eq!(node.children.len(), 2);
+        assert_eq!(
+            node.children[1].node_type,
+            NodeType::Text(Text {
+                data: "bcd".to_string()
+            })
+        );
+        // descendants are normalized too
+        assert_eq!(node.children[0].children.len(), 1);
+        assert_eq!(node.inner_html(), "<p>a</p>bcd");
+    }
+
+    #[test]
+    fn test_replace_element_by_id() {
+        let mut node =
+            html::parse(r#"<body><p>hello</p><div><span id="result">hoge</span></div></body>"#)
+                .unwrap();
+        let replacement = Element::new(
+            "div".to_string(),
+            [("id".to_string(), "new".to_string())]
+                .into_iter()
+                .collect(),
+            vec![Text::new("fuga".to_string())],
+        );
+        assert!(node.replace_element_by_id("result", replacement));
+        assert!(node.get_element_by_id("result").is_none());
+        assert_eq!(
+            node.debug_tree(0),
+            r#"- Element(body) []
+  - Element(p) []
+    - Text("hello")
+  - Element(div) []
+    - Element(div) [id="new"]
+      - Text("fuga")
+"#
+        );
+
+        let replacement = Text::new("piyo".to_string());
+        assert!(!node.replace_element_by_id("result", replacement));
+    }
+
+    #[test]
+    fn test_query_selector() {
+        let mut node = html::parse(
+            r#"<body><p id="result">hello</p><div><p class="inline">world</p></div></body>"#,
+        )
+        .unwrap();
+        let found = node.query_selector(".inline").unwrap().unwrap();
+        assert_eq!(found.inner_text(), "world");
+        assert_eq!(
+            node.query_selector("body").unwrap().unwrap().children.len(),
+            2
+        );
+        assert_eq!(
+            node.query_selector("div, p").unwrap().unwrap().inner_text(),
+            "hello"
+        );
+        assert!(node.query_selector("span").unwrap().is_none());
+        assert!(node.query_selector("{").is_err());
+
+        node.query_selector_mut(".inline")
+            .unwrap()
+            .unwrap()
+            .set_inner_text("fuga");
+        assert_eq!(node.children[1].inner_text(), "fuga");
+    }
+
+    #[test]
+    fn test_build_id_index() {
+        // a tree of 3^6 leaves, where every element has an id and some ids are duplicated
+        fn tree(depth: usize, id: &mut usize) -> Box<Node> {
+            *id += 1;
+            let attributes = [("id".to_string(), format!("e{}", *id % 500))]
+                .into_iter()
+                .collect();
+            let children = if depth == 0 {
+                vec![Text::new(format!("leaf{}", id))]
+            } else {
+                (0..3).map(|_| tree(depth - 1, id)).collect()
+            };
+            Element::new("div".to_string(), attributes, children)
+        }
+        let mut node = tree(6, &mut 0);
+
+        let index = node.build_id_index();
+        assert_eq!(index.len(), 500);
+        for (id, path) in &index {
+            let expected = node.get_element_by_id(id).unwrap().debug_tree(0);
+            assert_eq!(
+                node.node_at(path).unwrap().debug_tree(0),
+                expected,
+                "{}",
+                id
+            );
+        }
+        assert_eq!(index.get("e1"), Some(&vec![]));
+        assert!(node.node_at(&[3]).is_none());
+    }
+
+    #[test]
+    fn test_clone_subtree() {
+        let mut node =
+            html::parse(r#"<body><div id="a"><p class="inline">hello</p></div></body>"#).unwrap();
+        let mut cloned = node.children[0].clone_subtree();
+        assert_eq!(cloned, node.children[0]);
+
+        cloned.children[0].set_inner_text("world");
+        cloned.children.push(Text::new("!".to_string()));
+        assert_eq!(node.children[0].inner_text(), "hello");
+        assert_eq!(node.children[0].children.len(), 1);
+
+        node.children[0].set_inner_text("fuga");
+        assert_eq!(cloned.inner_text(), "world!");
+    }
+
+    #[test]
+    fn test_descendants() {
+        let node = html::parse(
+            r#"<body><div id="a"><p id="b">hello</p></div><span id="c">world</span></body>"#,
+        )
+        .unwrap();
+        assert_eq!(node.descendants().count(), node.node_count());
+        let ids = node
+            .descendants()
+            .filter_map(|node| match &node.node_type {
+                NodeType::Element(e) => e.id().cloned(),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_get_elements_by_tag_name() {
+        let node = html::parse(
+            r#"<body><p id="a">hello</p><div><P id="b">world</P><span>!</span></div></body>"#,
+        )
+        .unwrap();
+        let ids = |tag_name: &str| {
+            node.get_elements_by_tag_name(tag_name)
+                .into_iter()
+                .map(|node| match &node.node_type {
+                    NodeType::Element(e) => e.id().cloned().unwrap_or_default(),
+                    _ => unreachable!(),
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ids("p"), vec!["a", "b"]);
+        assert_eq!(ids("body").len(), 1);
+        assert_eq!(ids("*").len(), 5);
+        assert!(ids("table").is_empty());
+    }
+
+    #[test]
+    fn test_node_count_and_max_depth() {
+        let node = html::parse(
+            r#"<body>
+    <script>
+    document.getElementById("result").innerText = "fuga";
+    </script>
+    <p>hello</p>
+    <p class="inline">world</p>
+    <div class="none">
+        <p>this should not be shown</p>
+    </div>
+    <span id="result">hoge</span>
+</body>"#,
+        )
+        .unwrap();
+        // body, script, p, p, div, p, span and 5 texts in them
+        assert_eq!(node.node_count(), 12);
+        // body > div > p > text
+        assert_eq!(node.max_depth(), 4);
+
+        let text = Text::new("leaf".to_string());
+        assert_eq!(text.node_count(), 1);
+        assert_eq!(text.max_depth(), 1);
     }
 }
@@ -1,41 +1,65 @@
-use crate::blank;
 use crate::dom::{AttrMap, Element, Node, NodeType, Text};
-use combine::error::{ParseError, StreamError};
-use combine::parser::char::{char, letter};
-use combine::{attempt, between, choice, many, many1, parser, satisfy, sep_end_by, Parser, Stream};
+use crate::{blank, parse_error};
+use anyhow::{bail, Result};
+use combine::error::{Commit, ParseError, StdParseResult, StreamError};
+use combine::parser::char::{alpha_num, char, letter, string, string_cmp};
+use combine::parser::repeat::take_until;
+use combine::stream::position;
+use combine::{
+    attempt, between, choice, eof, look_ahead, many, many1, optional, satisfy, sep_end_by,
+    skip_many, EasyParser, Parser as _, Stream,
+};
+use std::{cell::Cell, rc::Rc};
 
-/// `attribute` consumes `name="value"`.
-// attribute := attribute_name S* "=" S* attribute_value
-// attribute_name := alphabet+
+/// `attribute` consumes `name="value"`, or a bare `name` whose value is empty like `required`.
+// attribute := attribute_name (S* "=" S* attribute_value)?
+// attribute_name := alphabet (alphabet | digit | "-" | "_")*
 // attribute_value := '"' attribute_inner_value '"'
-// attribute_inner_value := (alphabet | digit | sign | " ")+
-fn attribute<Input>() -> impl Parser<Input, Output = (String, String)>
+// attribute_inner_value := (escaped_quote | [^"])+
+// escaped_quote := '\\"'
+// character references like `&quot;` in the value are decoded.
+fn attribute<Input>() -> impl combine::Parser<Input, Output = (String, String)>
 where
     Input: Stream<Token = char>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
 {
     (
-        // letter(): [a-zA-Z]
-        // many1: +
-        many1::<String, _, _>(letter()), // まずは属性の名前を何文字か読む
-        // \s*
-        blank(),
-        // =
-        char('='), // = を読む
-        // \s*
-        blank(),
-        // "[!"]+"
-        between(
-            char('"'),
-            char('"'),
-            many1::<String, _, _>(satisfy(|c: char| c != '"')),
-        ), // 引用符の間の、引用符を含まない文字を読む
+        // [a-zA-Z][a-zA-Z0-9_-]* e.g. `data-user-id`
+        (
+            letter(),
+            many::<String, _, _>(satisfy(|c: char| {
+                c.is_ascii_alphanumeric() || c == '-' || c == '_'
+            })),
+        )
+            .map(|(first, rest)| format!("{}{}", first, rest)), // まずは属性の名前を何文字か読む
+        optional((
+            // \s* =
+            attempt((blank(), char('='))), // = を読む
+            // \s*
+            blank(),
+            // "[!"]*"
+            between(
+                char('"'),
+                char('"'),
+                many::<String, _, _>(choice((
+                    // escaped quotes do not terminate the value
+                    attempt(string("\\\"")).map(|_| '"'),
+                    satisfy(|c: char| c != '"'),
+                ))),
+            ), // 引用符の間の、引用符を含まない文字を読む
+        )),
     )
-        .map(|v| (v.0, v.4))
+        .map(|(name, value)| {
+            let value = value.map_or(String::new(), |(_, _, value)| {
+                decode_character_references(&value)
+            });
+            (name, value)
+        })
 }
 
 /// `attributes` consumes `name1="value1" name2="value2" ... name="value"`
-fn attributes<Input>() -> impl Parser<Input, Output = AttrMap>
+/// The values are trimmed so that `class=" inline "` is the same as `class="inline"`.
+fn attributes<Input>() -> impl combine::Parser<Input, Output = AttrMap>
 where
     Input: Stream<Token = char>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
@@ -43,116 +67,600 @@ where
     // 空白区切りで `attribute`を使いたい
     // https://docs.rs/combine/latest/combine/fn.sep_by.html
     //          ↓ `F` は変換先の型
-    sep_end_by::<Vec<_>, _, _, _>(attribute(), blank()).map(|attrs| AttrMap::from_iter(attrs))
+    sep_end_by::<Vec<_>, _, _, _>(attribute(), blank()).map(|attributes| {
+        attributes
+            .into_iter()
+            .map(|(name, value)| (name, value.trim().to_string()))
+            .collect()
+    })
+}
+
+/// `tag_name` consumes a tag name like `p` or `h1`.
+fn tag_name<Input>() -> impl combine::Parser<Input, Output = String>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    (letter(), many::<String, _, _>(alpha_num())).map(|(first, rest)| format!("{}{}", first, rest))
 }
 
 /// `open_tag` consumes `<tag_name attr_name="attr_value" ...>`.
-fn open_tag<Input>() -> impl Parser<Input, Output = (String, AttrMap)>
+/// It consumes nothing when the input does not start with `<tag_name`, and fails where the tag is malformed otherwise,
+/// so that the error points at the malformed part rather than at the outermost node backtracked to.
+fn open_tag<Input>() -> impl combine::Parser<Input, Output = (String, AttrMap)>
 where
     Input: Stream<Token = char>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
 {
     (
-        char('<'),
-        many1::<String, _, _>(letter()),
+        attempt((char('<'), tag_name())),
         blank(),
         attributes(),
         char('>'),
     )
-        .map(|v| (v.1, v.3))
+        .map(|v| (v.0 .1, v.2))
 }
 
 /// close_tag consumes `</tag_name>`.
-fn close_tag<Input>() -> impl Parser<Input, Output = String>
+fn close_tag<Input>() -> impl combine::Parser<Input, Output = String>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    (char('<'), char('/'), tag_name(), char('>')).map(|v| v.2)
+}
+
+/// `close_tag_of` consumes `</tag_name>` of the element `tag_name`, and fails on a close tag of another element.
+fn close_tag_of<Input>(tag_name: String) -> impl combine::Parser<Input, Output = ()>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    close_tag().and_then(move |close_tag_name| {
+        if close_tag_name == tag_name {
+            Ok(())
+        } else {
+            Err(<Input::Error as combine::error::ParseError<
+                char,
+                Input::Range,
+                Input::Position,
+            >>::StreamError::message_static_message(
+                "tag name of open tag and close tag mismatched",
+            ))
+        }
+    })
+}
+
+/// `doctype` consumes `<!DOCTYPE ...>` in any case and discards it.
+/// See https://html.spec.whatwg.org/multipage/syntax.html#the-doctype
+fn doctype<Input>() -> impl combine::Parser<Input, Output = ()>
 where
     Input: Stream<Token = char>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
 {
     (
         char('<'),
-        char('/'),
-        many1::<String, _, _>(letter()),
+        char('!'),
+        string_cmp("doctype", |l, r| l.eq_ignore_ascii_case(&r)),
+        skip_many(satisfy(|c: char| c != '>')),
         char('>'),
     )
-        .map(|v| v.2)
+        .map(|_| ())
+}
+
+/// `ParseState` is what the parsers of nodes need to know about the surroundings and `ParseOptions`.
+#[derive(Debug, Clone)]
+struct ParseState {
+    /// `open` is the names of the open elements from the outermost in the lenient mode, and `None` in the strict mode.
+    open: Option<Vec<String>>,
+    /// `keep_whitespace` is `ParseOptions::keep_whitespace`.
+    keep_whitespace: bool,
+    /// `preformatted` is true inside `pre`, where texts are kept as they are.
+    preformatted: bool,
+    /// `depth` is the number of the elements enclosing the nodes being parsed.
+    depth: usize,
+    /// `max_depth` is `ParseOptions::max_depth`.
+    max_depth: usize,
+    /// `too_deep` is set when an element is nested deeper than `max_depth`.
+    /// It is shared by all the states since the error itself is lost while the parsers backtrack.
+    too_deep: Rc<Cell<bool>>,
+}
+
+impl ParseState {
+    fn new(options: &ParseOptions) -> Self {
+        ParseState {
+            open: options.lenient.then(Vec::new),
+            keep_whitespace: options.keep_whitespace,
+            preformatted: false,
+            depth: 0,
+            max_depth: options.max_depth,
+            too_deep: Rc::new(Cell::new(false)),
+        }
+    }
+}
+
+impl Default for ParseState {
+    fn default() -> Self {
+        ParseState::new(&ParseOptions::default())
+    }
 }
 
-// `nodes_` (and `nodes`) tries to parse input as Element or Text.
-fn nodes_<Input>() -> impl Parser<Input, Output = Vec<Box<Node>>>
+/// `nodes` consumes the nodes up to anything which is not a node, such as the close tag of the parent.
+fn nodes<Input>(state: ParseState) -> impl combine::Parser<Input, Output = Vec<Box<Node>>>
 where
     Input: Stream<Token = char>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
 {
-    // attempt
-    // https://docs.rs/combine/latest/combine/fn.attempt.html
+    combine::parser(move |input: &mut Input| parse_nodes(input, &state))
+}
 
-    // choice
-    // https://docs.rs/combine/latest/combine/fn.choice.html
+/// `parse_nodes` is the body of `nodes`.
+///
+/// NOTE: `parse_nodes` and `parse_children` recurse for each level of nesting, so they are written by hand
+/// and leave the parsers to functions which return before going deeper.
+/// Otherwise the frames of the combinators stacked for each level would take kilobytes of the stack.
+// nodes := (node S*)*
+// node := comment | void_element | element | stray_close_tag | text
+#[allow(clippy::vec_box)]
+fn parse_nodes<Input>(
+    input: &mut Input,
+    state: &ParseState,
+) -> StdParseResult<Vec<Box<Node>>, Input>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    let keep_whitespace = state.keep_whitespace || state.preformatted;
+    let mut nodes = vec![];
+    let mut committed = Commit::Peek(());
+    loop {
+        // an element is not backtracked once its open tag starts, so that an error in it is reported where it is
+        let node = match leaf_node(input, state)?.0 {
+            Some(node) => node,
+            None => match element_open_tag(input, state) {
+                Ok((tag, _)) => Some(parse_children(input, state, tag)?.0),
+                Err(Commit::Peek(_)) => break,
+                Err(e) => return Err(e),
+            },
+        };
+        committed = Commit::Commit(());
+        // text要素の前後をtrimしたものが空なら無視する
+        if let Some(node) = node {
+            if !matches!(&node.node_type, NodeType::Text(t) if !keep_whitespace && t.data.trim().is_empty())
+            {
+                nodes.push(node);
+            }
+        }
+    }
+    Ok((nodes, committed))
+}
 
-    // nodes := (node S*)*
-    // node := element | text
-    attempt(many(choice((attempt(element()), attempt(text())))))
+/// `leaf_node` consumes a node which has no children to parse, or a stray close tag which becomes `Some(None)`.
+/// It returns `None` if none of them comes.
+/// A void element is tried before `element_open_tag` takes its open tag.
+#[inline(never)]
+#[allow(clippy::type_complexity)]
+fn leaf_node<Input>(
+    input: &mut Input,
+    state: &ParseState,
+) -> StdParseResult<Option<Option<Box<Node>>>, Input>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    optional(choice((
+        attempt(comment()).map(Some),
+        attempt(void_element()).map(Some),
+        attempt(stray_close_tag(state.open.as_deref())).map(|_| None),
+        attempt(text(state.keep_whitespace, state.preformatted)).map(Some),
+    )))
+    .parse_stream(input)
+    .into_result()
+}
+
+/// `stray_close_tag` consumes a close tag which closes none of the `open` elements, which is ignored in the lenient mode.
+/// It always fails in the strict mode.
+fn stray_close_tag<'a, Input>(
+    open: Option<&'a [String]>,
+) -> impl combine::Parser<Input, Output = ()> + 'a
+where
+    Input: Stream<Token = char> + 'a,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    close_tag().and_then(move |tag_name| match &open {
+        Some(open) if !open.contains(&tag_name) => Ok(()),
+        _ => Err(<Input::Error as combine::error::ParseError<
+            char,
+            Input::Range,
+            Input::Position,
+        >>::StreamError::message_static_message(
+            "unexpected close tag"
+        )),
+    })
+}
+
+/// `comment` consumes `<!-- comment -->`.
+fn comment<Input>() -> impl combine::Parser<Input, Output = Box<Node>>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    (
+        string("<!--"),
+        take_until::<String, _, _>(attempt(string("-->"))),
+        string("-->"),
+    )
+        .map(|(_, comment, _)| Node::new(NodeType::Comment(comment), vec![]))
 }
 
 /// `text` consumes input until `<` comes.
-fn text<Input>() -> impl Parser<Input, Output = Box<Node>>
+/// The whitespaces around the text are trimmed unless `keep_whitespace` is true.
+/// A `preformatted` text is neither trimmed nor collapsed.
+fn text<Input>(
+    keep_whitespace: bool,
+    preformatted: bool,
+) -> impl combine::Parser<Input, Output = Box<Node>>
 where
     Input: Stream<Token = char>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
 {
-    many1(satisfy(|c: char| c != '<')).map(|t: String| Text::new(t.trim().to_string()))
+    many1(satisfy(|c: char| c != '<')).map(move |t: String| {
+        let t = decode_character_references(&t);
+        if preformatted {
+            return Text::new(t);
+        }
+        let t = if keep_whitespace {
+            t.as_str()
+        } else {
+            t.trim()
+        };
+        Text::new(collapse_whitespace(t))
+    })
 }
 
-/// `element` consumes `<tag_name attr_name="attr_value" ...>(children)</tag_name>`.
-fn element<Input>() -> impl Parser<Input, Output = Box<Node>>
+/// `CHARACTER_REFERENCES` are the named character references which `decode_character_references` knows.
+const CHARACTER_REFERENCES: [(&str, char); 4] = [
+    ("&amp;", '&'),
+    ("&lt;", '<'),
+    ("&gt;", '>'),
+    ("&quot;", '"'),
+];
+
+/// `decode_character_references` replaces the character references in `CHARACTER_REFERENCES` with the characters.
+/// Unknown ones are left as they are.
+/// See https://html.spec.whatwg.org/multipage/syntax.html#character-references
+fn decode_character_references(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(i) = rest.find('&') {
+        decoded.push_str(&rest[..i]);
+        rest = &rest[i..];
+        match CHARACTER_REFERENCES
+            .iter()
+            .find(|(reference, _)| rest.starts_with(reference))
+        {
+            Some((reference, c)) => {
+                decoded.push(*c);
+                rest = &rest[reference.len()..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+/// `collapse_whitespace` replaces each run of whitespaces (spaces, tabs and newlines) with a single space.
+/// https://www.w3.org/TR/css-text-3/#white-space-phase-1
+fn collapse_whitespace(text: &str) -> String {
+    let mut collapsed = String::with_capacity(text.len());
+    let mut in_whitespace = false;
+    for c in text.chars() {
+        if c.is_whitespace() {
+            if !in_whitespace {
+                collapsed.push(' ');
+            }
+            in_whitespace = true;
+        } else {
+            collapsed.push(c);
+            in_whitespace = false;
+        }
+    }
+    collapsed
+}
+
+/// `RAW_TEXT_ELEMENTS` are the elements whose contents are a text as it is, up to their close tag.
+/// See https://html.spec.whatwg.org/multipage/syntax.html#raw-text-elements
+pub(crate) const RAW_TEXT_ELEMENTS: [&str; 2] = ["script", "style"];
+
+/// `raw_text` consumes the contents of a raw text element `tag_name` up to its close tag.
+fn raw_text<Input>(tag_name: String) -> impl combine::Parser<Input, Output = Vec<Box<Node>>>
 where
     Input: Stream<Token = char>,
     Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
 {
-    (open_tag(), blank(), nodes(), blank(), close_tag()).and_then(
-        |((open_tag_name, attributes), _, children, _, close_tag_name)| {
-            if open_tag_name == close_tag_name {
-                Ok(Element::new(open_tag_name, attributes, children))
+    take_until::<String, _, _>(attempt(look_ahead(close_tag_of(tag_name)))).map(|t| {
+        if t.is_empty() {
+            vec![]
+        } else {
+            vec![Text::new(t)]
+        }
+    })
+}
+
+/// `VOID_ELEMENTS` are the elements which have no contents and no close tag.
+/// See https://html.spec.whatwg.org/multipage/syntax.html#void-elements
+const VOID_ELEMENTS: [&str; 13] = [
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+    "wbr",
+];
+
+/// `is_void_element` tells whether `tag_name` is one of `VOID_ELEMENTS`, compared ASCII case-insensitively.
+fn is_void_element(tag_name: &str) -> bool {
+    VOID_ELEMENTS
+        .iter()
+        .any(|name| name.eq_ignore_ascii_case(tag_name))
+}
+
+/// `void_element` consumes `<tag_name attr_name="attr_value" ...>` of a void element.
+/// A close tag right after it like `<br></br>` is consumed together.
+fn void_element<Input>() -> impl combine::Parser<Input, Output = Box<Node>>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    open_tag()
+        .and_then(|(tag_name, attributes)| {
+            if is_void_element(&tag_name) {
+                Ok((tag_name, attributes))
             } else {
                 Err(<Input::Error as combine::error::ParseError<
                     char,
                     Input::Range,
                     Input::Position,
                 >>::StreamError::message_static_message(
-                    "tag name of open tag and close tag mismatched",
+                    "close tag is missing"
                 ))
             }
-        },
-    )
+        })
+        .then(|(tag_name, attributes)| {
+            optional(attempt(close_tag_of(tag_name.clone())))
+                .map(move |_| Element::new(tag_name.clone(), attributes.clone(), vec![]))
+        })
 }
 
-parser! {
-    fn nodes[Input]()(Input) -> Vec<Box<Node>>
-    where [Input: Stream<Token = char>]
+/// `element_open_tag` consumes the open tag `<tag_name attr_name="attr_value" ...>` of an element.
+/// A void element is left for `void_element` since it has no children nor close tag.
+#[inline(never)]
+fn element_open_tag<Input>(
+    input: &mut Input,
+    state: &ParseState,
+) -> StdParseResult<(String, AttrMap), Input>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    let (depth, max_depth, too_deep) = (state.depth, state.max_depth, state.too_deep.clone());
+    // the parsers recurse for each level of nesting, so a too deep document is rejected before it overflows the stack
+    open_tag()
+        .and_then(move |tag: (String, AttrMap)| {
+            if is_void_element(&tag.0) {
+                Err(<Input::Error as combine::error::ParseError<
+                    char,
+                    Input::Range,
+                    Input::Position,
+                >>::StreamError::message_static_message(
+                    "void element cannot have children",
+                ))
+            } else if depth < max_depth {
+                Ok(tag)
+            } else {
+                too_deep.set(true);
+                Err(<Input::Error as combine::error::ParseError<
+                    char,
+                    Input::Range,
+                    Input::Position,
+                >>::StreamError::message_static_message(
+                    "elements are nested too deeply",
+                ))
+            }
+        })
+        .parse_stream(input)
+        .into_result()
+}
+
+/// `parse_children` consumes the rest of an element after its open tag `tag`: `(children)</tag_name>`.
+///
+/// In the lenient mode (`state.open` is `Some`), the element is closed implicitly when a close tag of an ancestor
+/// or the end of input comes before its own close tag, as browsers recover from misnested tags.
+fn parse_children<Input>(
+    input: &mut Input,
+    state: &ParseState,
+    (tag_name, attributes): (String, AttrMap),
+) -> StdParseResult<Box<Node>, Input>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    let preformatted = state.preformatted || tag_name.eq_ignore_ascii_case("pre");
+    // the whitespaces around the children are significant in `pre` and raw text elements,
+    // and are kept under `keep_whitespace`
+    let children = if RAW_TEXT_ELEMENTS
+        .iter()
+        .any(|name| name.eq_ignore_ascii_case(&tag_name))
     {
-        nodes_().map(|nodes| nodes
-            // text要素の前後をtrimしたものが空なら無視する
-            .into_iter()
-            .filter(|n| match &n.node_type {
-                NodeType::Text(t) => !t.data.trim().is_empty(),
-                _ => true,
-            }).collect())
+        committed(raw_text(tag_name.clone()), input)?.0
+    } else {
+        let child_state = ParseState {
+            open: state.open.clone().map(|mut open| {
+                open.push(tag_name.clone());
+                open
+            }),
+            preformatted,
+            depth: state.depth + 1,
+            ..state.clone()
+        };
+        let trim = !(preformatted || state.keep_whitespace);
+        if trim {
+            committed(blank(), input)?;
+        }
+        let (children, _) = parse_nodes(input, &child_state).map_err(Commit::into_commit)?;
+        if trim {
+            committed(blank(), input)?;
+        }
+        children
+    };
+    // a close tag of another element is an error in the strict mode,
+    // and is left for the ancestor which it closes in the lenient mode
+    let own_close_tag = close_tag_of(tag_name.clone());
+    if state.open.is_some() {
+        // the element is closed implicitly in the lenient mode
+        committed(optional(attempt(own_close_tag)), input)?;
+    } else {
+        committed(own_close_tag.expected("close tag"), input)?;
     }
+    Ok((
+        Element::new(tag_name, attributes, children),
+        Commit::Commit(()),
+    ))
 }
 
-pub fn parse(raw: &str) -> Box<Node> {
-    let mut nodes = parse_raw(raw);
+/// `committed` runs `parser` as a part of an element whose open tag has been consumed,
+/// so that it is committed to even if `parser` fails without consuming input.
+#[inline(never)]
+fn committed<Input, P>(mut parser: P, input: &mut Input) -> StdParseResult<P::Output, Input>
+where
+    Input: Stream<Token = char>,
+    P: combine::Parser<Input>,
+{
+    parser
+        .parse_stream(input)
+        .into_result()
+        .map_err(Commit::into_commit)
+}
+
+/// `DEFAULT_MAX_DEPTH` is the default of `ParseOptions::max_depth`.
+/// Each level of nesting takes a few kilobytes of the stack in a debug build,
+/// so it is kept low enough for a thread with a 2MB stack like the ones of `cargo test`.
+pub const DEFAULT_MAX_DEPTH: usize = 256;
+
+/// `ParseOptions` changes how a document is parsed.
+#[derive(Debug)]
+pub struct ParseOptions {
+    /// `preserve_comments` keeps comments as `NodeType::Comment` instead of dropping them.
+    pub preserve_comments: bool,
+    /// `lenient` recovers from misnested tags instead of failing: an element is closed implicitly
+    /// by a close tag of its ancestor or the end of input, and a close tag matching no open element is ignored.
+    pub lenient: bool,
+    /// `keep_whitespace` keeps the whitespaces around texts and whitespace-only texts, collapsing each run of them into a space,
+    /// as they are significant between inline contents like `a <b>b</b>`.
+    pub keep_whitespace: bool,
+    /// `max_depth` is how deeply elements can be nested. A document nested deeper is an error.
+    pub max_depth: usize,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions {
+            preserve_comments: false,
+            lenient: false,
+            keep_whitespace: false,
+            max_depth: DEFAULT_MAX_DEPTH,
+        }
+    }
+}
+
+/// `parse` returns the single root node of `raw`.
+/// Multiple top-level nodes are wrapped in a synthetic `html` element; use `parse_fragment` to get them as they are.
+pub fn parse(raw: &str) -> Result<Box<Node>> {
+    parse_with(raw, &ParseOptions::default())
+}
+
+pub fn parse_with(raw: &str, options: &ParseOptions) -> Result<Box<Node>> {
+    let mut nodes = parse_raw_with(raw, options)?;
     if nodes.len() == 1 {
-        nodes.pop().unwrap()
+        Ok(nodes.pop().unwrap())
     } else {
-        Element::new("html".to_string(), AttrMap::new(), nodes)
+        Ok(Element::new("html".to_string(), AttrMap::new(), nodes))
+    }
+}
+
+/// `Parser` parses a document given in chunks, e.g. as they are fetched.
+///
+/// NOTE: chunks are buffered and parsed at once by `finish`, so a tag may be split at any point.
+#[derive(Debug, Default)]
+pub struct Parser {
+    buffer: String,
+}
+
+impl Parser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `feed` appends `chunk` to the document.
+    pub fn feed(&mut self, chunk: &str) {
+        self.buffer.push_str(chunk);
     }
+
+    /// `finish` parses the whole document fed so far as `parse` does.
+    pub fn finish(self) -> Result<Box<Node>> {
+        parse(&self.buffer)
+    }
+}
+
+/// `parse_fragment` returns the top-level nodes of `raw` without wrapping them.
+pub fn parse_fragment(raw: &str) -> Result<Vec<Box<Node>>> {
+    parse_raw(raw)
 }
 
-pub fn parse_raw(raw: &str) -> Vec<Box<Node>> {
-    let (nodes, _) = nodes().parse(raw).unwrap();
-    nodes
+pub fn parse_raw(raw: &str) -> Result<Vec<Box<Node>>> {
+    parse_raw_with(raw, &ParseOptions::default())
+}
+
+pub fn parse_raw_with(raw: &str, options: &ParseOptions) -> Result<Vec<Box<Node>>> {
+    let state = ParseState::new(options);
+    let too_deep = state.too_deep.clone();
+    // a document may start with `<!DOCTYPE html>`, which is not a node
+    let result = (optional(attempt((blank(), doctype()))), nodes(state), eof())
+        .easy_parse(position::Stream::new(raw));
+    if too_deep.get() {
+        bail!(
+            "elements are nested deeper than {} levels",
+            options.max_depth
+        );
+    }
+    let ((_, mut nodes, _), _) = result.map_err(parse_error)?;
+    if !options.preserve_comments {
+        remove_comments(&mut nodes);
+    }
+    Ok(nodes)
+}
+
+#[allow(clippy::vec_box)]
+fn remove_comments(nodes: &mut Vec<Box<Node>>) {
+    nodes.retain(|node| !matches!(node.node_type, NodeType::Comment(_)));
+    for node in nodes {
+        remove_comments(&mut node.children);
+    }
 }
+
+/// `extract_stylesheets` concatenates the contents of every `<style>` element in document order,
+/// so that the result can be fed into `css::parse`.
+pub fn extract_stylesheets(node: &Node) -> String {
+    node.find_all(|node| match &node.node_type {
+        NodeType::Element(e) => e.tag_name.eq_ignore_ascii_case("style"),
+        _ => false,
+    })
+    .iter()
+    .map(|node| node.inner_text())
+    .collect::<Vec<_>>()
+    .join("\n")
+}
+
 #[cfg(test)]
 mod tests {
     use crate::dom::Text;
@@ -170,7 +678,33 @@ mod tests {
         assert_eq!(
             attribute().parse("test = \"foobar\""),
             Ok((("test".to_string(), "foobar".to_string()), ""))
-        )
+        );
+
+        assert_eq!(
+            attribute().parse("required=\"\""),
+            Ok((("required".to_string(), "".to_string()), ""))
+        );
+        assert_eq!(
+            attribute().parse("required"),
+            Ok((("required".to_string(), "".to_string()), ""))
+        );
+
+        assert_eq!(
+            attribute().parse(r#"title="say &quot;hi&quot;""#),
+            Ok((("title".to_string(), r#"say "hi""#.to_string()), ""))
+        );
+        assert_eq!(
+            attribute().parse(r#"title="a \" b""#),
+            Ok((("title".to_string(), r#"a " b"#.to_string()), ""))
+        );
+
+        assert_eq!(
+            attribute().parse(r#"data-user_id2="42""#),
+            Ok((("data-user_id2".to_string(), "42".to_string()), ""))
+        );
+
+        let html = r#"<p title="say &quot;hi&quot;">hello</p>"#;
+        assert_eq!(parse_raw(html).unwrap()[0].outer_html(), html);
     }
 
     #[test]
@@ -183,7 +717,21 @@ mod tests {
             Ok((expected_map, ""))
         );
 
-        assert_eq!(attributes().parse(""), Ok((AttrMap::new(), "")))
+        assert_eq!(attributes().parse(""), Ok((AttrMap::new(), "")));
+
+        let mut expected_map = AttrMap::new();
+        expected_map.insert("class".to_string(), "inline".to_string());
+        assert_eq!(
+            attributes().parse("class=\" inline \""),
+            Ok((expected_map, ""))
+        );
+    }
+
+    #[test]
+    fn test_trimmed_class_matches() {
+        let node = parse(r#"<p class="  inline	">hello</p>"#).unwrap();
+        let stylesheet = crate::css::parse(".inline { display: inline; }").unwrap();
+        assert!(stylesheet.rules[0].matches(&node));
     }
 
     #[test]
@@ -205,7 +753,6 @@ mod tests {
         {
             let mut attributes = AttrMap::new();
             attributes.insert("id".to_string(), "test".to_string());
-            // TODO:
             assert_eq!(
                 open_tag().parse("<p id=\"test\"  >"),
                 Ok((("p".to_string(), attributes), ""))
@@ -221,7 +768,15 @@ mod tests {
         }
 
         {
-            assert!(open_tag().parse("<p id>").is_err());
+            assert!(open_tag().parse("<p id=>").is_err());
+        }
+
+        {
+            let result = open_tag().parse("<div\n  id=\"x\"\r\n\tclass=\"y\"\n>");
+            let mut attributes = AttrMap::new();
+            attributes.insert("id".to_string(), "x".to_string());
+            attributes.insert("class".to_string(), "y".to_string());
+            assert_eq!(result, Ok((("div".to_string(), attributes), "")));
         }
     }
 
@@ -232,15 +787,29 @@ mod tests {
         assert_eq!(result, Ok(("p".to_string(), "")))
     }
 
+    /// `element` consumes an element, e.g. `<p>hello</p>`.
+    fn element<'a, Input>(
+        state: &'a ParseState,
+    ) -> impl combine::Parser<Input, Output = Box<Node>> + 'a
+    where
+        Input: Stream<Token = char> + 'a,
+        Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+    {
+        combine::parser(move |input: &mut Input| {
+            let (tag, _) = element_open_tag(input, state)?;
+            parse_children(input, state, tag)
+        })
+    }
+
     #[test]
     fn test_parse_element() {
         assert_eq!(
-            element().parse("<p></p>"),
+            element(&ParseState::default()).parse("<p></p>"),
             Ok((Element::new("p".to_string(), AttrMap::new(), vec![]), ""))
         );
 
         assert_eq!(
-            element().parse("<p>hello world</p>"),
+            element(&ParseState::default()).parse("<p>hello world</p>"),
             Ok((
                 Element::new(
                     "p".to_string(),
@@ -252,7 +821,7 @@ mod tests {
         );
 
         assert_eq!(
-            element().parse("<div>  <p>hello world</p>\n </div>"),
+            element(&ParseState::default()).parse("<div>  <p>hello world</p>\n </div>"),
             Ok((
                 Element::new(
                     "div".to_string(),
@@ -267,22 +836,251 @@ mod tests {
             ))
         );
 
-        assert!(element().parse("<p>hello world</div>").is_err());
+        assert!(element(&ParseState::default())
+            .parse("<p>hello world</div>")
+            .is_err());
+    }
+
+    #[test]
+    fn test_parse_lenient() {
+        let lenient = ParseOptions {
+            lenient: true,
+            ..Default::default()
+        };
+        assert!(parse("<p>a<b>bold</p>").is_err());
+        assert_eq!(
+            parse_with("<p>a<b>bold</p>", &lenient)
+                .unwrap()
+                .outer_html(),
+            "<p>a<b>bold</b></p>"
+        );
+        // a stray close tag is ignored and an unclosed element ends at the end of input
+        assert_eq!(
+            parse_with("<div><p>a</b>b</p><p>c</div>", &lenient)
+                .unwrap()
+                .outer_html(),
+            "<div><p>ab</p><p>c</p></div>"
+        );
+        assert_eq!(
+            parse_with("</i><p>a", &lenient).unwrap().outer_html(),
+            "<p>a</p>"
+        );
+        assert!(parse("<p>a</b></p>").is_err());
+        // a void element does not take the following nodes as its children
+        assert_eq!(
+            *parse_with("<p>a<br>b</p>", &lenient).unwrap(),
+            *Element::new(
+                "p".to_string(),
+                AttrMap::new(),
+                vec![
+                    Text::new("a".to_string()),
+                    Element::new("br".to_string(), AttrMap::new(), vec![]),
+                    Text::new("b".to_string()),
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn test_parse_doctype() {
+        assert_eq!(
+            parse("<!DOCTYPE html><body><p>x</p></body>").unwrap(),
+            Element::new(
+                "body".to_string(),
+                AttrMap::new(),
+                vec![Element::new(
+                    "p".to_string(),
+                    AttrMap::new(),
+                    vec![Text::new("x".to_string())]
+                )]
+            )
+        );
+        assert_eq!(
+            parse("\n<!doctype html>\n<p>x</p>").unwrap(),
+            parse("<p>x</p>").unwrap()
+        );
     }
 
     #[test]
     fn test_parse_text() {
         {
             assert_eq!(
-                text().parse("Hello World"),
+                text(false, false).parse("Hello World"),
                 Ok((Text::new("Hello World".to_string()), ""))
             );
         }
         {
             assert_eq!(
-                text().parse("Hello World<"),
+                text(false, false).parse("Hello World<"),
                 Ok((Text::new("Hello World".to_string()), "<"))
             );
         }
+        {
+            assert_eq!(
+                text(false, false).parse("  hello   \n\t world  "),
+                Ok((Text::new("hello world".to_string()), ""))
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_preformatted() {
+        let pre = parse("<pre>a   b\n c</pre>").unwrap();
+        assert_eq!(pre.inner_text(), "a   b\n c");
+        let pre = parse("<div> <pre> <b>a</b>\n </pre> </div>").unwrap();
+        assert_eq!(pre.outer_html(), "<div><pre> <b>a</b>\n </pre></div>");
+
+        // a raw text element keeps its contents as they are even if they look like tags
+        let script = parse("<script>// c\nvar a = 1 < 2;\n</script>").unwrap();
+        assert_eq!(script.inner_text(), "// c\nvar a = 1 < 2;\n");
+        let style = parse("<style>\n p  { display: block; }\n</style>").unwrap();
+        assert_eq!(style.inner_text(), "\n p  { display: block; }\n");
+        assert_eq!(parse("<script></script>").unwrap().children, vec![]);
+
+        // the tag names are compared ASCII case-insensitively
+        let pre = parse("<PRE>a   b\n c</PRE>").unwrap();
+        assert_eq!(pre.inner_text(), "a   b\n c");
+        let script = parse("<SCRIPT>if (a <b) {}</SCRIPT>").unwrap();
+        assert_eq!(script.inner_text(), "if (a <b) {}");
+        let style = parse("<Style>\n p  { display: block; }\n</Style>").unwrap();
+        assert_eq!(style.inner_text(), "\n p  { display: block; }\n");
+    }
+
+    #[test]
+    fn test_streaming_parser() {
+        let raw = r#"<body><p id="result" class="inline">hello</p><div><p>world</p></div></body>"#;
+        let mut parser = Parser::new();
+        // split inside a tag name, an attribute, a text and a close tag
+        for chunk in [
+            "<bo",
+            r#"dy><p id="res"#,
+            r#"ult" class="inline">hel"#,
+            "lo</p><div><p>world</",
+            "p></div></body>",
+        ] {
+            parser.feed(chunk);
+        }
+        assert_eq!(parser.finish().unwrap(), parse(raw).unwrap());
+
+        let mut parser = Parser::new();
+        parser.feed("<body><p>unclosed");
+        assert!(parser.finish().is_err());
+    }
+
+    #[test]
+    fn test_extract_stylesheets() {
+        let node = parse(
+            "<html><head><style>p{display:block}</style></head><body><p>hello</p><style>.inline{display:inline}</style></body></html>",
+        )
+        .unwrap();
+        let css = extract_stylesheets(&node);
+        assert_eq!(css, "p{display:block}\n.inline{display:inline}");
+        assert_eq!(crate::css::parse(&css).unwrap().rules.len(), 2);
+
+        let node = parse(
+            "<html><head><style>\n  p { display: block; }\n</style><STYLE>div{display:none}</STYLE></head></html>",
+        )
+        .unwrap();
+        let css = extract_stylesheets(&node);
+        assert_eq!(css, "\n  p { display: block; }\n\ndiv{display:none}");
+        assert_eq!(crate::css::parse(&css).unwrap().rules.len(), 2);
+    }
+
+    #[test]
+    fn test_parse_void_element() {
+        let br = || Element::new("br".to_string(), AttrMap::new(), vec![]);
+        assert_eq!(
+            parse("<p>hello<br>world<br></br></p>").unwrap(),
+            Element::new(
+                "p".to_string(),
+                AttrMap::new(),
+                vec![
+                    Text::new("hello".to_string()),
+                    br(),
+                    Text::new("world".to_string()),
+                    br(),
+                ]
+            )
+        );
+        assert!(parse("<p>hello<span>world</p>").is_err());
+
+        let node =
+            parse(r#"<head><meta charset="utf-8"><link rel="stylesheet" href="a.css"></head>"#)
+                .unwrap();
+        assert_eq!(
+            node.debug_tree(0),
+            "- Element(head) []\n  - Element(meta) [charset=\"utf-8\"]\n  - Element(link) [rel=\"stylesheet\" href=\"a.css\"]\n"
+        );
+        assert_eq!(
+            parse(r#"<p><img src="a.png" alt="a"><hr><INPUT type="text"></p>"#)
+                .unwrap()
+                .children
+                .len(),
+            3
+        );
+    }
+
+    #[test]
+    fn test_parse_fragment() {
+        let nodes = parse_fragment("<p>hello</p><p>world</p>").unwrap();
+        assert_eq!(
+            nodes,
+            vec![
+                Element::new(
+                    "p".to_string(),
+                    AttrMap::new(),
+                    vec![Text::new("hello".to_string())]
+                ),
+                Element::new(
+                    "p".to_string(),
+                    AttrMap::new(),
+                    vec![Text::new("world".to_string())]
+                ),
+            ]
+        );
+        assert!(nodes
+            .iter()
+            .all(|node| !matches!(&node.node_type, NodeType::Element(e) if e.tag_name == "html")));
+    }
+
+    #[test]
+    fn test_parse_error_position() {
+        // the position is where the error is, not where the outermost node starts
+        let err = parse("<div>\n  <p>hello</div>").unwrap_err();
+        assert!(err.to_string().starts_with("2:11: "), "{}", err);
+
+        let err = parse_raw("<p>hello</p>\n<p>world").unwrap_err();
+        assert!(err.to_string().starts_with("2:9: "), "{}", err);
+
+        let err = parse("<body>\n<p id=>hello</p></body>").unwrap_err();
+        assert!(err.to_string().starts_with("2:7: "), "{}", err);
+    }
+
+    #[test]
+    fn test_max_depth() {
+        let nested = |depth: usize| format!("{}{}", "<div>".repeat(depth), "</div>".repeat(depth));
+        assert_eq!(parse(&nested(40)).unwrap().max_depth(), 40);
+        assert_eq!(
+            parse(&nested(DEFAULT_MAX_DEPTH)).unwrap().max_depth(),
+            DEFAULT_MAX_DEPTH
+        );
+        let err = parse(&nested(DEFAULT_MAX_DEPTH + 1)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "elements are nested deeper than {} levels",
+                DEFAULT_MAX_DEPTH
+            )
+        );
+        // a document far deeper than the limit is rejected without overflowing the stack
+        assert!(parse(&nested(100_000)).is_err());
+
+        let options = ParseOptions {
+            max_depth: 2,
+            lenient: true,
+            ..Default::default()
+        };
+        assert!(parse_with("<div><p>a</p></div>", &options).is_ok());
+        assert!(parse_with("<div><p><span>a</span></p></div>", &options).is_err());
     }
 }
@@ -1,11 +1,49 @@
 use super::{dom::NodeType, style::StyledNode};
-use crate::style::{Display, PropertyMap};
+use crate::css::Stylesheet;
+use crate::dom::{Node, Text};
+use crate::style::{
+    to_styled_node, to_styled_node_with_viewport, validate, Display, PropertyMap, StyleError,
+};
+use crate::util::Point;
 use core::fmt;
+use serde::Serialize;
+use serde_json::json;
+use std::{borrow::Cow, sync::LazyLock};
+
+/// `LIST_MARKER` is the text painted in front of the contents of a `display: list-item` box.
+static LIST_MARKER: LazyLock<NodeType> = LazyLock::new(|| {
+    NodeType::Text(Text {
+        data: "• ".to_string(),
+    })
+});
+
+/// `Dimensions` is the position (top-left corner) and the size of a box, computed by the layout pass.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Dimensions {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl Dimensions {
+    pub fn contains(&self, point: &Point) -> bool {
+        self.x <= point.x
+            && point.x < self.x + self.width
+            && self.y <= point.y
+            && point.y < self.y + self.height
+    }
+}
 
 #[derive(Debug, PartialEq)]
 pub struct LayoutBox<'a> {
     pub box_type: BoxType<'a>,
     pub children: Vec<LayoutBox<'a>>,
+
+    pub dimensions: Dimensions,
+    /// `content_size` is the width and the height of the contents cached by the layout pass.
+    /// It is `None` until measured, so a rebuilt tree never reads a stale size.
+    pub content_size: Option<(f64, f64)>,
 }
 
 impl<'a> LayoutBox<'a> {
@@ -13,35 +51,78 @@ impl<'a> LayoutBox<'a> {
         Self {
             box_type: BoxType::AnonymousBox,
             children: vec![],
+            dimensions: Dimensions::default(),
+            content_size: None,
+        }
+    }
+
+    fn list_marker() -> Self {
+        Self {
+            box_type: BoxType::InlineBox(BoxProps {
+                node_type: Cow::Borrowed(&LIST_MARKER),
+                properties: PropertyMap::new(),
+            }),
+            children: vec![],
+            dimensions: Dimensions::default(),
+            content_size: None,
         }
     }
 
     pub fn new(snode: StyledNode<'a>) -> Self {
+        let display = snode.display();
+        if display == Display::None {
+            // `display: none` generates no box. The empty anonymous box returned here is dropped by the parent.
+            return LayoutBox::anonymous_block();
+        }
+
         let mut root = Self {
-            box_type: match snode.display() {
+            box_type: match display {
                 Display::Inline => BoxType::InlineBox(BoxProps::from(&snode)),
-                Display::Block => BoxType::BlockBox(BoxProps::from(&snode)),
+                Display::Block | Display::ListItem => BoxType::BlockBox(BoxProps::from(&snode)),
+                Display::InlineBlock => BoxType::InlineBlockBox(BoxProps::from(&snode)),
+                // `display: none` has returned above
                 Display::None => unreachable!(),
             },
             children: vec![],
+            dimensions: Dimensions::default(),
+            content_size: None,
         };
 
+        if display == Display::ListItem {
+            root.get_inline_container()
+                .children
+                .push(LayoutBox::list_marker());
+        }
+
         for child in snode.children {
             let child = LayoutBox::new(child);
             match child.box_type {
                 BoxType::BlockBox(_) => root.children.push(child),
-                BoxType::InlineBox(_) => root.get_inline_container().children.push(child),
+                BoxType::InlineBox(_) | BoxType::InlineBlockBox(_) => {
+                    root.get_inline_container().children.push(child)
+                }
                 BoxType::AnonymousBox => {}
             }
         }
 
+        // a block container whose children are all inline holds them directly without an anonymous wrapper
+        if matches!(
+            root.box_type,
+            BoxType::BlockBox(_) | BoxType::InlineBlockBox(_)
+        ) && root.children.len() == 1
+            && root.children[0].box_type == BoxType::AnonymousBox
+        {
+            root.children = root.children.pop().unwrap().children;
+        }
+
         root
     }
 
     fn get_inline_container(&mut self) -> &mut LayoutBox<'a> {
         match self.box_type {
             BoxType::InlineBox(_) | BoxType::AnonymousBox => self,
-            BoxType::BlockBox(_) => {
+            // an inline-block box lays out its contents like a block box
+            BoxType::BlockBox(_) | BoxType::InlineBlockBox(_) => {
                 if self
                     .children
                     .last()
@@ -55,41 +136,95 @@ impl<'a> LayoutBox<'a> {
         }
     }
 
+    /// `hit_test` returns the innermost box containing `point`.
+    /// It relies on `dimensions` precomputed by the layout pass.
+    pub fn hit_test(&self, point: &Point) -> Option<&LayoutBox<'a>> {
+        if !self.dimensions.contains(point) {
+            return None;
+        }
+        // later siblings are painted over earlier ones
+        self.children
+            .iter()
+            .rev()
+            .find_map(|child| child.hit_test(point))
+            .or(Some(self))
+    }
+
     pub fn debug(&self, nest: usize) -> String {
+        self.debug_with(nest, false)
+    }
+
+    /// `debug_layout` is `debug` followed by the position and the size of each box, e.g. `(0, 16) 40x16`.
+    pub fn debug_layout(&self, nest: usize) -> String {
+        self.debug_with(nest, true)
+    }
+
+    fn debug_with(&self, nest: usize, dimensions: bool) -> String {
         let pad = " ".repeat(nest * 2);
         let mut s = match &self.box_type {
             BoxType::BlockBox(p) => format!("{}- BlockBox {}", pad, p),
             BoxType::InlineBox(p) => format!("{}- InlineBox {}", pad, p),
+            BoxType::InlineBlockBox(p) => format!("{}- InlineBlockBox {}", pad, p),
             BoxType::AnonymousBox => format!("{}- AnonymousBox", pad),
         };
+        if dimensions {
+            let d = &self.dimensions;
+            s += &format!(" ({}, {}) {}x{}", d.x, d.y, d.width, d.height);
+        }
         s += "\n";
         for child in &self.children {
-            s += child.debug(nest + 1).as_str();
+            s += child.debug_with(nest + 1, dimensions).as_str();
         }
         s
     }
+
+    /// `to_json` is a machine-readable counterpart of `debug`.
+    /// Boxes with a node have either `tag` or `text`, and `properties`.
+    pub fn to_json(&self) -> serde_json::Value {
+        let box_type = match &self.box_type {
+            BoxType::BlockBox(_) => "BlockBox",
+            BoxType::InlineBox(_) => "InlineBox",
+            BoxType::InlineBlockBox(_) => "InlineBlockBox",
+            BoxType::AnonymousBox => "AnonymousBox",
+        };
+        let mut value = json!({
+            "type": box_type,
+            "dimensions": self.dimensions,
+            "children": self.children.iter().map(|child| child.to_json()).collect::<Vec<_>>(),
+        });
+        if let Some(props) = self.box_type.get_props() {
+            let (key, data) = match &*props.node_type {
+                NodeType::Element(e) => ("tag", e.tag_name.as_str()),
+                NodeType::Text(t) => ("text", t.data.as_str()),
+                NodeType::Comment(c) => ("comment", c.as_str()),
+            };
+            value[key] = json!(data);
+            value["properties"] = json!(props.properties);
+        }
+        value
+    }
 }
 
 #[derive(Debug, PartialEq)]
 pub enum BoxType<'a> {
     BlockBox(BoxProps<'a>),
     InlineBox(BoxProps<'a>),
+    /// `InlineBlockBox` flows inline like `InlineBox` but sizes its contents like `BlockBox`.
+    InlineBlockBox(BoxProps<'a>),
     AnonymousBox,
 }
 
 impl BoxType<'_> {
-    pub fn get_props(&self) -> Option<&BoxProps> {
+    pub fn get_props(&self) -> Option<&BoxProps<'_>> {
         match self {
-            BoxType::BlockBox(p) | BoxType::InlineBox(p) => Some(p),
+            BoxType::BlockBox(p) | BoxType::InlineBox(p) | BoxType::InlineBlockBox(p) => Some(p),
             BoxType::AnonymousBox => None,
         }
     }
 
+    /// NOTE: `AnonymousBox` is not inline because it wraps inline boxes into a block-level box.
     pub fn is_inline(&self) -> bool {
-        match self {
-            BoxType::InlineBox(_) | BoxType::AnonymousBox => true,
-            _ => false,
-        }
+        matches!(self, BoxType::InlineBox(_) | BoxType::InlineBlockBox(_))
     }
 }
 
@@ -98,6 +233,7 @@ impl fmt::Display for BoxType<'_> {
         match self {
             BoxType::BlockBox(p) => write!(f, "BlockBox {}", p),
             BoxType::InlineBox(p) => write!(f, "InlineBox {}", p),
+            BoxType::InlineBlockBox(p) => write!(f, "InlineBlockBox {}", p),
             BoxType::AnonymousBox => write!(f, "AnonymousBox"),
         }
     }
@@ -105,15 +241,16 @@ impl fmt::Display for BoxType<'_> {
 
 #[derive(Debug, PartialEq)]
 pub struct BoxProps<'a> {
-    pub node_type: &'a NodeType,
+    pub node_type: Cow<'a, NodeType>,
     pub properties: PropertyMap,
 }
 
 impl fmt::Display for BoxProps<'_> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self.node_type {
+        match &*self.node_type {
             NodeType::Element(e) => write!(f, "tag: {}", e.tag_name),
             NodeType::Text(t) => write!(f, "text: {}", t.data),
+            NodeType::Comment(c) => write!(f, "comment: {}", c),
         }
     }
 }
@@ -121,12 +258,30 @@ impl fmt::Display for BoxProps<'_> {
 impl<'a> BoxProps<'a> {
     fn from(snode: &StyledNode<'a>) -> Self {
         Self {
-            node_type: snode.node_type,
+            node_type: snode.node_type.clone(),
             properties: snode.properties.clone(),
         }
     }
 }
 
+/// `build_layout_tree` styles `node` with `stylesheet` and builds its box tree, which is not laid out yet.
+/// The `@media` rules are applied for `viewport_width` if it is given, and skipped otherwise.
+/// The warnings of `stylesheet` are logged.
+pub fn build_layout_tree<'a>(
+    node: &'a Node,
+    stylesheet: &Stylesheet,
+    viewport_width: Option<f64>,
+) -> Result<LayoutBox<'a>, StyleError> {
+    for warning in validate(stylesheet) {
+        log::warn!("{}", warning);
+    }
+    let styled_node = match viewport_width {
+        Some(viewport_width) => to_styled_node_with_viewport(node, stylesheet, viewport_width)?,
+        None => to_styled_node(node, stylesheet)?,
+    };
+    Ok(LayoutBox::new(styled_node))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -149,37 +304,37 @@ mod tests {
             attributes: [].iter().cloned().collect(),
         });
         let snode = StyledNode {
-            node_type: &node,
+            node_type: Cow::Borrowed(&node),
             properties: block.iter().cloned().collect(),
             children: vec![
                 StyledNode {
-                    node_type: &node,
+                    node_type: Cow::Borrowed(&node),
                     properties: block.iter().cloned().collect(),
                     children: vec![],
                 },
                 StyledNode {
-                    node_type: &node,
+                    node_type: Cow::Borrowed(&node),
                     properties: inline.iter().cloned().collect(),
                     children: vec![
                         StyledNode {
-                            node_type: &node,
+                            node_type: Cow::Borrowed(&node),
                             properties: block.iter().cloned().collect(),
                             children: vec![],
                         },
                         StyledNode {
-                            node_type: &node,
+                            node_type: Cow::Borrowed(&node),
                             properties: block.iter().cloned().collect(),
                             children: vec![],
                         },
                     ],
                 },
                 StyledNode {
-                    node_type: &node,
+                    node_type: Cow::Borrowed(&node),
                     properties: inline.iter().cloned().collect(),
                     children: vec![],
                 },
                 StyledNode {
-                    node_type: &node,
+                    node_type: Cow::Borrowed(&node),
                     properties: block.iter().cloned().collect(),
                     children: vec![],
                 },
@@ -190,60 +345,326 @@ mod tests {
             LayoutBox::new(snode),
             LayoutBox {
                 box_type: BoxType::BlockBox(BoxProps {
-                    node_type: &node,
+                    node_type: Cow::Borrowed(&node),
                     properties: block.iter().cloned().collect(),
                 }),
                 children: vec![
                     LayoutBox {
                         box_type: BoxType::BlockBox(BoxProps {
-                            node_type: &node,
+                            node_type: Cow::Borrowed(&node),
                             properties: block.iter().cloned().collect(),
                         }),
                         children: vec![],
+                        dimensions: Dimensions::default(),
+                        content_size: None,
                     },
                     LayoutBox {
                         box_type: BoxType::AnonymousBox,
                         children: vec![
                             LayoutBox {
                                 box_type: BoxType::InlineBox(BoxProps {
-                                    node_type: &node,
+                                    node_type: Cow::Borrowed(&node),
                                     properties: inline.iter().cloned().collect(),
                                 }),
                                 children: vec![
                                     LayoutBox {
                                         box_type: BoxType::BlockBox(BoxProps {
-                                            node_type: &node,
+                                            node_type: Cow::Borrowed(&node),
                                             properties: block.iter().cloned().collect(),
                                         }),
                                         children: vec![],
+                                        dimensions: Dimensions::default(),
+                                        content_size: None,
                                     },
                                     LayoutBox {
                                         box_type: BoxType::BlockBox(BoxProps {
-                                            node_type: &node,
+                                            node_type: Cow::Borrowed(&node),
                                             properties: block.iter().cloned().collect(),
                                         }),
                                         children: vec![],
+                                        dimensions: Dimensions::default(),
+                                        content_size: None,
                                     }
                                 ],
+                                dimensions: Dimensions::default(),
+                                content_size: None,
                             },
                             LayoutBox {
                                 box_type: BoxType::InlineBox(BoxProps {
-                                    node_type: &node,
+                                    node_type: Cow::Borrowed(&node),
                                     properties: inline.iter().cloned().collect(),
                                 }),
                                 children: vec![],
+                                dimensions: Dimensions::default(),
+                                content_size: None,
                             }
-                        ]
+                        ],
+                        dimensions: Dimensions::default(),
+                        content_size: None,
                     },
                     LayoutBox {
                         box_type: BoxType::BlockBox(BoxProps {
-                            node_type: &node,
+                            node_type: Cow::Borrowed(&node),
                             properties: block.iter().cloned().collect(),
                         }),
                         children: vec![],
+                        dimensions: Dimensions::default(),
+                        content_size: None,
                     }
                 ],
+                dimensions: Dimensions::default(),
+                content_size: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_hit_test() {
+        let node = NodeType::Element(Element {
+            tag_name: "div".into(),
+            attributes: [].iter().cloned().collect(),
+        });
+        fn block_box<'a>(
+            node_type: &'a NodeType,
+            dimensions: Dimensions,
+            children: Vec<LayoutBox<'a>>,
+        ) -> LayoutBox<'a> {
+            LayoutBox {
+                box_type: BoxType::BlockBox(BoxProps {
+                    node_type: Cow::Borrowed(node_type),
+                    properties: PropertyMap::new(),
+                }),
+                children,
+                dimensions,
+                content_size: None,
             }
+        }
+        let dimensions = |x: f64, y: f64, width: f64, height: f64| Dimensions {
+            x,
+            y,
+            width,
+            height,
+        };
+
+        // +--root(0,0,100,100)----------+
+        // | +--outer(10,10,50,50)--+    |
+        // | | +--inner(20,20,10,10)|    |
+        // | +----------------------+    |
+        // +-----------------------------+
+        let root = block_box(
+            &node,
+            dimensions(0., 0., 100., 100.),
+            vec![block_box(
+                &node,
+                dimensions(10., 10., 50., 50.),
+                vec![block_box(&node, dimensions(20., 20., 10., 10.), vec![])],
+            )],
+        );
+        let outer = &root.children[0];
+        let inner = &outer.children[0];
+
+        assert_eq!(root.hit_test(&Point { x: 25., y: 25. }), Some(inner));
+        assert_eq!(root.hit_test(&Point { x: 15., y: 15. }), Some(outer));
+        assert_eq!(root.hit_test(&Point { x: 90., y: 90. }), Some(&root));
+        assert_eq!(root.hit_test(&Point { x: 150., y: 10. }), None);
+    }
+
+    #[test]
+    fn test_to_json() {
+        let node = crate::html::parse(r#"<div><span>a</span></div>"#).unwrap();
+        let stylesheet = crate::css::parse("div { display: block; }").unwrap();
+        let snode = crate::style::to_styled_node(&node, &stylesheet).unwrap();
+        let dimensions = json!({"x": 0.0, "y": 0.0, "width": 0.0, "height": 0.0});
+
+        assert_eq!(
+            LayoutBox::new(snode).to_json(),
+            json!({
+                "type": "BlockBox",
+                "tag": "div",
+                "properties": {"display": {"Keyword": "block"}},
+                "dimensions": dimensions,
+                "children": [{
+                    "type": "InlineBox",
+                    "tag": "span",
+                    "properties": {},
+                    "dimensions": dimensions,
+                    "children": [{
+                        "type": "InlineBox",
+                        "text": "a",
+                        "properties": {},
+                        "dimensions": dimensions,
+                        "children": [],
+                    }],
+                }],
+            })
+        );
+    }
+
+    #[test]
+    fn test_to_layout_box_inline_block() {
+        let node = crate::html::parse(
+            r#"<div><span class="ib">a</span><span class="ib">b</span><p>c</p></div>"#,
+        )
+        .unwrap();
+        let stylesheet =
+            crate::css::parse("div, p { display: block; } .ib { display: inline-block; }").unwrap();
+        let snode = crate::style::to_styled_node(&node, &stylesheet).unwrap();
+
+        // inline-block boxes share a line box, while their contents are wrapped like a block box
+        assert_eq!(
+            LayoutBox::new(snode).debug(0),
+            r#"- BlockBox tag: div
+  - AnonymousBox
+    - InlineBlockBox tag: span
+      - InlineBox text: a
+    - InlineBlockBox tag: span
+      - InlineBox text: b
+  - BlockBox tag: p
+    - InlineBox text: c
+"#
+        );
+    }
+
+    #[test]
+    fn test_to_layout_box_anonymous_collapse() {
+        let stylesheet = crate::css::parse("div, p { display: block; }").unwrap();
+        let layout = |html: &str| {
+            let node = crate::html::parse(html).unwrap();
+            LayoutBox::new(crate::style::to_styled_node(&node, &stylesheet).unwrap()).debug(0)
+        };
+
+        // only inline children: no anonymous box
+        assert_eq!(
+            layout("<div>a<span>b</span></div>"),
+            r#"- BlockBox tag: div
+  - InlineBox text: a
+  - InlineBox tag: span
+    - InlineBox text: b
+"#
+        );
+        // mixed children: inline runs are wrapped
+        assert_eq!(
+            layout("<div>a<p>b</p>c</div>"),
+            r#"- BlockBox tag: div
+  - AnonymousBox
+    - InlineBox text: a
+  - BlockBox tag: p
+    - InlineBox text: b
+  - AnonymousBox
+    - InlineBox text: c
+"#
+        );
+    }
+
+    #[test]
+    fn test_to_layout_box_list_item() {
+        let node = crate::html::parse("<ul><li>a</li><li>b</li></ul>").unwrap();
+        let stylesheet =
+            crate::css::parse("ul { display: block; } li { display: list-item; }").unwrap();
+        let snode = crate::style::to_styled_node(&node, &stylesheet).unwrap();
+
+        assert_eq!(
+            LayoutBox::new(snode).debug(0),
+            [
+                "- BlockBox tag: ul",
+                "  - BlockBox tag: li",
+                "    - InlineBox text: • ",
+                "    - InlineBox text: a",
+                "  - BlockBox tag: li",
+                "    - InlineBox text: • ",
+                "    - InlineBox text: b",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn test_to_layout_box_display_none() {
+        let block = [(
+            "display".to_string(),
+            CSSValue::Keyword("block".to_string()),
+        )];
+        let none = [("display".to_string(), CSSValue::Keyword("none".to_string()))];
+
+        let node = NodeType::Element(Element {
+            tag_name: "div".into(),
+            attributes: [].iter().cloned().collect(),
+        });
+        let snode = StyledNode {
+            node_type: Cow::Borrowed(&node),
+            properties: block.iter().cloned().collect(),
+            children: vec![
+                StyledNode {
+                    node_type: Cow::Borrowed(&node),
+                    properties: none.iter().cloned().collect(),
+                    children: vec![StyledNode {
+                        node_type: Cow::Borrowed(&node),
+                        properties: block.iter().cloned().collect(),
+                        children: vec![],
+                    }],
+                },
+                StyledNode {
+                    node_type: Cow::Borrowed(&node),
+                    properties: block.iter().cloned().collect(),
+                    children: vec![],
+                },
+            ],
+        };
+
+        assert_eq!(
+            LayoutBox::new(snode),
+            LayoutBox {
+                box_type: BoxType::BlockBox(BoxProps {
+                    node_type: Cow::Borrowed(&node),
+                    properties: block.iter().cloned().collect(),
+                }),
+                children: vec![LayoutBox {
+                    box_type: BoxType::BlockBox(BoxProps {
+                        node_type: Cow::Borrowed(&node),
+                        properties: block.iter().cloned().collect(),
+                    }),
+                    children: vec![],
+                    dimensions: Dimensions::default(),
+                    content_size: None,
+                }],
+                dimensions: Dimensions::default(),
+                content_size: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_to_layout_box_generated_content() {
+        let node = crate::html::parse(r#"<p>hello <b>world</b></p>"#).unwrap();
+        let stylesheet =
+            crate::css::parse(r#"p { display: block; } p::after { content: "!"; }"#).unwrap();
+        let snode = crate::style::to_styled_node(&node, &stylesheet).unwrap();
+        let layout_box = LayoutBox::new(snode);
+        assert_eq!(
+            layout_box.debug(0),
+            "- BlockBox tag: p\n  - InlineBox text: hello\n  - InlineBox tag: b\n    - InlineBox text: world\n  - InlineBox text: !\n"
+        );
+    }
+
+    #[test]
+    fn test_build_layout_tree() {
+        let node = crate::html::parse(r#"<div><p>hello</p></div>"#).unwrap();
+        let stylesheet = crate::css::parse(
+            "div, p { display: block; } @media (max-width: 600px) { p { display: none; } }",
+        )
+        .unwrap();
+        assert_eq!(
+            build_layout_tree(&node, &stylesheet, None)
+                .unwrap()
+                .debug(0),
+            "- BlockBox tag: div\n  - BlockBox tag: p\n    - InlineBox text: hello\n"
+        );
+        assert_eq!(
+            build_layout_tree(&node, &stylesheet, Some(400.))
+                .unwrap()
+                .debug(0),
+            "- BlockBox tag: div\n"
         );
     }
 }
@@ -1,18 +1,24 @@
-use combine::error::ParseError;
-use combine::parser::char::{newline, space};
-use combine::{many, Parser, Stream};
+use combine::easy;
+use combine::stream::position::SourcePosition;
 
+pub mod color;
 pub mod css;
 pub mod dom;
 pub mod html;
 pub mod layout;
 pub mod style;
+pub mod util;
 
-fn blank<Input>() -> impl Parser<Input, Output = ()>
-where
-    Input: Stream<Token = char>,
-    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
-{
-    // 空白と改行を読み飛ばす
-    many::<String, _, _>(space().or(newline())).map(|_| ())
+pub use util::{blank, Point};
+
+/// `parse_error` converts an error of a parser run over a positioned stream
+/// into an error whose message starts with `line:column`.
+fn parse_error(e: easy::Errors<char, &str, SourcePosition>) -> anyhow::Error {
+    let messages = e
+        .errors
+        .iter()
+        .map(|e| e.to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    anyhow::anyhow!("{}:{}: {}", e.position.line, e.position.column, messages)
 }
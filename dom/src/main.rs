@@ -0,0 +1,112 @@
+use anyhow::Result;
+use dom::{css, html, layout::build_layout_tree};
+use std::{fs, path::PathBuf};
+use structopt::StructOpt;
+
+const SAMPLE_HTML: &str = r#"<body>
+    <p>hello</p>
+    <p class="inline">world</p>
+    <p class="inline">:)</p>
+    <div class="none"><p>this should not be shown</p></div>
+</body>"#;
+
+const SAMPLE_CSS: &str = r#"body, p, div { display: block; }
+.inline { display: inline; }
+.none { display: none; }"#;
+
+/// Prints the layout tree of a document.
+#[derive(Debug, StructOpt)]
+struct Opt {
+    /// HTML file to render. The built-in sample is used if omitted.
+    #[structopt(long, env = "SMALL_BROWSER_HTML", parse(from_os_str))]
+    html: Option<PathBuf>,
+    /// CSS file to apply. The built-in sample is used if omitted.
+    #[structopt(long, env = "SMALL_BROWSER_CSS", parse(from_os_str))]
+    css: Option<PathBuf>,
+    /// File to write the layout tree to. It is printed to stdout if omitted.
+    #[structopt(short, long, parse(from_os_str))]
+    output: Option<PathBuf>,
+}
+
+fn read_or(path: &Option<PathBuf>, sample: &str) -> Result<String> {
+    match path {
+        Some(path) => Ok(fs::read_to_string(path)?),
+        None => Ok(sample.to_string()),
+    }
+}
+
+/// `layout_tree` parses `html` and `css` and returns the debug view of the box tree.
+/// The `@media` rules are skipped since there is no viewport.
+/// The boxes are not laid out, so the view has no dimensions.
+fn layout_tree(html: &str, css: &str) -> Result<String> {
+    let node = html::parse(html)?;
+    let stylesheet = css::parse(css)?;
+    Ok(build_layout_tree(&node, &stylesheet, None)?.debug(0))
+}
+
+fn run(opt: &Opt) -> Result<()> {
+    let html = read_or(&opt.html, SAMPLE_HTML)?;
+    let css = read_or(&opt.css, SAMPLE_CSS)?;
+    let tree = layout_tree(&html, &css)?;
+    match &opt.output {
+        Some(path) => fs::write(path, tree)?,
+        None => print!("{}", tree),
+    }
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    run(&Opt::from_args())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run() {
+        let dir = std::env::temp_dir().join(format!("small_browser_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let html = dir.join("index.html");
+        let css = dir.join("style.css");
+        let output = dir.join("layout.txt");
+        fs::write(&html, r#"<body><p>hello</p></body>"#).unwrap();
+        fs::write(&css, "body { display: block; }\np { display: none; }\n").unwrap();
+
+        run(&Opt {
+            html: Some(html.clone()),
+            css: None,
+            output: Some(output.clone()),
+        })
+        .unwrap();
+        let tree = fs::read_to_string(&output).unwrap();
+        assert!(tree.contains("tag: body"));
+        assert!(tree.contains("text: hello"));
+
+        // a CSS file ends with a newline
+        run(&Opt {
+            html: Some(html),
+            css: Some(css),
+            output: Some(output.clone()),
+        })
+        .unwrap();
+        let tree = fs::read_to_string(&output).unwrap();
+        assert!(tree.contains("tag: body"));
+        assert!(!tree.contains("text: hello"));
+
+        assert!(run(&Opt {
+            html: Some(dir.join("missing.html")),
+            css: None,
+            output: None,
+        })
+        .is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_layout_tree_sample() {
+        let tree = layout_tree(SAMPLE_HTML, SAMPLE_CSS).unwrap();
+        assert!(tree.contains("text: world"));
+        assert!(!tree.contains("this should not be shown"));
+    }
+}
@@ -1,17 +1,98 @@
 //! This module includes some implementations on node styles.
 
 use crate::{
-    css::{CSSValue, Stylesheet},
-    dom::{Node, NodeType},
+    color::Color,
+    css::{
+        CSSValue, MatchingContext, PseudoElement, Rule, SimpleSelector, Specificity, Stylesheet,
+        Unit,
+    },
+    dom::{Node, NodeType, Text},
 };
-use std::collections::HashMap;
+use serde_json::json;
+use std::{borrow::Cow, collections::HashMap};
+use thiserror::Error;
 
 pub type PropertyMap = HashMap<String, CSSValue>;
 
+/// `INHERITED_PROPERTIES` are the properties whose values are passed down to the children unless they declare their own.
+/// See https://www.w3.org/TR/css-cascade-5/#inheriting
+const INHERITED_PROPERTIES: &[&str] = &[
+    "color",
+    "font-family",
+    "line-height",
+    "text-align",
+    "white-space",
+];
+
+/// `KNOWN_PROPERTIES` are the properties which the engine understands.
+/// A declaration of any other property has no effect.
+const KNOWN_PROPERTIES: &[&str] = &[
+    "all",
+    "background-color",
+    "bottom",
+    "box-sizing",
+    "color",
+    "content",
+    "display",
+    "font-family",
+    "font-size",
+    "left",
+    "line-height",
+    "margin",
+    "margin-bottom",
+    "margin-left",
+    "margin-right",
+    "margin-top",
+    "max-width",
+    "min-width",
+    "opacity",
+    "overflow",
+    "padding",
+    "padding-bottom",
+    "padding-left",
+    "padding-right",
+    "padding-top",
+    "position",
+    "right",
+    "text-align",
+    "text-decoration",
+    "top",
+    "white-space",
+    "width",
+];
+
+/// `initial_value` returns the initial value of a longhand property in `KNOWN_PROPERTIES`, which applies when no value is cascaded.
+/// See https://www.w3.org/TR/css-cascade-5/#initial-values
+fn initial_value(name: &str) -> Option<CSSValue> {
+    let keyword = |k: &str| Some(CSSValue::Keyword(k.to_string()));
+    match name {
+        "background-color" => keyword("transparent"),
+        "bottom" | "left" | "right" | "top" | "width" => keyword("auto"),
+        "box-sizing" => keyword("content-box"),
+        "color" => Some(CSSValue::Color(Color::from_rgb(0, 0, 0))),
+        "content" | "line-height" | "white-space" => keyword("normal"),
+        "display" => keyword("inline"),
+        "font-family" => keyword("serif"),
+        "font-size" => keyword("medium"),
+        "margin-bottom" | "margin-left" | "margin-right" | "margin-top" | "min-width"
+        | "padding-bottom" | "padding-left" | "padding-right" | "padding-top" => {
+            Some(CSSValue::Length(0., Unit::Px))
+        }
+        "max-width" | "text-decoration" => keyword("none"),
+        "opacity" => Some(CSSValue::Number(1.)),
+        "overflow" => keyword("visible"),
+        "position" => keyword("static"),
+        "text-align" => keyword("left"),
+        _ => None,
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum Display {
     Inline,
     Block,
+    InlineBlock,
+    ListItem,
     None,
 }
 
@@ -19,58 +100,433 @@ pub enum Display {
 /// It forms a tree as `Node` does.
 #[derive(Debug, PartialEq)]
 pub struct StyledNode<'a> {
-    pub node_type: &'a NodeType,
+    pub node_type: Cow<'a, NodeType>,
     pub children: Vec<StyledNode<'a>>,
 
     pub properties: PropertyMap,
 }
 
-pub fn to_styled_node<'a>(node: &'a Box<Node>, stylesheet: &Stylesheet) -> Option<StyledNode<'a>> {
-    // The last declaration in document order wins.
+#[derive(Debug, PartialEq, Error)]
+pub enum StyleError {
+    #[error("the root node is not rendered because it has `display: none`")]
+    RootDisplayNone,
+}
+
+/// `StyleWarning` is a problem in a stylesheet which does not stop styling but makes some declarations ineffective.
+#[derive(Debug, PartialEq, Error)]
+pub enum StyleWarning {
+    #[error("unknown property `{name}` is ignored")]
+    UnknownProperty { name: String },
+}
+
+/// `validate` collects the warnings of `stylesheet` in source order.
+pub fn validate(stylesheet: &Stylesheet) -> Vec<StyleWarning> {
+    stylesheet
+        .rules
+        .iter()
+        .flat_map(|rule| &rule.declarations)
+        .filter(|declaration| !KNOWN_PROPERTIES.contains(&declaration.name.as_str()))
+        .map(|declaration| StyleWarning::UnknownProperty {
+            name: declaration.name.clone(),
+        })
+        .collect()
+}
+
+/// `to_styled_node` applies `stylesheet` to `node`.
+/// Rules in `@media` blocks are skipped because the viewport is unknown; use `to_styled_node_with_viewport` to apply them.
+pub fn to_styled_node<'a>(
+    node: &'a Node,
+    stylesheet: &Stylesheet,
+) -> Result<StyledNode<'a>, StyleError> {
+    styled_node(
+        node,
+        &RuleIndex::new(stylesheet),
+        None,
+        &MatchingContext::default(),
+        &PropertyMap::new(),
+    )
+    .ok_or(StyleError::RootDisplayNone)
+}
+
+/// `to_styled_node_with_viewport` applies `stylesheet` to `node`, including the `@media` rules matching `viewport_width`.
+pub fn to_styled_node_with_viewport<'a>(
+    node: &'a Node,
+    stylesheet: &Stylesheet,
+    viewport_width: f64,
+) -> Result<StyledNode<'a>, StyleError> {
+    styled_node(
+        node,
+        &RuleIndex::new(stylesheet),
+        Some(viewport_width),
+        &MatchingContext::default(),
+        &PropertyMap::new(),
+    )
+    .ok_or(StyleError::RootDisplayNone)
+}
+
+/// `BucketKey` is the part of a selector which an element must have for the selector to match.
+#[derive(Debug, PartialEq, Eq, Hash)]
+enum BucketKey {
+    Id(String),
+    Class(String),
+    /// `Tag` is lowercased since tag names are ASCII case-insensitive.
+    Tag(String),
+}
+
+impl BucketKey {
+    /// `of_selector` returns the key of `selector`, or `None` if it may match any node.
+    fn of_selector(selector: &SimpleSelector) -> Option<Self> {
+        match selector {
+            SimpleSelector::IdSelector { id } => Some(BucketKey::Id(id.clone())),
+            SimpleSelector::ClassSelector { class_name } => {
+                Some(BucketKey::Class(class_name.clone()))
+            }
+            SimpleSelector::TypeSelector { tag_name }
+            | SimpleSelector::AttributeSelector { tag_name, .. }
+                if tag_name != "*" =>
+            {
+                Some(BucketKey::Tag(tag_name.to_ascii_lowercase()))
+            }
+            SimpleSelector::PseudoClassSelector { base, .. }
+            | SimpleSelector::PseudoElementSelector { base, .. } => Self::of_selector(base),
+            SimpleSelector::CombinedSelector { subject, .. } => Self::of_selector(subject),
+            _ => None,
+        }
+    }
+
+    /// `of_node` returns the keys that `node` has.
+    fn of_node(node: &Node) -> Vec<Self> {
+        let NodeType::Element(e) = &node.node_type else {
+            return vec![];
+        };
+        let mut keys = vec![BucketKey::Tag(e.tag_name.to_ascii_lowercase())];
+        if let Some(id) = e.id() {
+            keys.push(BucketKey::Id(id.clone()));
+        }
+        if let Some(class) = e.attributes.get("class") {
+            keys.push(BucketKey::Class(class.clone()));
+        }
+        keys
+    }
+}
+
+/// `RuleIndex` buckets the rules of a stylesheet by the id, class or tag name their selectors require,
+/// so that only the candidate rules are tested against each node instead of all the rules.
+pub struct RuleIndex<'s> {
+    rules: &'s [Rule],
+    buckets: HashMap<BucketKey, Vec<usize>>,
+    /// `universal` is the indices of the rules which have a selector that may match any node.
+    universal: Vec<usize>,
+}
+
+impl<'s> RuleIndex<'s> {
+    pub fn new(stylesheet: &'s Stylesheet) -> Self {
+        let mut buckets: HashMap<BucketKey, Vec<usize>> = HashMap::new();
+        let mut universal = vec![];
+        for (i, rule) in stylesheet.rules.iter().enumerate() {
+            for selector in &rule.selectors {
+                let bucket = match BucketKey::of_selector(selector) {
+                    Some(key) => buckets.entry(key).or_default(),
+                    None => &mut universal,
+                };
+                // a rule with several selectors of the same key is put only once
+                if bucket.last() != Some(&i) {
+                    bucket.push(i);
+                }
+            }
+        }
+        RuleIndex {
+            rules: &stylesheet.rules,
+            buckets,
+            universal,
+        }
+    }
+
+    /// `candidates` returns the rules which may match `node` in source order.
+    /// The selectors of the rules still need to be tested.
+    pub fn candidates(&self, node: &Node) -> Vec<&'s Rule> {
+        let mut indices: Vec<usize> = BucketKey::of_node(node)
+            .iter()
+            .filter_map(|key| self.buckets.get(key))
+            .chain([&self.universal])
+            .flatten()
+            .copied()
+            .collect();
+        indices.sort_unstable();
+        indices.dedup();
+        indices.into_iter().map(|i| &self.rules[i]).collect()
+    }
+}
+
+/// `expand_shorthand` expands `margin` and `padding` into the properties of each side
+/// following the 1/2/3/4-value rules (https://www.w3.org/TR/css-box-3/#margin-shorthand).
+/// Other declarations are returned as they are.
+fn expand_shorthand(name: &str, value: &CSSValue) -> Vec<(String, CSSValue)> {
+    if name != "margin" && name != "padding" {
+        return vec![(name.to_string(), value.clone())];
+    }
+    let values = match value {
+        CSSValue::Multiple(values) => values.as_slice(),
+        value => std::slice::from_ref(value),
+    };
+    let [top, right, bottom, left] = match values {
+        [all] => [all, all, all, all],
+        [vertical, horizontal] => [vertical, horizontal, vertical, horizontal],
+        [top, horizontal, bottom] => [top, horizontal, bottom, horizontal],
+        [top, right, bottom, left] => [top, right, bottom, left],
+        // invalid declarations are ignored
+        _ => return vec![],
+    };
+    [
+        ("top", top),
+        ("right", right),
+        ("bottom", bottom),
+        ("left", left),
+    ]
+    .into_iter()
+    .map(|(side, value)| (format!("{}-{}", name, side), value.clone()))
+    .collect()
+}
+
+/// `reset_all` applies `all: value` to `properties`, the properties cascaded so far.
+/// `initial` drops all of them, `inherit` takes all the properties of the parent, `inherited`,
+/// and `unset` takes only the inherited properties of the parent. Other values are ignored.
+/// See https://www.w3.org/TR/css-cascade-5/#all-shorthand
+fn reset_all(properties: &mut PropertyMap, value: &CSSValue, inherited: &PropertyMap) {
+    let CSSValue::Keyword(keyword) = value else {
+        return;
+    };
+    *properties = match keyword.as_str() {
+        "initial" => PropertyMap::new(),
+        "inherit" => inherited.clone(),
+        "unset" => INHERITED_PROPERTIES
+            .iter()
+            .filter_map(|name| Some((name.to_string(), inherited.get(*name)?.clone())))
+            .collect(),
+        _ => return,
+    };
+}
+
+/// `cascade` computes the properties from the declarations of `matched`, the rules and their specificities in source order.
+/// `inherited` is the properties of the parent.
+fn cascade(mut matched: Vec<(Specificity, &Rule)>, inherited: &PropertyMap) -> PropertyMap {
+    let mut properties: PropertyMap = INHERITED_PROPERTIES
+        .iter()
+        .filter_map(|name| Some((name.to_string(), inherited.get(*name)?.clone())))
+        .collect();
+    // The declaration of the highest specificity wins, and the last one in document order wins at equal specificity.
+    // Each matching rule applies once with the specificity of its most specific selector matching the node.
     // see https://www.w3.org/TR/css-cascade-5/#cascade-order
-    let mut properties = PropertyMap::new();
-    for rule in &stylesheet.rules {
-        if rule.matches(node) {
-            for declarations in &rule.declarations {
-                properties.insert(declarations.name.clone(), declarations.value.clone());
+    // the sort is stable, so the rules stay in source order at equal specificity
+    matched.sort_by_key(|(specificity, _)| *specificity);
+    // `!important` declarations win over the normal ones regardless of the specificity
+    for important in [false, true] {
+        for (_, rule) in &matched {
+            for declaration in rule
+                .declarations
+                .iter()
+                .filter(|d| d.important == important)
+            {
+                if declaration.name == "all" {
+                    reset_all(&mut properties, &declaration.value, inherited);
+                    continue;
+                }
+                properties.extend(expand_shorthand(&declaration.name, &declaration.value));
             }
         }
     }
+    // `inherit` takes the value of the parent, and `initial` drops the value so that the initial value is used.
+    // See https://www.w3.org/TR/css-cascade-5/#defaulting-keywords
+    properties
+        .into_iter()
+        .filter_map(|(name, value)| match &value {
+            CSSValue::Keyword(k) if k == "inherit" => {
+                let value = inherited.get(&name)?.clone();
+                Some((name, value))
+            }
+            CSSValue::Keyword(k) if k == "initial" => None,
+            _ => Some((name, value)),
+        })
+        .collect()
+}
+
+/// `generated_content` returns the box of `pseudo_element` of `node` whose properties are `properties`,
+/// if a matching rule gives it a string `content`.
+///
+/// NOTE: only a string is supported as `content`, which is put into a text node.
+/// See https://www.w3.org/TR/css-content-3/#content-property
+fn generated_content<'a>(
+    node: &Node,
+    rules: &[&Rule],
+    context: &MatchingContext,
+    properties: &PropertyMap,
+    pseudo_element: PseudoElement,
+) -> Option<StyledNode<'a>> {
+    if !matches!(node.node_type, NodeType::Element(_)) {
+        return None;
+    }
+    let matched: Vec<_> = rules
+        .iter()
+        .filter_map(|rule| {
+            let specificity = rule.pseudo_element_specificity_in(node, context, pseudo_element)?;
+            Some((specificity, *rule))
+        })
+        .collect();
+    if matched.is_empty() {
+        return None;
+    }
+    // a pseudo-element inherits from its element
+    let properties = cascade(matched, properties);
+    let Some(CSSValue::String(content)) = properties.get("content") else {
+        return None;
+    };
+    if matches!(properties.get("display"), Some(CSSValue::Keyword(k)) if k.eq_ignore_ascii_case("none"))
+    {
+        return None;
+    }
+    Some(StyledNode {
+        node_type: Cow::Owned(NodeType::Text(Text {
+            data: content.clone(),
+        })),
+        children: vec![],
+        properties,
+    })
+}
+
+/// `styled_node` returns `None` if `node` is not rendered.
+/// `inherited` is the properties of the parent.
+fn styled_node<'a>(
+    node: &'a Node,
+    rules: &RuleIndex,
+    viewport_width: Option<f64>,
+    context: &MatchingContext,
+    inherited: &PropertyMap,
+) -> Option<StyledNode<'a>> {
+    let candidates: Vec<_> = rules
+        .candidates(node)
+        .into_iter()
+        .filter(|rule| match &rule.media {
+            Some(media) => viewport_width.is_some_and(|width| media.matches(width)),
+            None => true,
+        })
+        .collect();
+    let matched = candidates
+        .iter()
+        .filter_map(|rule| Some((rule.specificity_in(node, context)?, *rule)))
+        .collect();
+    let properties = cascade(matched, inherited);
 
-    if properties.get("display") == Some(&CSSValue::Keyword("none".to_string())) {
+    if matches!(properties.get("display"), Some(CSSValue::Keyword(k)) if k.eq_ignore_ascii_case("none"))
+    {
+        return None;
+    }
+    // comments are never rendered
+    if matches!(node.node_type, NodeType::Comment(_)) {
         return None;
     }
 
+    let children = node.children.iter().enumerate().filter_map(|(i, child)| {
+        styled_node(
+            child,
+            rules,
+            viewport_width,
+            &context.child(node, i),
+            &properties,
+        )
+    });
+    let generated =
+        |pseudo_element| generated_content(node, &candidates, context, &properties, pseudo_element);
+    let children = generated(PseudoElement::Before)
+        .into_iter()
+        .chain(children)
+        .chain(generated(PseudoElement::After))
+        .collect();
     Some(StyledNode {
-        node_type: &node.node_type,
-        children: node
-            .children
-            .iter()
-            .filter_map(|child| to_styled_node(child, stylesheet))
-            .collect(),
+        node_type: Cow::Borrowed(&node.node_type),
+        children,
         properties,
     })
 }
 
 impl<'a> StyledNode<'a> {
+    /// NOTE: for a two-value `display` like `block flow`, only the outer display type is taken into account.
+    /// See https://www.w3.org/TR/css-display-3/#the-display-properties
+    ///
+    /// Keywords are ASCII case-insensitive. An unknown value is ignored and the initial value `inline` is used.
     pub fn display(&self) -> Display {
-        match self.properties.get("display") {
-            Some(CSSValue::Keyword(s)) => match s.as_str() {
-                "block" => Display::Block,
-                "none" => Display::None,
-                _ => Display::Inline,
+        let keyword = match self.properties.get("display") {
+            Some(CSSValue::Multiple(values)) if values.len() == 2 => match values.first() {
+                Some(CSSValue::Keyword(s)) => Some(s.as_str()),
+                _ => None,
             },
+            _ => self.keyword("display"),
+        };
+        match keyword.map(str::to_ascii_lowercase).as_deref() {
+            Some("block") => Display::Block,
+            Some("inline-block") => Display::InlineBlock,
+            Some("list-item") => Display::ListItem,
+            Some("none") => Display::None,
             _ => Display::Inline,
         }
     }
+
+    /// `keyword` returns the value of the property `name` if it is a keyword.
+    pub fn keyword(&self, name: &str) -> Option<&str> {
+        match self.properties.get(name) {
+            Some(CSSValue::Keyword(s)) => Some(s.as_str()),
+            _ => None,
+        }
+    }
+
+    /// `length_px` returns the value of the property `name` in pixels if it is a length.
+    /// It returns `None` for `em` since font sizes are not resolved in the style tree.
+    pub fn length_px(&self, name: &str) -> Option<f64> {
+        match self.properties.get(name) {
+            Some(CSSValue::Length(l, Unit::Px)) => Some(*l),
+            _ => None,
+        }
+    }
+
+    /// `color` returns the value of the property `name` if it is a color or a named color.
+    pub fn color(&self, name: &str) -> Option<Color> {
+        self.properties.get(name)?.to_color()
+    }
+
+    /// `computed` returns the value of the property `name` like `getComputedStyle`:
+    /// the cascaded or inherited value if any, or the initial value otherwise.
+    /// It returns `None` for a shorthand or an unknown property.
+    ///
+    /// NOTE: values are not resolved further, e.g. a percentage stays a percentage.
+    pub fn computed(&self, name: &str) -> Option<CSSValue> {
+        // inherited values have already been put into `properties` by the cascade
+        match self.properties.get(name) {
+            Some(value) => Some(value.clone()),
+            None => initial_value(name),
+        }
+    }
+
+    /// `to_json` dumps the computed properties of the node and its descendants.
+    /// Nodes have either `tag`, `text` or `comment`, like `LayoutBox::to_json`.
+    pub fn to_json(&self) -> serde_json::Value {
+        let (key, data) = match &*self.node_type {
+            NodeType::Element(e) => ("tag", e.tag_name.as_str()),
+            NodeType::Text(t) => ("text", t.data.as_str()),
+            NodeType::Comment(c) => ("comment", c.as_str()),
+        };
+        json!({
+            key: data,
+            "properties": self.properties,
+            "children": self.children.iter().map(|child| child.to_json()).collect::<Vec<_>>(),
+        })
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::{
-        css::{AttributeSelectorOp, Declaration, Rule, SimpleSelector},
-        dom::Element,
+        css::{AttributeSelectorOp, Declaration, Rule, SimpleSelector, Unit},
+        dom::{AttrMap, Element},
     };
     use pretty_assertions::assert_eq;
 
@@ -88,10 +544,12 @@ mod tests {
             (
                 // * { display: block; }
                 Stylesheet::new(vec![Rule {
+                    media: None,
                     selectors: vec![SimpleSelector::UniversalSelector],
                     declarations: vec![Declaration {
                         name: "display".to_string(),
                         value: CSSValue::Keyword("block".to_string()),
+                        important: false,
                     }],
                 }]),
                 vec![(
@@ -102,12 +560,14 @@ mod tests {
             (
                 // div { display: block; }
                 Stylesheet::new(vec![Rule {
+                    media: None,
                     selectors: vec![SimpleSelector::TypeSelector {
                         tag_name: "div".into(),
                     }],
                     declarations: vec![Declaration {
                         name: "display".into(),
                         value: CSSValue::Keyword("block".to_string()),
+                        important: false,
                     }],
                 }]),
                 vec![],
@@ -117,19 +577,23 @@ mod tests {
                 // div { display: inline; }
                 Stylesheet::new(vec![
                     Rule {
+                        media: None,
                         selectors: vec![SimpleSelector::UniversalSelector],
                         declarations: vec![Declaration {
                             name: "display".to_string(),
                             value: CSSValue::Keyword("block".into()),
+                            important: false,
                         }],
                     },
                     Rule {
+                        media: None,
                         selectors: vec![SimpleSelector::TypeSelector {
                             tag_name: "div".into(),
                         }],
                         declarations: vec![Declaration {
                             name: "display".into(),
                             value: CSSValue::Keyword("inline".into()),
+                            important: false,
                         }],
                     },
                 ]),
@@ -143,13 +607,16 @@ mod tests {
                 // p { display: inline; testname: testvalue; }
                 Stylesheet::new(vec![
                     Rule {
+                        media: None,
                         selectors: vec![SimpleSelector::UniversalSelector],
                         declarations: vec![Declaration {
                             name: "display".to_string(),
                             value: CSSValue::Keyword("block".into()),
+                            important: false,
                         }],
                     },
                     Rule {
+                        media: None,
                         selectors: vec![SimpleSelector::TypeSelector {
                             tag_name: "p".into(),
                         }],
@@ -157,10 +624,12 @@ mod tests {
                             Declaration {
                                 name: "display".into(),
                                 value: CSSValue::Keyword("inline".into()),
+                                important: false,
                             },
                             Declaration {
                                 name: "testname".into(),
                                 value: CSSValue::Keyword("testvalue".into()),
+                                important: false,
                             },
                         ],
                     },
@@ -175,13 +644,16 @@ mod tests {
                 // p[id=hello] { testname: testvalue; }
                 Stylesheet::new(vec![
                     Rule {
+                        media: None,
                         selectors: vec![SimpleSelector::UniversalSelector],
                         declarations: vec![Declaration {
                             name: "display".to_string(),
                             value: CSSValue::Keyword("block".into()),
+                            important: false,
                         }],
                     },
                     Rule {
+                        media: None,
                         selectors: vec![SimpleSelector::AttributeSelector {
                             tag_name: "p".into(),
                             op: AttributeSelectorOp::Eq,
@@ -191,6 +663,7 @@ mod tests {
                         declarations: vec![Declaration {
                             name: "testname".into(),
                             value: CSSValue::Keyword("testvalue".into()),
+                            important: false,
                         }],
                     },
                 ]),
@@ -201,13 +674,16 @@ mod tests {
                 // p[id=hello] { testname: testvalue; }
                 Stylesheet::new(vec![
                     Rule {
+                        media: None,
                         selectors: vec![SimpleSelector::UniversalSelector],
                         declarations: vec![Declaration {
                             name: "display".to_string(),
                             value: CSSValue::Keyword("block".into()),
+                            important: false,
                         }],
                     },
                     Rule {
+                        media: None,
                         selectors: vec![SimpleSelector::AttributeSelector {
                             tag_name: "p".into(),
                             op: AttributeSelectorOp::Eq,
@@ -217,6 +693,7 @@ mod tests {
                         declarations: vec![Declaration {
                             name: "testname".into(),
                             value: CSSValue::Keyword("testvalue".into()),
+                            important: false,
                         }],
                     },
                 ]),
@@ -230,8 +707,8 @@ mod tests {
         for (stylesheet, properties) in testcases {
             assert_eq!(
                 to_styled_node(e, &stylesheet),
-                Some(StyledNode {
-                    node_type: &e.node_type,
+                Ok(StyledNode {
+                    node_type: Cow::Borrowed(&e.node_type),
                     properties: properties.iter().cloned().collect(),
                     children: vec![],
                 })
@@ -269,17 +746,19 @@ mod tests {
         {
             // * { display: block; }
             let stylesheet = Stylesheet::new(vec![Rule {
+                media: None,
                 selectors: vec![SimpleSelector::UniversalSelector],
                 declarations: vec![Declaration {
                     name: "display".to_string(),
                     value: CSSValue::Keyword("block".to_string()),
+                    important: false,
                 }],
             }]);
 
             assert_eq!(
                 to_styled_node(parent, &stylesheet),
-                Some(StyledNode {
-                    node_type: &parent.node_type,
+                Ok(StyledNode {
+                    node_type: Cow::Borrowed(&parent.node_type),
                     properties: [(
                         "display".to_string(),
                         CSSValue::Keyword("block".to_string()),
@@ -288,7 +767,7 @@ mod tests {
                     .cloned()
                     .collect(),
                     children: vec![StyledNode {
-                        node_type: &child_node_type,
+                        node_type: Cow::Borrowed(&child_node_type),
                         properties: [(
                             "display".to_string(),
                             CSSValue::Keyword("block".to_string()),
@@ -305,22 +784,24 @@ mod tests {
         {
             // p { display: block; }
             let stylesheet = Stylesheet::new(vec![Rule {
+                media: None,
                 selectors: vec![SimpleSelector::TypeSelector {
                     tag_name: "p".into(),
                 }],
                 declarations: vec![Declaration {
                     name: "display".to_string(),
                     value: CSSValue::Keyword("block".to_string()),
+                    important: false,
                 }],
             }]);
 
             assert_eq!(
                 to_styled_node(parent, &stylesheet),
-                Some(StyledNode {
-                    node_type: &parent.node_type,
+                Ok(StyledNode {
+                    node_type: Cow::Borrowed(&parent.node_type),
                     properties: [].iter().cloned().collect(),
                     children: vec![StyledNode {
-                        node_type: &child_node_type,
+                        node_type: Cow::Borrowed(&child_node_type),
                         properties: [(
                             "display".to_string(),
                             CSSValue::Keyword("block".to_string()),
@@ -348,16 +829,21 @@ mod tests {
 
         // p { display: none; }
         let stylesheet = Stylesheet::new(vec![Rule {
+            media: None,
             selectors: vec![SimpleSelector::TypeSelector {
                 tag_name: "div".into(),
             }],
             declarations: vec![Declaration {
                 name: "display".to_string(),
                 value: CSSValue::Keyword("none".to_string()),
+                important: false,
             }],
         }]);
 
-        assert_eq!(to_styled_node(parent, &stylesheet), None);
+        assert_eq!(
+            to_styled_node(parent, &stylesheet),
+            Err(StyleError::RootDisplayNone)
+        );
     }
 
     #[test]
@@ -380,22 +866,446 @@ mod tests {
 
         // p { display: none; }
         let stylesheet = Stylesheet::new(vec![Rule {
+            media: None,
             selectors: vec![SimpleSelector::TypeSelector {
                 tag_name: "p".into(),
             }],
             declarations: vec![Declaration {
                 name: "display".to_string(),
                 value: CSSValue::Keyword("none".to_string()),
+                important: false,
             }],
         }]);
 
         assert_eq!(
             to_styled_node(parent, &stylesheet),
-            Some(StyledNode {
-                node_type: &parent.node_type,
+            Ok(StyledNode {
+                node_type: Cow::Borrowed(&parent.node_type),
                 properties: [].iter().cloned().collect(),
                 children: vec![],
             })
         );
     }
+
+    #[test]
+    fn test_to_styled_node_merged_stylesheets() {
+        let e = &Element::new("p".to_string(), AttrMap::new(), vec![]);
+        let ua = crate::css::parse("p { display: block; }").unwrap();
+        let author = crate::css::parse("p { display: inline; }").unwrap();
+
+        assert_eq!(
+            to_styled_node(e, &ua.merge(author)),
+            Ok(StyledNode {
+                node_type: Cow::Borrowed(&e.node_type),
+                properties: [("display".to_string(), CSSValue::Keyword("inline".into()))]
+                    .iter()
+                    .cloned()
+                    .collect(),
+                children: vec![],
+            })
+        );
+    }
+
+    #[test]
+    fn test_to_styled_node_root_display_none() {
+        let e = &Element::new("body".to_string(), AttrMap::new(), vec![]);
+        let stylesheet = crate::css::parse("body { display: none; }").unwrap();
+
+        let err = to_styled_node(e, &stylesheet).unwrap_err();
+        assert_eq!(err, StyleError::RootDisplayNone);
+        assert!(err.to_string().contains("display: none"));
+    }
+
+    #[test]
+    fn test_expand_margin_padding_shorthand() {
+        let e = &Element::new("p".to_string(), AttrMap::new(), vec![]);
+        let px = |v: f64| CSSValue::Length(v, Unit::Px);
+        let testcases = vec![
+            (
+                "margin: 10px",
+                "margin",
+                [px(10.), px(10.), px(10.), px(10.)],
+            ),
+            (
+                "margin: 10px 20px",
+                "margin",
+                [px(10.), px(20.), px(10.), px(20.)],
+            ),
+            (
+                "padding: 1px 2px 3px",
+                "padding",
+                [px(1.), px(2.), px(3.), px(2.)],
+            ),
+            (
+                "padding: 1px 2px 3px 4px",
+                "padding",
+                [px(1.), px(2.), px(3.), px(4.)],
+            ),
+        ];
+
+        for (declaration, name, [top, right, bottom, left]) in testcases {
+            let stylesheet = crate::css::parse(&format!("p {{ {}; }}", declaration)).unwrap();
+            let snode = to_styled_node(e, &stylesheet).unwrap();
+            assert_eq!(
+                snode.properties,
+                [
+                    (format!("{}-top", name), top),
+                    (format!("{}-right", name), right),
+                    (format!("{}-bottom", name), bottom),
+                    (format!("{}-left", name), left),
+                ]
+                .into_iter()
+                .collect::<PropertyMap>(),
+                "{}",
+                declaration
+            );
+        }
+
+        // the later declaration wins over the shorthand
+        let stylesheet = crate::css::parse("p { margin: 10px; margin-left: 5px; }").unwrap();
+        let snode = to_styled_node(e, &stylesheet).unwrap();
+        assert_eq!(snode.properties.get("margin-top"), Some(&px(10.)));
+        assert_eq!(snode.properties.get("margin-left"), Some(&px(5.)));
+    }
+
+    #[test]
+    fn test_inherited_properties() {
+        let node = crate::html::parse("<div><p>hello</p><span>world</span></div>").unwrap();
+        let stylesheet =
+            crate::css::parse("div { line-height: 2; display: block; } p { line-height: 20px; }")
+                .unwrap();
+        let snode = to_styled_node(&node, &stylesheet).unwrap();
+        let line_height = |snode: &StyledNode| snode.properties.get("line-height").cloned();
+
+        // the text inherits from `p`, which declares its own value
+        let p = &snode.children[0];
+        assert_eq!(line_height(p), Some(CSSValue::Length(20., Unit::Px)));
+        assert_eq!(
+            line_height(&p.children[0]),
+            Some(CSSValue::Length(20., Unit::Px))
+        );
+        // non-inherited properties are not passed down
+        assert_eq!(p.properties.get("display"), None);
+        assert_eq!(line_height(&snode), Some(CSSValue::Number(2.)));
+        assert_eq!(line_height(&snode.children[1]), Some(CSSValue::Number(2.)));
+    }
+
+    #[test]
+    fn test_two_value_display() {
+        let e = &Element::new("p".to_string(), AttrMap::new(), vec![]);
+        let display = |declaration: &str| {
+            let stylesheet =
+                crate::css::parse(&format!("p {{ display: {}; }}", declaration)).unwrap();
+            to_styled_node(e, &stylesheet).unwrap().display()
+        };
+        assert_eq!(display("block flow"), Display::Block);
+        assert_eq!(display("inline flow-root"), Display::Inline);
+        // three keywords are not a valid `display`
+        assert_eq!(display("block flow list-item"), Display::Inline);
+    }
+
+    #[test]
+    fn test_display_case_insensitive() {
+        let e = &Element::new("p".to_string(), AttrMap::new(), vec![]);
+        let display = |declaration: &str| {
+            let stylesheet =
+                crate::css::parse(&format!("p {{ display: {}; }}", declaration)).unwrap();
+            to_styled_node(e, &stylesheet).unwrap().display()
+        };
+        assert_eq!(display("BLOCK"), Display::Block);
+        assert_eq!(display("Inline-Block"), Display::InlineBlock);
+        assert_eq!(display("Block Flow"), Display::Block);
+        assert_eq!(display("grid"), Display::Inline);
+
+        let node = crate::html::parse("<div><p>hello</p></div>").unwrap();
+        let stylesheet = crate::css::parse("p { display: NONE; }").unwrap();
+        assert!(to_styled_node(&node, &stylesheet)
+            .unwrap()
+            .children
+            .is_empty());
+    }
+
+    #[test]
+    fn test_typed_accessors() {
+        let e = &Element::new("p".to_string(), AttrMap::new(), vec![]);
+        let stylesheet = crate::css::parse(
+            "p { text-align: center; width: 100px; margin-left: 1em; color: rgb(255, 0, 0); line-height: 2; }",
+        )
+        .unwrap();
+        let snode = to_styled_node(e, &stylesheet).unwrap();
+
+        assert_eq!(snode.keyword("text-align"), Some("center"));
+        assert_eq!(snode.keyword("width"), None);
+        assert_eq!(snode.keyword("float"), None);
+
+        assert_eq!(snode.length_px("width"), Some(100.));
+        assert_eq!(snode.length_px("line-height"), None);
+        assert_eq!(snode.length_px("height"), None);
+        assert_eq!(snode.length_px("margin-left"), None);
+
+        assert_eq!(
+            snode.color("color"),
+            Some(Color {
+                r: 255,
+                g: 0,
+                b: 0,
+                a: 1.
+            })
+        );
+        assert_eq!(snode.color("text-align"), None);
+        let stylesheet = crate::css::parse("p { color: Red; }").unwrap();
+        let snode = to_styled_node(e, &stylesheet).unwrap();
+        assert_eq!(snode.color("color"), Some(Color::from_rgb(255, 0, 0)));
+        assert_eq!(snode.color("background-color"), None);
+    }
+
+    #[test]
+    fn test_important() {
+        let node = crate::html::parse(r#"<p id="a" class="b">hello</p>"#).unwrap();
+        let stylesheet = crate::css::parse(
+            "p { display: block !important; text-align: left !important; } #a { display: none; } .b { text-align: right !important; }",
+        )
+        .unwrap();
+        let snode = to_styled_node(&node, &stylesheet).unwrap();
+        // `!important` wins over a more specific rule
+        assert_eq!(snode.display(), Display::Block);
+        // among `!important` declarations the specificity decides
+        assert_eq!(snode.keyword("text-align"), Some("right"));
+    }
+
+    #[test]
+    fn test_inherit_and_initial() {
+        let node = crate::html::parse(
+            r#"<div><p class="inherit">a</p><p class="initial">b</p><p>c</p></div>"#,
+        )
+        .unwrap();
+        let stylesheet = crate::css::parse(
+            "div { color: rgb(255, 0, 0); display: block; margin: 10px; } p { color: rgb(0, 0, 255); } .inherit { color: inherit; margin: inherit; } .initial { color: initial; display: initial; }",
+        )
+        .unwrap();
+        let snode = to_styled_node(&node, &stylesheet).unwrap();
+        let red = Some(Color {
+            r: 255,
+            g: 0,
+            b: 0,
+            a: 1.,
+        });
+
+        let inherit = &snode.children[0];
+        assert_eq!(inherit.color("color"), red);
+        assert_eq!(inherit.length_px("margin-left"), Some(10.));
+        // the inherited value is passed down further
+        assert_eq!(inherit.children[0].color("color"), red);
+
+        let initial = &snode.children[1];
+        assert_eq!(initial.color("color"), None);
+        assert_eq!(initial.display(), Display::Inline);
+        assert_eq!(initial.children[0].color("color"), None);
+
+        assert_eq!(
+            snode.children[2].color("color"),
+            Some(Color {
+                r: 0,
+                g: 0,
+                b: 255,
+                a: 1.
+            })
+        );
+    }
+
+    #[test]
+    fn test_media_rule_by_viewport() {
+        let node = crate::html::parse("<div><p>hello</p></div>").unwrap();
+        let stylesheet = crate::css::parse(
+            "div, p { display: block; } @media (max-width: 600px) { p { display: none; } }",
+        )
+        .unwrap();
+
+        let narrow = to_styled_node_with_viewport(&node, &stylesheet, 400.).unwrap();
+        assert!(narrow.children.is_empty());
+        let wide = to_styled_node_with_viewport(&node, &stylesheet, 800.).unwrap();
+        assert_eq!(wide.children.len(), 1);
+        // the viewport is unknown
+        let unknown = to_styled_node(&node, &stylesheet).unwrap();
+        assert_eq!(unknown.children.len(), 1);
+    }
+
+    #[test]
+    fn test_rule_index_same_as_naive() {
+        let node = crate::html::parse(
+            r#"<div id="main" class="box"><P class="note">a</P><span lang="en-US">b</span><p id="x">c</p></div>"#,
+        )
+        .unwrap();
+        let stylesheet = crate::css::parse(
+            "* { color: rgb(0, 0, 0); } div, p { display: block; } p:first-child { display: inline; } .note { margin: 1px; } #x, .box, #main { padding: 2px; } span[lang|=en] { display: none; } [id] { text-align: center; } p { margin: 3px; }",
+        )
+        .unwrap();
+        let index = RuleIndex::new(&stylesheet);
+        for node in node.descendants() {
+            let naive: Vec<&Rule> = stylesheet
+                .rules
+                .iter()
+                .filter(|rule| rule.matches(node))
+                .collect();
+            let indexed: Vec<&Rule> = index
+                .candidates(node)
+                .into_iter()
+                .filter(|rule| rule.matches(node))
+                .collect();
+            assert_eq!(naive, indexed);
+        }
+        // text nodes are tested only against the rules which may match any node
+        let text = &node.children[0].children[0];
+        assert_eq!(index.candidates(text).len(), 2);
+    }
+
+    #[test]
+    fn test_specificity_of_matching_selector() {
+        let node = crate::html::parse(r#"<div><p class="inline">a</p><p>b</p></div>"#).unwrap();
+        // `p, .inline` applies to `<p class="inline">` with the specificity of `.inline`, which beats the later `p`
+        let stylesheet = crate::css::parse(
+            "div { display: block; } p, .inline { display: inline; color: rgb(255, 0, 0); } p { display: block; } p { color: rgb(0, 0, 255); }",
+        )
+        .unwrap();
+        let snode = to_styled_node(&node, &stylesheet).unwrap();
+        assert_eq!(snode.children[0].display(), Display::Inline);
+        assert_eq!(
+            snode.children[0].color("color"),
+            Some(Color::from_rgb(255, 0, 0))
+        );
+        // `<p>` without the class matches only `p`, so the later rules win
+        assert_eq!(snode.children[1].display(), Display::Block);
+        assert_eq!(
+            snode.children[1].color("color"),
+            Some(Color::from_rgb(0, 0, 255))
+        );
+
+        let rule = &stylesheet.rules[1];
+        let context = MatchingContext::default();
+        assert_eq!(
+            rule.specificity_in(&node.children[0], &context),
+            Some((0, 1, 0))
+        );
+        assert_eq!(
+            rule.specificity_in(&node.children[1], &context),
+            Some((0, 0, 1))
+        );
+        assert_eq!(rule.specificity_in(&node, &context), None);
+    }
+
+    #[test]
+    fn test_validate() {
+        let stylesheet = crate::css::parse(
+            "p { foo: bar; display: block; } .x { margin-top: 1px; colour: red; }",
+        )
+        .unwrap();
+        let warnings = validate(&stylesheet);
+        assert_eq!(
+            warnings,
+            vec![
+                StyleWarning::UnknownProperty {
+                    name: "foo".to_string()
+                },
+                StyleWarning::UnknownProperty {
+                    name: "colour".to_string()
+                },
+            ]
+        );
+        assert_eq!(warnings[0].to_string(), "unknown property `foo` is ignored");
+    }
+
+    #[test]
+    fn test_all() {
+        let node = crate::html::parse(
+            r#"<div><p class="initial">a</p><p class="unset">b</p><p class="later">c</p></div>"#,
+        )
+        .unwrap();
+        let stylesheet = crate::css::parse(
+            "div { display: block; color: rgb(255, 0, 0); margin: 1px; } p { display: block; text-align: center; color: rgb(0, 0, 255); } .initial { all: initial; } .unset { all: unset; } .later { all: initial; display: block; }",
+        )
+        .unwrap();
+        let snode = to_styled_node(&node, &stylesheet).unwrap();
+        let [initial, unset, later] = &snode.children[..] else {
+            panic!("unexpected children: {:?}", snode.children);
+        };
+        // `all: initial` wipes `display: block`
+        assert_eq!(initial.display(), Display::Inline);
+        assert!(initial.properties.is_empty());
+        // `all: unset` keeps the inherited `color` of the parent
+        assert_eq!(unset.display(), Display::Inline);
+        assert_eq!(unset.color("color"), Some(Color::from_rgb(255, 0, 0)));
+        assert_eq!(unset.keyword("text-align"), None);
+        // declarations after `all` in the same block still apply
+        assert_eq!(later.display(), Display::Block);
+        assert_eq!(later.properties.len(), 1);
+    }
+
+    #[test]
+    fn test_to_json() {
+        let node = crate::html::parse(r#"<p class="x">hello</p>"#).unwrap();
+        let stylesheet = crate::css::parse(".x { display: block; }").unwrap();
+        let json = to_styled_node(&node, &stylesheet).unwrap().to_json();
+        assert_eq!(json["tag"], "p");
+        assert_eq!(json["properties"]["display"], json!({"Keyword": "block"}));
+        assert_eq!(json["children"][0]["text"], "hello");
+    }
+
+    #[test]
+    fn test_generated_content() {
+        let node = crate::html::parse(r#"<div><p class="x">hello</p><p>world</p></div>"#).unwrap();
+        let stylesheet = crate::css::parse(
+            r#"p { color: red; } .x::after { content: "!"; } .x::before { content: ">"; color: blue; } p::after { content: normal; }"#,
+        )
+        .unwrap();
+        let snode = to_styled_node(&node, &stylesheet).unwrap();
+        let texts = |snode: &StyledNode| -> Vec<String> {
+            snode
+                .children
+                .iter()
+                .map(|child| match &*child.node_type {
+                    NodeType::Text(t) => t.data.clone(),
+                    _ => panic!("unexpected node: {:?}", child.node_type),
+                })
+                .collect()
+        };
+        assert_eq!(texts(&snode.children[0]), vec![">", "hello", "!"]);
+        // a pseudo-element inherits from its element
+        let before = &snode.children[0].children[0];
+        assert_eq!(before.color("color"), Some(Color::from_rgb(0, 0, 255)));
+        let after = &snode.children[0].children[2];
+        assert_eq!(after.color("color"), Some(Color::from_rgb(255, 0, 0)));
+        // `content` other than a string generates nothing
+        assert_eq!(texts(&snode.children[1]), vec!["world"]);
+    }
+
+    #[test]
+    fn test_computed() {
+        let node = crate::html::parse(r#"<div><p>hello</p></div>"#).unwrap();
+        let stylesheet =
+            crate::css::parse("div { display: block; color: #ff0000; width: 50%; }").unwrap();
+        let snode = to_styled_node(&node, &stylesheet).unwrap();
+        let p = &snode.children[0];
+        // `color` is inherited from the parent
+        assert_eq!(
+            p.computed("color"),
+            Some(CSSValue::Color(Color::from_rgb(255, 0, 0)))
+        );
+        // `display` and `width` are not inherited, so the initial values are used
+        assert_eq!(
+            p.computed("display"),
+            Some(CSSValue::Keyword("inline".to_string()))
+        );
+        assert_eq!(
+            p.computed("width"),
+            Some(CSSValue::Keyword("auto".to_string()))
+        );
+        assert_eq!(snode.computed("width"), Some(CSSValue::Percentage(50.)));
+        assert_eq!(p.computed("margin"), None);
+        assert_eq!(p.computed("unknown"), None);
+        // every known longhand has an initial value
+        for name in KNOWN_PROPERTIES {
+            let shorthand = ["all", "margin", "padding"].contains(name);
+            assert_eq!(initial_value(name).is_none(), shorthand, "{}", name);
+        }
+    }
 }
@@ -0,0 +1,109 @@
+//! This module includes the small utilities shared by the crates of the browser.
+
+use combine::error::ParseError;
+use combine::parser::char::{newline, space};
+use combine::{many, Parser, Stream};
+
+/// `blank` skips spaces and newlines.
+pub fn blank<Input>() -> impl Parser<Input, Output = ()>
+where
+    Input: Stream<Token = char>,
+    Input::Error: ParseError<Input::Token, Input::Range, Input::Position>,
+{
+    // 空白と改行を読み飛ばす
+    many::<String, _, _>(space().or(newline())).map(|_| ())
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Point {
+    pub x: f64,
+    pub y: f64,
+}
+
+impl std::fmt::Display for Point {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "({}, {})", self.x.round(), self.y.round())
+    }
+}
+
+impl std::ops::Add for Point {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            x: self.x + other.x,
+            y: self.y + other.y,
+        }
+    }
+}
+
+impl std::ops::Sub for Point {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        Self {
+            x: self.x - other.x,
+            y: self.y - other.y,
+        }
+    }
+}
+
+impl std::ops::Mul<f64> for Point {
+    type Output = Self;
+
+    fn mul(self, scale: f64) -> Self {
+        Self {
+            x: self.x * scale,
+            y: self.y * scale,
+        }
+    }
+}
+
+impl Point {
+    /// `distance_to` returns the Euclidean distance between two points.
+    pub fn distance_to(&self, other: &Point) -> f64 {
+        (self.x - other.x).hypot(self.y - other.y)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blank() {
+        assert_eq!(blank().parse("  \n\t abc "), Ok(((), "abc ")));
+        assert_eq!(blank().parse("abc"), Ok(((), "abc")));
+        assert_eq!(blank().parse(""), Ok(((), "")));
+    }
+
+    #[test]
+    fn test_add() {
+        assert_eq!(
+            Point { x: 1., y: 2. } + Point { x: 3., y: -4. },
+            Point { x: 4., y: -2. }
+        );
+    }
+
+    #[test]
+    fn test_sub() {
+        assert_eq!(
+            Point { x: 1., y: 2. } - Point { x: 3., y: -4. },
+            Point { x: -2., y: 6. }
+        );
+    }
+
+    #[test]
+    fn test_mul() {
+        assert_eq!(Point { x: 1.5, y: -2. } * 2., Point { x: 3., y: -4. });
+        assert_eq!(Point { x: 1.5, y: -2. } * 0., Point { x: 0., y: 0. });
+    }
+
+    #[test]
+    fn test_distance_to() {
+        let origin = Point::default();
+        assert_eq!(origin.distance_to(&Point { x: 3., y: 4. }), 5.);
+        assert_eq!(Point { x: 3., y: 4. }.distance_to(&origin), 5.);
+        assert_eq!(origin.distance_to(&origin), 0.);
+    }
+}
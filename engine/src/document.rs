@@ -1,8 +1,12 @@
-use crate::{element::Element, DOM};
+use crate::{element::Element, runtime::Dom};
 use boa_engine::{
     class::{Class, ClassBuilder},
-    js_string, Context, Finalize, JsData, JsError, JsResult, JsValue, NativeFunction, Trace,
+    js_string,
+    object::builtins::JsArray,
+    property::Attribute,
+    Context, Finalize, JsData, JsError, JsNativeError, JsResult, JsValue, NativeFunction, Trace,
 };
+use dom::dom::{AttrMap, Element as DomElement, Node, Text};
 
 #[derive(Debug, Trace, Finalize, JsData)]
 pub struct Document;
@@ -14,16 +18,114 @@ impl Document {
         context: &mut Context,
     ) -> JsResult<JsValue> {
         let id = args[0].as_string().unwrap().to_std_string_escaped();
-        let mut dom = DOM.try_lock().unwrap();
-        if dom.get_element_by_id(id.as_str()).is_none() {
+        let dom = Dom::get(context);
+        let Some(key) = dom
+            .borrow_mut()
+            .get_element_by_id(id.as_str())
+            .map(|node| node.key())
+        else {
             return Err(JsError::from_opaque(JsValue::String(js_string!(format!(
                 "get_element_by_id #{} not found",
                 id
             )))));
         };
-        let element = Element::from_data(Element { id }, context).unwrap();
+        let element = Element::from_data(Element { key: Some(key) }, context).unwrap();
         Ok(JsValue::Object(element))
     }
+
+    fn query_selector(
+        _this: &JsValue,
+        args: &[JsValue],
+        context: &mut Context,
+    ) -> JsResult<JsValue> {
+        let selectors = args
+            .first()
+            .and_then(JsValue::as_string)
+            .ok_or_else(|| {
+                JsNativeError::typ().with_message("querySelector: selectors must be a string")
+            })?
+            .to_std_string_escaped();
+        let dom = Dom::get(context);
+        let key = dom
+            .borrow()
+            .query_selector(&selectors)
+            .map_err(|e| JsNativeError::syntax().with_message(e.to_string()))?
+            .map(Node::key);
+        let Some(key) = key else {
+            return Ok(JsValue::null());
+        };
+        let element = Element::from_data(Element { key: Some(key) }, context)?;
+        Ok(JsValue::Object(element))
+    }
+
+    fn get_elements_by_tag_name(
+        _this: &JsValue,
+        args: &[JsValue],
+        context: &mut Context,
+    ) -> JsResult<JsValue> {
+        let tag_name = args
+            .first()
+            .and_then(JsValue::as_string)
+            .ok_or_else(|| {
+                JsNativeError::typ().with_message("getElementsByTagName: tag name must be a string")
+            })?
+            .to_std_string_escaped();
+        let dom = Dom::get(context);
+        let keys = dom
+            .borrow()
+            .get_elements_by_tag_name(&tag_name)
+            .into_iter()
+            .map(Node::key)
+            .collect::<Vec<_>>();
+        let elements = keys
+            .into_iter()
+            .map(|key| Element::from_data(Element { key: Some(key) }, context).map(JsValue::Object))
+            .collect::<JsResult<Vec<_>>>()?;
+        Ok(JsArray::from_iter(elements, context).into())
+    }
+
+    fn get_title(_this: &JsValue, _args: &[JsValue], context: &mut Context) -> JsResult<JsValue> {
+        let dom = Dom::get(context);
+        let dom = dom.borrow();
+        let title = match dom.query_selector("title") {
+            Ok(Some(title)) => title.inner_text(),
+            _ => String::new(),
+        };
+        Ok(JsValue::String(js_string!(title)))
+    }
+
+    fn set_title(_this: &JsValue, args: &[JsValue], context: &mut Context) -> JsResult<JsValue> {
+        let title = args
+            .first()
+            .cloned()
+            .unwrap_or_default()
+            .to_string(context)?
+            .to_std_string_escaped();
+        let dom = Dom::get(context);
+        set_title(&mut dom.borrow_mut(), &title);
+        Ok(JsValue::undefined())
+    }
+}
+
+/// `set_title` replaces the text of the `title` element.
+/// If there is no `title` element, one is created in `head`, which is also created at the top of the document if absent.
+fn set_title(dom: &mut Node, title: &str) {
+    if let Ok(Some(node)) = dom.query_selector_mut("title") {
+        node.set_inner_text(title);
+        return;
+    }
+    let title = DomElement::new(
+        "title".to_string(),
+        AttrMap::new(),
+        vec![Text::new(title.to_string())],
+    );
+    match dom.query_selector_mut("head") {
+        Ok(Some(head)) => head.children.push(title),
+        _ => dom.children.insert(
+            0,
+            DomElement::new("head".to_string(), AttrMap::new(), vec![title]),
+        ),
+    }
 }
 
 impl Class for Document {
@@ -42,6 +144,117 @@ impl Class for Document {
         let get_element_by_id = NativeFunction::from_fn_ptr(Self::get_element_by_id);
         class.method(js_string!("getElementById"), 1, get_element_by_id);
 
+        let query_selector = NativeFunction::from_fn_ptr(Self::query_selector);
+        class.method(js_string!("querySelector"), 1, query_selector);
+
+        let get_elements_by_tag_name = NativeFunction::from_fn_ptr(Self::get_elements_by_tag_name);
+        class.method(
+            js_string!("getElementsByTagName"),
+            1,
+            get_elements_by_tag_name,
+        );
+
+        let get_title =
+            NativeFunction::from_fn_ptr(Self::get_title).to_js_function(class.context().realm());
+        let set_title =
+            NativeFunction::from_fn_ptr(Self::set_title).to_js_function(class.context().realm());
+        class.accessor(
+            js_string!("title"),
+            Some(get_title),
+            Some(set_title),
+            Attribute::all(),
+        );
+
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::runtime::Runtime;
+    use dom::html;
+    use std::{cell::RefCell, rc::Rc};
+
+    #[test]
+    fn test_title() {
+        let dom = Rc::new(RefCell::new(
+            html::parse("<html><head><title>hoge</title></head><body></body></html>").unwrap(),
+        ));
+        let mut runtime = Runtime::new(dom.clone());
+        assert_eq!(runtime.execute("(test)", "document.title").unwrap(), "hoge");
+        runtime.execute("(test)", "document.title = 'x'").unwrap();
+        assert_eq!(
+            dom.borrow()
+                .query_selector("title")
+                .unwrap()
+                .unwrap()
+                .inner_text(),
+            "x"
+        );
+        assert_eq!(runtime.execute("(test)", "document.title").unwrap(), "x");
+
+        // `title` is created in `head` when it is missing
+        let dom = Rc::new(RefCell::new(
+            html::parse("<html><body><p>hello</p></body></html>").unwrap(),
+        ));
+        let mut runtime = Runtime::new(dom.clone());
+        assert_eq!(runtime.execute("(test)", "document.title").unwrap(), "");
+        runtime.execute("(test)", "document.title = 'y'").unwrap();
+        let dom = dom.borrow();
+        let head = &dom.children[0];
+        assert_eq!(
+            head.debug_tree(0),
+            "- Element(head) []\n  - Element(title) []\n    - Text(\"y\")\n"
+        );
+    }
+
+    #[test]
+    fn test_get_elements_by_tag_name() {
+        let dom = Rc::new(RefCell::new(
+            html::parse(
+                r#"<body>
+    <p>hello</p>
+    <p class="inline" id="world">world</p>
+    <div><p>!</p></div>
+    <span id="result">hoge</span>
+</body>"#,
+            )
+            .unwrap(),
+        ));
+        let mut runtime = Runtime::new(dom.clone());
+        assert_eq!(
+            runtime
+                .execute("(test)", "document.getElementsByTagName('p').length")
+                .unwrap(),
+            "3"
+        );
+        assert_eq!(
+            runtime
+                .execute(
+                    "(test)",
+                    "document.getElementsByTagName('p').map(e => e.innerText).join(',')"
+                )
+                .unwrap(),
+            "hello,world,!"
+        );
+        runtime
+            .execute(
+                "(test)",
+                "document.getElementsByTagName('P')[2].innerText = 'fuga'",
+            )
+            .unwrap();
+        assert_eq!(dom.borrow().children[2].inner_text(), "fuga");
+        assert!(dom.borrow_mut().get_element_by_id("world").is_some());
+        // the elements without an id are not given one
+        assert_eq!(
+            dom.borrow().children[2].outer_html(),
+            "<div><p>fuga</p></div>"
+        );
+        assert_eq!(
+            runtime
+                .execute("(test)", "document.getElementsByTagName('table').length")
+                .unwrap(),
+            "0"
+        );
+    }
+}
@@ -1,80 +1,277 @@
-use crate::DOM;
+use crate::runtime::{DetachedNodes, Dom, EventListeners};
 use boa_engine::{
     class::{Class, ClassBuilder},
     js_string,
+    object::{builtins::JsFunction, ObjectInitializer},
     property::Attribute,
     Context, Finalize, JsData, JsError, JsNativeError, JsObject, JsResult, JsValue, NativeFunction,
     Trace,
 };
-use dom::dom::NodeType;
+use dom::dom::{Node, NodeKey, NodeType};
 
 #[derive(Debug, Trace, Finalize, JsData)]
 pub struct Element {
-    pub id: String,
+    /// `key` refers to the node of the element, so that the document is not touched to keep track of it.
+    #[unsafe_ignore_trace]
+    pub key: Option<NodeKey>,
 }
 
 impl Element {
-    fn get_id(this: &JsValue) -> JsResult<String> {
+    fn get_key(this: &JsValue) -> JsResult<NodeKey> {
         let this = this
             .as_object()
             .and_then(JsObject::downcast_ref::<Self>)
             .ok_or_else(|| {
                 JsNativeError::typ().with_message("get Element.tagName called with invalid value")
             })?;
-        Ok(this.id.clone())
+        this.key.ok_or_else(|| {
+            JsError::from_native(
+                JsNativeError::typ().with_message("the element is not bound to any node"),
+            )
+        })
     }
 
-    fn get_tag_name(
+    /// `stale_error` is thrown when the node of an element can no longer be found,
+    /// which happens when the element has been removed.
+    fn stale_error() -> JsError {
+        JsError::from_native(
+            JsNativeError::reference()
+                .with_message("the element is no longer in the document; it may have been removed"),
+        )
+    }
+
+    /// `with_node` calls `f` with the node of `this`, which is looked up in the document first and then in the detached nodes.
+    fn with_node<R>(
         this: &JsValue,
-        _args: &[JsValue],
-        _context: &mut Context,
-    ) -> JsResult<JsValue> {
-        let id = Self::get_id(this)?;
-        let mut dom = DOM.try_lock().unwrap();
-        let Some(node) = dom.get_element_by_id(&id) else {
-            return Err(JsError::from_native(
-                JsNativeError::typ().with_message(format!("{} not found", id)),
-            ));
-        };
-        let NodeType::Element(element) = &node.node_type else {
-            return Err(JsError::from_native(
-                JsNativeError::typ().with_message(format!("{} is not an element", id)),
-            ));
-        };
-        Ok(JsValue::String(js_string!(element.tag_name.clone())))
+        context: &mut Context,
+        f: impl FnOnce(&mut Box<Node>) -> JsResult<R>,
+    ) -> JsResult<R> {
+        let key = Self::get_key(this)?;
+        let dom = Dom::get(context);
+        if let Some(node) = dom.borrow_mut().get_node_by_key(key) {
+            return f(node);
+        }
+        DetachedNodes::with_node(context, key, f).unwrap_or_else(|| Err(Self::stale_error()))
+    }
+
+    fn get_tag_name(this: &JsValue, _args: &[JsValue], context: &mut Context) -> JsResult<JsValue> {
+        Self::with_node(this, context, |node| {
+            let NodeType::Element(element) = &node.node_type else {
+                return Err(JsError::from_native(
+                    JsNativeError::typ().with_message("the node is not an element"),
+                ));
+            };
+            Ok(JsValue::String(js_string!(element.tag_name.clone())))
+        })
     }
 
     fn get_inner_text(
         this: &JsValue,
         _args: &[JsValue],
-        _context: &mut Context,
+        context: &mut Context,
     ) -> JsResult<JsValue> {
-        let id = Self::get_id(this)?;
-        let mut dom = DOM.try_lock().unwrap();
-        let Some(node) = dom.get_element_by_id(&id) else {
-            return Err(JsError::from_native(
-                JsNativeError::typ().with_message(format!("{} not found", id)),
-            ));
-        };
-        Ok(JsValue::String(js_string!(node.inner_text())))
+        Self::with_node(this, context, |node| {
+            Ok(JsValue::String(js_string!(node.inner_text())))
+        })
     }
 
     fn set_inner_text(
         this: &JsValue,
         args: &[JsValue],
-        _context: &mut Context,
+        context: &mut Context,
     ) -> JsResult<JsValue> {
         let text = args[0].as_string().unwrap().to_std_string_escaped();
-        let id = Self::get_id(this)?;
-        let mut dom = DOM.try_lock().unwrap();
-        let Some(node) = dom.get_element_by_id(&id) else {
-            return Err(JsError::from_native(
-                JsNativeError::typ().with_message(format!("{} not found", id)),
-            ));
+        Self::with_node(this, context, |node| {
+            node.set_inner_text(&text);
+            Ok(JsValue::String(js_string!(text)))
+        })
+    }
+
+    /// `get_dataset` returns an object of the `data-*` attributes keyed by their camelCased names, e.g. `userId` for `data-user-id`.
+    ///
+    /// NOTE: the object is a snapshot, so assigning to it does not change the attributes.
+    /// See https://html.spec.whatwg.org/multipage/dom.html#dom-dataset
+    fn get_dataset(this: &JsValue, _args: &[JsValue], context: &mut Context) -> JsResult<JsValue> {
+        let entries = Self::with_node(this, context, |node| {
+            let NodeType::Element(element) = &node.node_type else {
+                return Ok(vec![]);
+            };
+            Ok(element
+                .attributes
+                .iter()
+                .filter_map(|(name, value)| Some((data_key(name)?, value.clone())))
+                .collect::<Vec<_>>())
+        })?;
+        let mut dataset = ObjectInitializer::new(context);
+        for (key, value) in entries {
+            dataset.property(js_string!(key), js_string!(value), Attribute::all());
+        }
+        Ok(JsValue::Object(dataset.build()))
+    }
+
+    /// `get_data_attribute` returns the value of `data-*` attribute for a camelCased `name`, or `undefined` if it is missing.
+    fn get_data_attribute(
+        this: &JsValue,
+        args: &[JsValue],
+        context: &mut Context,
+    ) -> JsResult<JsValue> {
+        let name = args
+            .first()
+            .and_then(JsValue::as_string)
+            .ok_or_else(|| {
+                JsNativeError::typ().with_message("getDataAttribute: name must be a string")
+            })?
+            .to_std_string_escaped();
+        let attribute = data_attribute(&name);
+        Self::with_node(this, context, |node| {
+            let NodeType::Element(element) = &node.node_type else {
+                return Ok(JsValue::undefined());
+            };
+            Ok(element
+                .attributes
+                .get(&attribute)
+                .map_or(JsValue::undefined(), |value| {
+                    JsValue::String(js_string!(value.clone()))
+                }))
+        })
+    }
+
+    /// `clone_node` returns a copy of the element which is not in the document.
+    fn clone_node(this: &JsValue, args: &[JsValue], context: &mut Context) -> JsResult<JsValue> {
+        let deep = args.first().is_some_and(JsValue::to_boolean);
+        let node = Self::with_node(this, context, |node| {
+            if deep {
+                return Ok(node.clone_subtree());
+            }
+            Ok(Node::new(node.node_type.clone(), vec![]))
+        })?;
+        let key = DetachedNodes::insert(context, node);
+        let element = Element::from_data(Element { key: Some(key) }, context)?;
+        Ok(JsValue::Object(element))
+    }
+
+    /// `element_key` returns the key of the element wrapped by `value`, or `None` for `null` and `undefined`.
+    fn element_key(value: &JsValue) -> JsResult<Option<NodeKey>> {
+        if value.is_null_or_undefined() {
+            return Ok(None);
+        }
+        Self::get_key(value).map(Some)
+    }
+
+    /// `insert_before` inserts `newNode` into `this` just before the child `referenceNode`, or at the end if it is `null`.
+    /// `newNode` is moved if it is already in the document.
+    /// See https://dom.spec.whatwg.org/#dom-node-insertbefore
+    fn insert_before(this: &JsValue, args: &[JsValue], context: &mut Context) -> JsResult<JsValue> {
+        let new_node = args.first().cloned().unwrap_or_default();
+        let new_key = Self::element_key(&new_node)?.ok_or_else(|| {
+            JsNativeError::typ().with_message("insertBefore: the node to insert must be an element")
+        })?;
+        let reference_key = Self::element_key(&args.get(1).cloned().unwrap_or_default())?;
+        let parent_key = Self::get_key(this)?;
+        if reference_key == Some(new_key) {
+            return Ok(new_node);
+        }
+        let child_index = |parent: &Node, key: NodeKey| {
+            parent.children.iter().position(|child| child.key() == key)
         };
-        node.set_inner_text(&text);
-        Ok(JsValue::String(js_string!(text)))
+        // check everything before `newNode` is taken out so that it is never lost
+        let reference_found = Self::with_node(this, context, |parent| {
+            Ok(reference_key.is_none_or(|key| child_index(parent, key).is_some()))
+        })?;
+        if !reference_found {
+            return Err(JsError::from_native(JsNativeError::typ().with_message(
+                "insertBefore: the reference node is not a child of this element",
+            )));
+        }
+        if Self::with_node(&new_node, context, |node| {
+            Ok(node.get_node_by_key(parent_key).is_some())
+        })? {
+            return Err(JsError::from_native(JsNativeError::typ().with_message(
+                "insertBefore: an element cannot be inserted into itself or its descendant",
+            )));
+        }
+
+        let dom = Dom::get(context);
+        let node = dom.borrow_mut().remove_node_by_key(new_key);
+        let node = node
+            .or_else(|| DetachedNodes::remove(context, new_key))
+            .ok_or_else(Self::stale_error)?;
+        Self::with_node(this, context, |parent| {
+            let index = reference_key
+                .and_then(|key| child_index(parent, key))
+                .unwrap_or(parent.children.len());
+            parent.insert_child_at(index, node);
+            Ok(())
+        })?;
+        Ok(new_node)
+    }
+
+    fn add_event_listener(
+        this: &JsValue,
+        args: &[JsValue],
+        context: &mut Context,
+    ) -> JsResult<JsValue> {
+        let key = Self::get_key(this)?;
+        let event_type = args
+            .first()
+            .and_then(JsValue::as_string)
+            .ok_or_else(|| {
+                JsNativeError::typ().with_message("addEventListener: type must be a string")
+            })?
+            .to_std_string_escaped();
+        let callback = args
+            .get(1)
+            .and_then(JsValue::as_object)
+            .cloned()
+            .and_then(JsFunction::from_object)
+            .ok_or_else(|| {
+                JsNativeError::typ().with_message("addEventListener: callback must be a function")
+            })?;
+        EventListeners::add(context, key, &event_type, callback);
+        Ok(JsValue::undefined())
+    }
+
+    fn remove(this: &JsValue, _args: &[JsValue], context: &mut Context) -> JsResult<JsValue> {
+        let key = Self::get_key(this)?;
+        let dom = Dom::get(context);
+        let mut dom = dom.borrow_mut();
+        if dom.remove_node_by_key(key).is_none() {
+            return Err(Self::stale_error());
+        }
+        Ok(JsValue::undefined())
+    }
+}
+
+/// `data_key` converts an attribute name like `data-user-id` into the key of `dataset` like `userId`.
+/// It returns `None` if the attribute is not a `data-*` attribute.
+fn data_key(attribute: &str) -> Option<String> {
+    let name = attribute.strip_prefix("data-")?;
+    let mut key = String::with_capacity(name.len());
+    let mut chars = name.chars().peekable();
+    while let Some(c) = chars.next() {
+        match chars.peek() {
+            Some(next) if c == '-' && next.is_ascii_lowercase() => {
+                key.push(next.to_ascii_uppercase());
+                chars.next();
+            }
+            _ => key.push(c),
+        }
     }
+    Some(key)
+}
+
+/// `data_attribute` converts a key of `dataset` like `userId` into the attribute name like `data-user-id`.
+fn data_attribute(key: &str) -> String {
+    let mut attribute = String::from("data-");
+    for c in key.chars() {
+        if c.is_ascii_uppercase() {
+            attribute.push('-');
+            attribute.push(c.to_ascii_lowercase());
+        } else {
+            attribute.push(c);
+        }
+    }
+    attribute
 }
 
 impl Class for Element {
@@ -86,7 +283,7 @@ impl Class for Element {
         _args: &[JsValue],
         _context: &mut Context,
     ) -> JsResult<Self> {
-        Ok(Element { id: "".to_string() })
+        Ok(Element { key: None })
     }
 
     fn init(class: &mut ClassBuilder<'_>) -> JsResult<()> {
@@ -112,6 +309,230 @@ impl Class for Element {
             Attribute::all(),
         );
 
+        // create `dataset` property
+        let get_dataset =
+            NativeFunction::from_fn_ptr(Self::get_dataset).to_js_function(class.context().realm());
+        class.accessor(
+            js_string!("dataset"),
+            Some(get_dataset),
+            None,
+            Attribute::READONLY,
+        );
+
+        class.method(
+            js_string!("getDataAttribute"),
+            1,
+            NativeFunction::from_fn_ptr(Self::get_data_attribute),
+        );
+        class.method(
+            js_string!("addEventListener"),
+            2,
+            NativeFunction::from_fn_ptr(Self::add_event_listener),
+        );
+        class.method(
+            js_string!("cloneNode"),
+            1,
+            NativeFunction::from_fn_ptr(Self::clone_node),
+        );
+        class.method(
+            js_string!("insertBefore"),
+            2,
+            NativeFunction::from_fn_ptr(Self::insert_before),
+        );
+        class.method(
+            js_string!("remove"),
+            0,
+            NativeFunction::from_fn_ptr(Self::remove),
+        );
+
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{data_attribute, data_key};
+    use crate::runtime::Runtime;
+    use dom::html;
+    use std::{cell::RefCell, rc::Rc};
+
+    #[test]
+    fn test_clone_node() {
+        let dom = Rc::new(RefCell::new(
+            html::parse(r#"<body><span id="result">hoge<b>fuga</b></span></body>"#).unwrap(),
+        ));
+        let mut runtime = Runtime::new(dom.clone());
+        runtime
+            .execute(
+                "(test)",
+                r#"var original = document.getElementById("result");
+                var deep = original.cloneNode(true);
+                var shallow = original.cloneNode(false);"#,
+            )
+            .unwrap();
+        assert_eq!(
+            runtime.execute("(test)", "deep.innerText").unwrap(),
+            "hogefuga"
+        );
+        assert_eq!(runtime.execute("(test)", "deep.tagName").unwrap(), "span");
+        assert_eq!(runtime.execute("(test)", "shallow.innerText").unwrap(), "");
+        assert_eq!(
+            runtime.execute("(test)", "shallow.tagName").unwrap(),
+            "span"
+        );
+
+        // the clone is detached from the document
+        runtime
+            .execute("(test)", r#"deep.innerText = "piyo""#)
+            .unwrap();
+        assert_eq!(runtime.execute("(test)", "deep.innerText").unwrap(), "piyo");
+        assert_eq!(
+            runtime.execute("(test)", "original.innerText").unwrap(),
+            "hogefuga"
+        );
+        assert_eq!(dom.borrow().inner_text(), "hogefuga");
+    }
+
+    #[test]
+    fn test_clone_after_insert() {
+        let dom = Rc::new(RefCell::new(
+            html::parse(r#"<ul id="list"><li id="item">a</li></ul>"#).unwrap(),
+        ));
+        let mut runtime = Runtime::new(dom.clone());
+        runtime
+            .execute(
+                "(test)",
+                r#"var list = document.getElementById("list");
+                var item = document.getElementById("item");
+                var x = item.cloneNode(true);
+                list.insertBefore(x, null);
+                var y = item.cloneNode(true);
+                y.innerText = "b";"#,
+            )
+            .unwrap();
+        assert_eq!(runtime.execute("(test)", "x.innerText").unwrap(), "a");
+        assert_eq!(runtime.execute("(test)", "y.innerText").unwrap(), "b");
+        // the clones keep the attributes of the original
+        assert_eq!(
+            dom.borrow().outer_html(),
+            r#"<ul id="list"><li id="item">a</li><li id="item">a</li></ul>"#
+        );
+    }
+
+    #[test]
+    fn test_insert_clones() {
+        let dom = Rc::new(RefCell::new(
+            html::parse(r#"<ul id="list"><li id="item">a</li></ul>"#).unwrap(),
+        ));
+        let mut runtime = Runtime::new(dom.clone());
+        runtime
+            .execute(
+                "(test)",
+                r#"var list = document.getElementById("list");
+                var item = document.getElementById("item");
+                var first = item.cloneNode(true);
+                list.insertBefore(first, null);
+                var second = item.cloneNode(true);
+                list.insertBefore(second, null);
+                second.innerText = "c";"#,
+            )
+            .unwrap();
+        assert_eq!(runtime.execute("(test)", "first.innerText").unwrap(), "a");
+        assert_eq!(runtime.execute("(test)", "second.innerText").unwrap(), "c");
+        assert_eq!(dom.borrow().inner_text(), "aac");
+    }
+
+    #[test]
+    fn test_stale_element() {
+        let dom = Rc::new(RefCell::new(
+            html::parse(r#"<body><span id="result">hoge</span></body>"#).unwrap(),
+        ));
+        let mut runtime = Runtime::new(dom);
+        runtime
+            .execute(
+                "(test)",
+                r#"var e = document.getElementById("result"); e.remove();"#,
+            )
+            .unwrap();
+        for script in ["e.innerText", "e.remove()"] {
+            let err = runtime.execute("(test)", script).unwrap_err().to_string();
+            assert!(
+                err.contains("the element is no longer in the document"),
+                "{}",
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn test_insert_before() {
+        let dom = Rc::new(RefCell::new(
+            html::parse(r#"<ul id="list"><li id="b">b</li><li id="c">c</li></ul>"#).unwrap(),
+        ));
+        let mut runtime = Runtime::new(dom.clone());
+        runtime
+            .execute(
+                "(test)",
+                r#"var list = document.getElementById("list");
+                var b = document.getElementById("b");
+                var a = b.cloneNode(true);
+                a.innerText = "a";
+                list.insertBefore(a, b);
+                list.insertBefore(b, null);"#,
+            )
+            .unwrap();
+        assert_eq!(dom.borrow().inner_text(), "acb");
+        assert_eq!(runtime.execute("(test)", "a.innerText").unwrap(), "a");
+
+        assert!(runtime
+            .execute("(test)", "list.insertBefore(list, null)")
+            .is_err());
+        assert!(runtime
+            .execute(
+                "(test)",
+                "a.insertBefore(list, document.getElementById(\"c\"))"
+            )
+            .is_err());
+        assert_eq!(dom.borrow().inner_text(), "acb");
+    }
+
+    #[test]
+    fn test_dataset() {
+        let dom = Rc::new(RefCell::new(
+            html::parse(
+                r#"<body><span id="result" data-user-id="42" data-name="foo">hoge</span></body>"#,
+            )
+            .unwrap(),
+        ));
+        let mut runtime = Runtime::new(dom);
+        runtime
+            .execute("(test)", r#"var e = document.getElementById("result");"#)
+            .unwrap();
+        assert_eq!(runtime.execute("(test)", "e.dataset.userId").unwrap(), "42");
+        assert_eq!(runtime.execute("(test)", "e.dataset.name").unwrap(), "foo");
+        assert_eq!(
+            runtime.execute("(test)", "e.dataset.id").unwrap(),
+            "undefined"
+        );
+        assert_eq!(
+            runtime
+                .execute("(test)", r#"e.getDataAttribute("userId")"#)
+                .unwrap(),
+            "42"
+        );
+        assert_eq!(
+            runtime
+                .execute("(test)", r#"e.getDataAttribute("missing")"#)
+                .unwrap(),
+            "undefined"
+        );
+    }
+
+    #[test]
+    fn test_data_key() {
+        assert_eq!(data_key("data-user-id").as_deref(), Some("userId"));
+        assert_eq!(data_key("data-x-1").as_deref(), Some("x-1"));
+        assert_eq!(data_key("id"), None);
+        assert_eq!(data_attribute("userId"), "data-user-id");
+    }
+}
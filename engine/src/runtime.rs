@@ -1,6 +1,121 @@
 use crate::{document::Document, element::Element};
 use anyhow::Result;
-use boa_engine::{class::Class, js_string, Context, Source};
+use boa_engine::{
+    class::Class, js_string, object::builtins::JsFunction, Context, Finalize, JsData, JsValue,
+    Source, Trace,
+};
+use dom::dom::{Node, NodeKey};
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+/// `Dom` holds the document which the scripts of a runtime operate on.
+/// It is stored in the host-defined slot of the realm so that native functions can resolve it from `Context`.
+#[derive(Trace, Finalize, JsData)]
+pub(crate) struct Dom(#[unsafe_ignore_trace] Rc<RefCell<Box<Node>>>);
+
+impl Dom {
+    pub(crate) fn get(context: &Context) -> Rc<RefCell<Box<Node>>> {
+        context
+            .realm()
+            .host_defined()
+            .get::<Dom>()
+            .expect("document is not registered to the runtime")
+            .0
+            .clone()
+    }
+}
+
+/// `DetachedNodes` holds the trees which are not in the document, such as the ones created by `cloneNode`, keyed by their roots.
+#[derive(Default, Trace, Finalize, JsData)]
+pub(crate) struct DetachedNodes(#[unsafe_ignore_trace] HashMap<NodeKey, Box<Node>>);
+
+impl DetachedNodes {
+    /// `insert` stores `node` as the root of a detached tree and returns its key.
+    pub(crate) fn insert(context: &Context, node: Box<Node>) -> NodeKey {
+        let mut host_defined = context.realm().host_defined_mut();
+        let detached = host_defined
+            .get_mut::<DetachedNodes>()
+            .expect("detached nodes are not registered to the runtime");
+        let key = node.key();
+        detached.0.insert(key, node);
+        key
+    }
+
+    /// `remove` takes the node of `key` out of the detached trees, e.g. to put it into the document.
+    pub(crate) fn remove(context: &Context, key: NodeKey) -> Option<Box<Node>> {
+        let mut host_defined = context.realm().host_defined_mut();
+        let detached = &mut host_defined.get_mut::<DetachedNodes>()?.0;
+        detached.remove(&key).or_else(|| {
+            detached
+                .values_mut()
+                .find_map(|root| root.remove_node_by_key(key))
+        })
+    }
+
+    /// `with_node` calls `f` with the node of `key` in the detached trees if any.
+    pub(crate) fn with_node<R>(
+        context: &Context,
+        key: NodeKey,
+        f: impl FnOnce(&mut Box<Node>) -> R,
+    ) -> Option<R> {
+        let mut host_defined = context.realm().host_defined_mut();
+        let node = host_defined
+            .get_mut::<DetachedNodes>()?
+            .0
+            .values_mut()
+            .find_map(|root| root.get_node_by_key(key))?;
+        Some(f(node))
+    }
+}
+
+/// `EventListeners` holds the callbacks registered by `addEventListener`, keyed by node and event type.
+#[derive(Default, Trace, Finalize, JsData)]
+pub(crate) struct EventListeners(HashMap<ListenerKey, Vec<JsFunction>>);
+
+/// `ListenerKey` is the key of `EventListeners`, which pairs a node with an event type.
+#[derive(PartialEq, Eq, Hash, Trace, Finalize)]
+struct ListenerKey(#[unsafe_ignore_trace] NodeKey, String);
+
+impl EventListeners {
+    pub(crate) fn add(context: &Context, key: NodeKey, event_type: &str, callback: JsFunction) {
+        let mut host_defined = context.realm().host_defined_mut();
+        let listeners = host_defined
+            .get_mut::<EventListeners>()
+            .expect("event listeners are not registered to the runtime");
+        listeners
+            .0
+            .entry(ListenerKey(key, event_type.to_string()))
+            .or_default()
+            .push(callback);
+    }
+
+    fn get(context: &Context, key: NodeKey, event_type: &str) -> Vec<JsFunction> {
+        context
+            .realm()
+            .host_defined()
+            .get::<EventListeners>()
+            .and_then(|listeners| {
+                listeners
+                    .0
+                    .get(&ListenerKey(key, event_type.to_string()))
+                    .cloned()
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// `JsOutput` is the result of a script with its native type kept.
+#[derive(Debug, PartialEq)]
+pub enum JsOutput {
+    Number(f64),
+    Bool(bool),
+    /// `Str` also holds the stringified form of values which have no variant of their own, like objects.
+    Str(String),
+    Null,
+    Undefined,
+}
+
+/// `DEFAULT_LOOP_ITERATION_LIMIT` is the number of iterations a single loop may run before it is aborted with an error.
+pub const DEFAULT_LOOP_ITERATION_LIMIT: u64 = 1_000_000;
 
 #[derive(Debug)]
 pub struct Runtime {
@@ -8,8 +123,20 @@ pub struct Runtime {
 }
 
 impl Runtime {
-    pub fn new() -> Self {
+    pub fn new(dom: Rc<RefCell<Box<Node>>>) -> Self {
         let mut context = Context::default();
+        context
+            .runtime_limits_mut()
+            .set_loop_iteration_limit(DEFAULT_LOOP_ITERATION_LIMIT);
+        context.realm().host_defined_mut().insert(Dom(dom));
+        context
+            .realm()
+            .host_defined_mut()
+            .insert(EventListeners::default());
+        context
+            .realm()
+            .host_defined_mut()
+            .insert(DetachedNodes::default());
         context.register_global_class::<Document>().unwrap();
         context.register_global_class::<Element>().unwrap();
 
@@ -23,17 +150,276 @@ impl Runtime {
         Runtime { context }
     }
 
-    /// `execute` runs a given source in the current context.
+    /// `set_loop_iteration_limit` changes how many iterations a single loop may run.
+    /// A script exceeding the limit fails with an error which cannot be caught by the script, so that e.g. `while (true) {}` does not hang.
+    pub fn set_loop_iteration_limit(&mut self, limit: u64) {
+        self.context
+            .runtime_limits_mut()
+            .set_loop_iteration_limit(limit);
+    }
+
+    /// `execute` runs a given source in the current context and returns the value of the last statement as a string.
+    /// A value which cannot be converted to a string, like a symbol, is an error as well as a thrown exception.
     pub fn execute(&mut self, _filename: &str, source: &str) -> Result<String, String> {
-        match self.context.eval(Source::from_bytes(source)) {
-            Ok(value) => {
-                let value = value
+        let value = self
+            .context
+            .eval(Source::from_bytes(source))
+            .map_err(|error| error.to_string())?;
+        let value = value
+            .to_string(&mut self.context)
+            .map_err(|error| error.to_string())?
+            .to_std_string_escaped();
+        Ok(value)
+    }
+
+    /// `execute_value` runs a given source in the current context like `execute`, but keeps the type of the result.
+    pub fn execute_value(&mut self, _filename: &str, source: &str) -> Result<JsOutput, String> {
+        let value = self
+            .context
+            .eval(Source::from_bytes(source))
+            .map_err(|error| error.to_string())?;
+        let output = match value {
+            JsValue::Null => JsOutput::Null,
+            JsValue::Undefined => JsOutput::Undefined,
+            JsValue::Boolean(b) => JsOutput::Bool(b),
+            JsValue::Integer(n) => JsOutput::Number(n as f64),
+            JsValue::Rational(n) => JsOutput::Number(n),
+            JsValue::String(s) => JsOutput::Str(s.to_std_string_escaped()),
+            value => JsOutput::Str(
+                value
                     .to_string(&mut self.context)
-                    .unwrap()
-                    .to_std_string_escaped();
-                Ok(value)
-            }
-            Err(error) => Err(error.to_string()),
+                    .map_err(|error| error.to_string())?
+                    .to_std_string_escaped(),
+            ),
+        };
+        Ok(output)
+    }
+
+    /// `dispatch_event` invokes the callbacks registered for `event_type` on the element with `id`, in registration order.
+    pub fn dispatch_event(&mut self, id: &str, event_type: &str) -> Result<(), String> {
+        let key = match Dom::get(&self.context).borrow_mut().get_element_by_id(id) {
+            Some(node) => node.key(),
+            None => return Ok(()),
+        };
+        let callbacks = EventListeners::get(&self.context, key, event_type);
+        if callbacks.is_empty() {
+            return Ok(());
+        }
+        let this = Element::from_data(Element { key: Some(key) }, &mut self.context)
+            .map_err(|error| error.to_string())?;
+        for callback in callbacks {
+            callback
+                .call(&JsValue::from(this.clone()), &[], &mut self.context)
+                .map_err(|error| error.to_string())?;
         }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dom::html;
+
+    #[test]
+    fn test_independent_documents() {
+        let dom1 = Rc::new(RefCell::new(
+            html::parse(r#"<body><span id="result">hoge</span></body>"#).unwrap(),
+        ));
+        let dom2 = Rc::new(RefCell::new(
+            html::parse(r#"<body><span id="result">piyo</span></body>"#).unwrap(),
+        ));
+        let mut runtime1 = Runtime::new(dom1.clone());
+        let mut runtime2 = Runtime::new(dom2.clone());
+
+        runtime1
+            .execute(
+                "(test)",
+                r#"document.getElementById("result").innerText = "fuga""#,
+            )
+            .unwrap();
+
+        assert_eq!(
+            runtime1
+                .execute("(test)", r#"document.getElementById("result").innerText"#)
+                .unwrap(),
+            "fuga"
+        );
+        assert_eq!(
+            runtime2
+                .execute("(test)", r#"document.getElementById("result").innerText"#)
+                .unwrap(),
+            "piyo"
+        );
+        assert_eq!(
+            dom1.borrow_mut()
+                .get_element_by_id("result")
+                .unwrap()
+                .inner_text(),
+            "fuga"
+        );
+        assert_eq!(
+            dom2.borrow_mut()
+                .get_element_by_id("result")
+                .unwrap()
+                .inner_text(),
+            "piyo"
+        );
+    }
+
+    #[test]
+    fn test_dispatch_click() {
+        let dom = Rc::new(RefCell::new(
+            html::parse(
+                r#"<body><button id="button">click</button><span id="result">hoge</span></body>"#,
+            )
+            .unwrap(),
+        ));
+        let mut runtime = Runtime::new(dom.clone());
+        runtime
+            .execute(
+                "(test)",
+                r#"document.getElementById("button").addEventListener("click", function () {
+                    document.getElementById("result").innerText = this.tagName;
+                })"#,
+            )
+            .unwrap();
+        assert_eq!(
+            dom.borrow_mut()
+                .get_element_by_id("result")
+                .unwrap()
+                .inner_text(),
+            "hoge"
+        );
+
+        runtime.dispatch_event("result", "click").unwrap();
+        runtime.dispatch_event("button", "mouseover").unwrap();
+        assert_eq!(
+            dom.borrow_mut()
+                .get_element_by_id("result")
+                .unwrap()
+                .inner_text(),
+            "hoge"
+        );
+
+        runtime.dispatch_event("button", "click").unwrap();
+        assert_eq!(
+            dom.borrow_mut()
+                .get_element_by_id("result")
+                .unwrap()
+                .inner_text(),
+            "button"
+        );
+    }
+
+    #[test]
+    fn test_remove_element() {
+        let dom = Rc::new(RefCell::new(
+            html::parse(r#"<body><span id="result">hoge</span><p>fuga</p></body>"#).unwrap(),
+        ));
+        let mut runtime = Runtime::new(dom.clone());
+        runtime
+            .execute("(test)", r#"document.getElementById("result").remove()"#)
+            .unwrap();
+        assert!(dom.borrow_mut().get_element_by_id("result").is_none());
+        assert_eq!(dom.borrow().children.len(), 1);
+    }
+
+    #[test]
+    fn test_query_selector() {
+        let dom = Rc::new(RefCell::new(
+            html::parse(r#"<body><p>hoge</p><span class="inline">fuga</span></body>"#).unwrap(),
+        ));
+        let mut runtime = Runtime::new(dom.clone());
+        assert_eq!(
+            runtime
+                .execute("(test)", r#"document.querySelector('.inline').tagName"#)
+                .unwrap(),
+            "span"
+        );
+        runtime
+            .execute(
+                "(test)",
+                r#"document.querySelector('.inline').innerText = "piyo""#,
+            )
+            .unwrap();
+        assert_eq!(dom.borrow().children[1].inner_text(), "piyo");
+        // the query leaves the attributes as they are
+        assert_eq!(
+            dom.borrow().children[1].outer_html(),
+            r#"<span class="inline">piyo</span>"#
+        );
+        assert_eq!(
+            runtime
+                .execute("(test)", r#"document.querySelector('div')"#)
+                .unwrap(),
+            "null"
+        );
+        assert!(runtime
+            .execute("(test)", r#"document.querySelector('{')"#)
+            .is_err());
+    }
+
+    #[test]
+    fn test_execute() {
+        let dom = Rc::new(RefCell::new(html::parse("<body></body>").unwrap()));
+        let mut runtime = Runtime::new(dom);
+        assert_eq!(
+            runtime.execute("(test)", "let a = 1; a + 1;"),
+            Ok("2".to_string())
+        );
+        assert!(runtime.execute("(test)", "Symbol()").is_err());
+        assert!(runtime.execute("(test)", "throw new Error('e')").is_err());
+    }
+
+    #[test]
+    fn test_execute_value() {
+        let dom = Rc::new(RefCell::new(html::parse("<body></body>").unwrap()));
+        let mut runtime = Runtime::new(dom);
+        assert_eq!(
+            runtime.execute_value("(test)", "1 + 2"),
+            Ok(JsOutput::Number(3.0))
+        );
+        assert_eq!(
+            runtime.execute_value("(test)", "1 / 2"),
+            Ok(JsOutput::Number(0.5))
+        );
+        assert_eq!(
+            runtime.execute_value("(test)", "true"),
+            Ok(JsOutput::Bool(true))
+        );
+        assert_eq!(
+            runtime.execute_value("(test)", r#""x""#),
+            Ok(JsOutput::Str("x".to_string()))
+        );
+        assert_eq!(
+            runtime.execute_value("(test)", r#""42""#),
+            Ok(JsOutput::Str("42".to_string()))
+        );
+        assert_eq!(runtime.execute_value("(test)", "null"), Ok(JsOutput::Null));
+        assert_eq!(
+            runtime.execute_value("(test)", "undefined"),
+            Ok(JsOutput::Undefined)
+        );
+        assert!(runtime.execute_value("(test)", "throw 1").is_err());
+    }
+
+    #[test]
+    fn test_loop_iteration_limit() {
+        let dom = Rc::new(RefCell::new(html::parse("<body></body>").unwrap()));
+        let mut runtime = Runtime::new(dom);
+        assert!(runtime.execute("(test)", "while (true) {}").is_err());
+
+        runtime.set_loop_iteration_limit(10);
+        assert!(runtime
+            .execute("(test)", "try { for (;;) {} } catch (e) {}")
+            .is_err());
+        assert_eq!(
+            runtime.execute_value("(test)", "let i = 0; while (i < 5) { i++; } i"),
+            Ok(JsOutput::Number(5.0))
+        );
+        assert!(runtime
+            .execute("(test)", "let j = 0; while (j < 100) { j++; }")
+            .is_err());
     }
 }
@@ -0,0 +1,192 @@
+//! This module includes fetching of the external resources referred from a document.
+
+use anyhow::{anyhow, bail, Result};
+use dom::{
+    css::{self, Stylesheet},
+    dom::{Node, NodeType},
+};
+use std::{future::Future, pin::Pin};
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+
+/// `Fetcher` retrieves the content at `url` as a text.
+pub trait Fetcher {
+    #[allow(async_fn_in_trait)]
+    async fn fetch(&self, url: &str) -> Result<String>;
+}
+
+/// `WindowFetcher` fetches resources with `window.fetch()` of the browser.
+pub struct WindowFetcher;
+
+impl Fetcher for WindowFetcher {
+    async fn fetch(&self, url: &str) -> Result<String> {
+        let window = web_sys::window().ok_or_else(|| anyhow!("window is not available"))?;
+        let response = JsFuture::from(window.fetch_with_str(url))
+            .await
+            .map_err(|e| anyhow!("failed to fetch {}: {:?}", url, e))?;
+        let response: web_sys::Response = response
+            .dyn_into()
+            .map_err(|e| anyhow!("unexpected response of {}: {:?}", url, e))?;
+        if !response.ok() {
+            bail!("failed to fetch {}: status {}", url, response.status());
+        }
+        let text = response
+            .text()
+            .map_err(|e| anyhow!("failed to read {}: {:?}", url, e))?;
+        JsFuture::from(text)
+            .await
+            .map_err(|e| anyhow!("failed to read {}: {:?}", url, e))?
+            .as_string()
+            .ok_or_else(|| anyhow!("the body of {} is not a text", url))
+    }
+}
+
+/// `stylesheet_links` returns `href` of `<link rel="stylesheet">` in document order.
+pub fn stylesheet_links(node: &Node) -> Vec<String> {
+    let mut hrefs = vec![];
+    if let NodeType::Element(ref element) = node.node_type {
+        let is_stylesheet = element.tag_name.eq_ignore_ascii_case("link")
+            && element
+                .attributes
+                .get("rel")
+                .is_some_and(|rel| rel.split_whitespace().any(|r| r == "stylesheet"));
+        if let (true, Some(href)) = (is_stylesheet, element.attributes.get("href")) {
+            hrefs.push(href.clone());
+        }
+    }
+    for child in &node.children {
+        hrefs.extend(stylesheet_links(child));
+    }
+    hrefs
+}
+
+/// `MAX_IMPORT_DEPTH` limits how deep `@import` is followed, which also stops cyclic imports.
+const MAX_IMPORT_DEPTH: usize = 8;
+
+/// `load_stylesheets` fetches and parses `hrefs`, merging them in order.
+/// A stylesheet which fails to be fetched or parsed is skipped.
+pub async fn load_stylesheets(fetcher: &impl Fetcher, hrefs: &[String]) -> Stylesheet {
+    load_stylesheets_in(fetcher, hrefs, 0).await
+}
+
+/// `load_stylesheets_in` loads `hrefs` imported at `depth`, where the links of the document are at 0.
+/// The rules of `@import` of each stylesheet are put before its own rules.
+fn load_stylesheets_in<'a, F: Fetcher>(
+    fetcher: &'a F,
+    hrefs: &'a [String],
+    depth: usize,
+) -> Pin<Box<dyn Future<Output = Stylesheet> + 'a>> {
+    Box::pin(async move {
+        let mut stylesheet = Stylesheet::new(vec![]);
+        for href in hrefs {
+            let loaded = match fetcher.fetch(href).await {
+                Ok(raw) => css::parse(&raw),
+                Err(e) => Err(e),
+            };
+            let mut loaded = match loaded {
+                Ok(loaded) => loaded,
+                Err(e) => {
+                    log::warn!("skipped stylesheet {}: {}", href, e);
+                    continue;
+                }
+            };
+            let imports = std::mem::take(&mut loaded.imports);
+            if depth < MAX_IMPORT_DEPTH {
+                let imported = load_stylesheets_in(fetcher, &imports, depth + 1).await;
+                stylesheet = stylesheet.merge(imported);
+            } else if !imports.is_empty() {
+                log::warn!("skipped imports of {}: too deeply nested", href);
+            }
+            stylesheet = stylesheet.merge(loaded);
+        }
+        stylesheet
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dom::html;
+    use std::{
+        collections::HashMap,
+        pin::pin,
+        task::{Context, Poll, Waker},
+    };
+
+    struct StubFetcher(HashMap<&'static str, &'static str>);
+
+    impl Fetcher for StubFetcher {
+        async fn fetch(&self, url: &str) -> Result<String> {
+            self.0
+                .get(url)
+                .map(|s| s.to_string())
+                .ok_or_else(|| anyhow!("{} not found", url))
+        }
+    }
+
+    /// `block_on` runs a future which never waits for anything.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut context = Context::from_waker(Waker::noop());
+        match pin!(future).poll(&mut context) {
+            Poll::Ready(output) => output,
+            Poll::Pending => panic!("the future is pending"),
+        }
+    }
+
+    #[test]
+    fn test_stylesheet_links() {
+        let node = html::parse(
+            r#"<html><head><link rel="stylesheet" href="a.css"><link rel="icon" href="favicon.ico"></head><body><link rel="alternate stylesheet" href="b.css"><LINK rel="stylesheet" href="c.css"></body></html>"#,
+        )
+        .unwrap();
+        assert_eq!(stylesheet_links(&node), vec!["a.css", "b.css", "c.css"]);
+    }
+
+    #[test]
+    fn test_load_stylesheets() {
+        let fetcher = StubFetcher(
+            [
+                ("a.css", "p {\n  display: block;\n}\n"),
+                ("b.css", "p { display: inline; }"),
+                ("broken.css", "p {"),
+            ]
+            .into_iter()
+            .collect(),
+        );
+        let hrefs = ["a.css", "missing.css", "broken.css", "b.css"].map(String::from);
+        let stylesheet = block_on(load_stylesheets(&fetcher, &hrefs));
+        assert_eq!(
+            stylesheet,
+            css::parse("p { display: block; } p { display: inline; }").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_load_imported_stylesheets() {
+        let fetcher = StubFetcher(
+            [
+                (
+                    "main.css",
+                    r#"@import "base.css"; @import "missing.css"; p { display: inline; }"#,
+                ),
+                (
+                    "base.css",
+                    "@import \"reset.css\";\n\np {\n  display: block;\n}\n",
+                ),
+                ("reset.css", "div { display: block; }"),
+                ("cycle.css", r#"@import "cycle.css"; p { display: block; }"#),
+            ]
+            .into_iter()
+            .collect(),
+        );
+        let stylesheet = block_on(load_stylesheets(&fetcher, &["main.css".to_string()]));
+        assert_eq!(
+            stylesheet,
+            css::parse("div { display: block; } p { display: block; } p { display: inline; }")
+                .unwrap()
+        );
+
+        let stylesheet = block_on(load_stylesheets(&fetcher, &["cycle.css".to_string()]));
+        assert_eq!(stylesheet.rules.len(), MAX_IMPORT_DEPTH + 1);
+    }
+}
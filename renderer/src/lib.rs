@@ -1,79 +1,71 @@
+use crate::fetch::{load_stylesheets, stylesheet_links, WindowFetcher};
 use crate::paint::paint;
 use anyhow::Result;
-use dom::{
-    css,
-    dom::{Node, NodeType},
-    html,
-    layout::LayoutBox,
-    style::to_styled_node,
-};
-use engine::{runtime::Runtime, DOM};
+use dom::css;
 use js_sys::wasm_bindgen;
 use paint::CanvasAPI;
-use util::Point;
 use wasm_bindgen::{prelude::*, JsValue};
 
-mod paint;
-mod util;
+mod fetch;
+pub mod paint;
+pub mod render;
 
-fn collect_tag_inners(node: &Box<Node>, tag_name: &str) -> Vec<String> {
-    if let NodeType::Element(ref element) = node.node_type {
-        if element.tag_name.as_str() == tag_name {
-            return vec![node.inner_text()];
-        }
-    }
-
-    node.children
-        .iter()
-        .map(|child| collect_tag_inners(child, tag_name))
-        .collect::<Vec<Vec<String>>>()
-        .into_iter()
-        .flatten()
-        .collect()
-}
-
-fn execute_inline_scripts(runtime: &mut Runtime) -> Result<String, String> {
-    let scripts = {
-        let document_element = DOM.try_lock().unwrap();
-        collect_tag_inners(&document_element, "script".into()).join("\n")
-    };
-    runtime.execute("(inline)", scripts.as_str())
-}
-
-fn run(html: &str, css: &str) -> Result<()> {
-    {
-        let mut dom = DOM.try_lock().unwrap();
-        *dom = html::parse(&html);
-    }
+async fn run(html: &str, css: &str) -> Result<()> {
+    let dom = render::load(html)?;
 
-    let mut runtime = Runtime::new();
-    let result = execute_inline_scripts(&mut runtime).map_err(|e| anyhow::anyhow!(e))?;
-    log::info!("Result: {}", result);
+    let hrefs = stylesheet_links(&dom.borrow());
+    let mut author = css::parse(css)?;
+    let imports = std::mem::take(&mut author.imports);
+    let stylesheet = load_stylesheets(&WindowFetcher, &hrefs)
+        .await
+        .merge(load_stylesheets(&WindowFetcher, &imports).await)
+        .merge(author);
 
-    let style = css::parse(css)?;
-
-    let dom = DOM.try_lock().unwrap();
-    let Some(styled_node) = to_styled_node(&dom, &style) else {
-        return Err(anyhow::anyhow!("Failed to style node"));
-    };
-    let layout_box = LayoutBox::new(styled_node);
+    let dom = dom.borrow();
     let canvas = CanvasAPI::new();
     canvas.clear();
-    paint(&Point { x: 0., y: 0. }, &canvas, &layout_box);
+    render::render(&dom, &stylesheet, &canvas, canvas.width(), |layout_box| {
+        paint(&canvas, layout_box)
+    })
+}
 
-    Ok(())
+/// `level_filter` parses a log level like `"debug"`, falling back to `info` if it is invalid.
+fn level_filter(level: &str) -> log::LevelFilter {
+    level.parse().unwrap_or(log::LevelFilter::Info)
 }
 
+/// `setup` initializes the logger to print the logs of `level` or more severe.
 #[wasm_bindgen]
-pub fn setup() {
-    wasm_logger::init(wasm_logger::Config::default());
+pub fn setup(level: &str) {
+    let filter = level_filter(level);
+    wasm_logger::init(wasm_logger::Config::new(
+        filter.to_level().unwrap_or(log::Level::Error),
+    ));
+    log::set_max_level(filter);
 }
 
 #[wasm_bindgen]
-pub fn render(html: &str, css: &str) -> Result<(), JsValue> {
-    match run(html, css) {
+pub async fn render(html: String, css: String) -> Result<(), JsValue> {
+    match run(&html, &css).await {
         Ok(_) => log::info!("Success"),
         Err(e) => log::error!("{}", e),
     };
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::LevelFilter;
+
+    #[test]
+    fn test_level_filter() {
+        assert_eq!(level_filter("debug"), LevelFilter::Debug);
+        assert_eq!(level_filter("info"), LevelFilter::Info);
+        assert_eq!(level_filter("warn"), LevelFilter::Warn);
+        assert_eq!(level_filter("error"), LevelFilter::Error);
+        assert_eq!(level_filter("ERROR"), LevelFilter::Error);
+        assert_eq!(level_filter("verbose"), LevelFilter::Info);
+        assert_eq!(level_filter(""), LevelFilter::Info);
+    }
+}
@@ -1,16 +1,28 @@
-use crate::util::Point;
 use dom::{
+    color::Color,
+    css::{CSSValue, Unit},
     dom::NodeType,
-    layout::{BoxType, LayoutBox},
+    layout::{BoxProps, BoxType, Dimensions, LayoutBox},
+    util::Point,
 };
 use std::f64;
 use wasm_bindgen::prelude::*;
 
+const DEFAULT_FONT_FAMILY: &str = "serif";
+/// `DEFAULT_FONT_SIZE` is the font size of the root in pixels, i.e. `medium`.
+const DEFAULT_FONT_SIZE: f64 = 16.;
+
 pub struct CanvasAPI {
     canvas: web_sys::HtmlCanvasElement,
     context: web_sys::CanvasRenderingContext2d,
 }
 
+impl Default for CanvasAPI {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl CanvasAPI {
     pub fn new() -> Self {
         let document = web_sys::window().unwrap().document().unwrap();
@@ -27,11 +39,16 @@ impl CanvasAPI {
             .dyn_into::<web_sys::CanvasRenderingContext2d>()
             .unwrap();
 
-        context.set_font("42px serif");
+        context.set_font(&format!("42px {}", DEFAULT_FONT_FAMILY));
 
         Self { canvas, context }
     }
 
+    /// `width` is the width of the canvas, which is the viewport of the document.
+    pub fn width(&self) -> f64 {
+        self.canvas.width() as f64
+    }
+
     pub fn clear(&self) {
         self.context.clear_rect(
             0.0,
@@ -41,116 +58,1248 @@ impl CanvasAPI {
         );
     }
 
-    /// 四角形を描画する
-    pub fn draw_rect(&self, pos: &Point, w: f64, h: f64) {
-        self.context.stroke_rect(pos.x, pos.y, w, h)
+    fn set_font_family(&self, font_family: &str) {
+        self.context.set_font(&format!("42px {}", font_family));
     }
+}
 
-    pub fn get_text_size(&self, text: &str) -> (f64, f64) {
+/// `TextMeasurer` measures the size of a text as it is drawn.
+pub trait TextMeasurer {
+    /// `measure_text` returns the width and the height of `text` drawn in `font_family`.
+    fn measure_text(&self, text: &str, font_family: &str) -> (f64, f64);
+}
+
+/// `Painter` is a backend which `paint` draws a laid out box tree with.
+/// Texts are measured with `TextMeasurer` so that they are drawn as large as they are laid out.
+pub trait Painter: TextMeasurer {
+    /// `fill_rect` fills the rectangle whose top-left corner is `pos` with `color`.
+    fn fill_rect(&self, pos: &Point, w: f64, h: f64, color: &Color);
+    /// `stroke_rect` draws the outline of the rectangle whose top-left corner is `pos`.
+    fn stroke_rect(&self, pos: &Point, w: f64, h: f64);
+    /// `draw_text` draws `text` so that the top-left corner of the glyphs is at `tl`.
+    fn draw_text(&self, tl: &Point, text: &str, font_family: &str, color: &Color);
+    /// `draw_line` draws a line segment from `from` to `to`.
+    fn draw_line(&self, from: &Point, to: &Point);
+    /// `push_clip` restricts the drawings to the rectangle, in addition to the clips pushed before, until `pop_clip`.
+    fn push_clip(&self, pos: &Point, w: f64, h: f64);
+    /// `pop_clip` removes the clip pushed last.
+    fn pop_clip(&self);
+    /// `push_opacity` multiplies the alpha of the drawings by `alpha` until `pop_opacity`.
+    fn push_opacity(&self, alpha: f64);
+    /// `pop_opacity` removes the opacity pushed last.
+    fn pop_opacity(&self);
+}
+
+impl TextMeasurer for CanvasAPI {
+    fn measure_text(&self, text: &str, font_family: &str) -> (f64, f64) {
+        self.set_font_family(font_family);
         let m = self.context.measure_text(text).unwrap();
         (
             m.width(),
             m.actual_bounding_box_ascent() + m.actual_bounding_box_descent(),
         )
     }
+}
+
+impl Painter for CanvasAPI {
+    fn fill_rect(&self, pos: &Point, w: f64, h: f64, color: &Color) {
+        self.context
+            .set_fill_style(&JsValue::from_str(&color.to_canvas_style()));
+        self.context.fill_rect(pos.x, pos.y, w, h)
+    }
+
+    /// 四角形を描画する
+    fn stroke_rect(&self, pos: &Point, w: f64, h: f64) {
+        self.context.stroke_rect(pos.x, pos.y, w, h)
+    }
 
     /// テキストを描画する
-    pub fn draw_text(&self, tl: &Point, text: &str) {
-        let h = self.get_text_size(text).1;
+    fn draw_text(&self, tl: &Point, text: &str, font_family: &str, color: &Color) {
+        let h = self.measure_text(text, font_family).1;
+        self.context
+            .set_fill_style(&JsValue::from_str(&color.to_canvas_style()));
         self.context.fill_text(text, tl.x, tl.y + h).unwrap();
     }
+
+    /// 線分を描画する
+    fn draw_line(&self, from: &Point, to: &Point) {
+        self.context.begin_path();
+        self.context.move_to(from.x, from.y);
+        self.context.line_to(to.x, to.y);
+        self.context.stroke();
+    }
+
+    fn push_clip(&self, pos: &Point, w: f64, h: f64) {
+        self.context.save();
+        self.context.begin_path();
+        self.context.rect(pos.x, pos.y, w, h);
+        self.context.clip();
+    }
+
+    fn pop_clip(&self) {
+        self.context.restore();
+    }
+
+    fn push_opacity(&self, alpha: f64) {
+        self.context.save();
+        // `globalAlpha` is not multiplied by `save`, so the opacity of the ancestors is applied here
+        self.context
+            .set_global_alpha(self.context.global_alpha() * alpha);
+    }
+
+    fn pop_opacity(&self) {
+        self.context.restore();
+    }
+}
+
+/// `font_family` returns the first family of `font-family`, which is the one to draw with.
+/// A family name given as a string is quoted so that it can be put into the `font` shorthand as it is.
+///
+/// NOTE: it does not fall back to the later families even if the first one is not available.
+fn font_family(props: &BoxProps) -> String {
+    let value = match props.properties.get("font-family") {
+        Some(CSSValue::List(values)) => values.first(),
+        value => value,
+    };
+    match value {
+        Some(CSSValue::Keyword(family)) => family.clone(),
+        Some(CSSValue::String(family)) => format!("{:?}", family),
+        _ => DEFAULT_FONT_FAMILY.to_string(),
+    }
+}
+
+/// `text_color` resolves `color` of a box. Texts are black unless a color or a named color is given.
+fn text_color(props: &BoxProps) -> Color {
+    props
+        .properties
+        .get("color")
+        .and_then(CSSValue::to_color)
+        .unwrap_or(Color::from_rgb(0, 0, 0))
+}
+
+/// `background_color` returns `background-color` of an element, or `None` if it is not given.
+fn background_color(props: &BoxProps) -> Option<Color> {
+    if !matches!(*props.node_type, NodeType::Element(_)) {
+        return None;
+    }
+    props
+        .properties
+        .get("background-color")
+        .and_then(CSSValue::to_color)
+}
+
+/// `opacity` returns `opacity` of an element clamped to 0-1, or `None` if it is not given.
+fn opacity(props: &BoxProps) -> Option<f64> {
+    if !matches!(*props.node_type, NodeType::Element(_)) {
+        return None;
+    }
+    match props.properties.get("opacity") {
+        Some(CSSValue::Number(n)) => Some(n.clamp(0., 1.)),
+        _ => None,
+    }
 }
 
-fn calc_size(canvas: &CanvasAPI, layout_box: &LayoutBox) -> (f64, f64) {
-    let (text_width, text_height) = match &layout_box.box_type {
-        BoxType::BlockBox(b) | BoxType::InlineBox(b) => match b.node_type {
-            NodeType::Text(text) => canvas.get_text_size(text.data.as_str()),
-            NodeType::Element(_) => (0.0, 0.0),
+/// `line_height` resolves `line-height` of a box whose glyphs are `glyph_height` tall.
+/// `em` is resolved against `font_size`, the font size of the box.
+fn line_height(props: &BoxProps, glyph_height: f64, font_size: f64) -> f64 {
+    match props.properties.get("line-height") {
+        Some(CSSValue::Number(n)) => glyph_height * n,
+        Some(CSSValue::Length(l, unit)) => to_px(*l, *unit, font_size),
+        _ => glyph_height,
+    }
+}
+
+/// `measure` caches the size of the contents of `layout_box` and its descendants to `content_size`.
+/// `parent_font_size` is the font size of the parent in pixels.
+fn measure(
+    measurer: &impl TextMeasurer,
+    layout_box: &mut LayoutBox,
+    parent_font_size: f64,
+) -> (f64, f64) {
+    if let Some(size) = layout_box.content_size {
+        return size;
+    }
+    let font_size = box_font_size(layout_box, parent_font_size);
+    for child in &mut layout_box.children {
+        measure(measurer, child, font_size);
+    }
+    let size = calc_size(measurer, layout_box, parent_font_size);
+    layout_box.content_size = Some(size);
+    size
+}
+
+/// `calc_size` returns the size of the contents of `layout_box`.
+/// It reads `content_size` cached by `measure` if any.
+/// `parent_font_size` is the font size of the parent in pixels, which `em` is resolved against.
+pub fn calc_size(
+    measurer: &impl TextMeasurer,
+    layout_box: &LayoutBox,
+    parent_font_size: f64,
+) -> (f64, f64) {
+    if let Some(size) = layout_box.content_size {
+        return size;
+    }
+    let font_size = box_font_size(layout_box, parent_font_size);
+    let (text_width, text_height) = match layout_box.box_type.get_props() {
+        Some(props) => match &*props.node_type {
+            NodeType::Text(text) => {
+                let (w, h) = measurer.measure_text(text.data.as_str(), &font_family(props));
+                (w, line_height(props, h, font_size))
+            }
+            // a line break is as tall as a line
+            NodeType::Element(_) if is_line_break(layout_box) => {
+                let (_, h) = measurer.measure_text("M", &font_family(props));
+                (0.0, line_height(props, h, font_size))
+            }
+            NodeType::Element(_) | NodeType::Comment(_) => (0.0, 0.0),
         },
-        BoxType::AnonymousBox => (0.0, 0.0),
+        None => (0.0, 0.0),
     };
     // height: 一行のheightの最大値を計算,各行のheightの合計
     // width: 各行のwidthの最大値
-    let mut max_height_in_row = text_height;
-    let mut cur_width = text_width;
-    let (mut height, mut width) = (0f64, text_width);
+    // block boxは単独で一行を占める
+    let (mut row_width, mut row_height) = (text_width, text_height);
+    let (mut width, mut height) = (text_width, 0f64);
     for child in &layout_box.children {
-        let (ch_w, ch_h) = calc_size(canvas, child);
-        match child.box_type {
-            BoxType::AnonymousBox | BoxType::InlineBox(_) => {
-                cur_width += ch_w;
-                max_height_in_row = max_height_in_row.max(ch_h);
-            }
-            BoxType::BlockBox(_) => {
-                height += max_height_in_row;
-                max_height_in_row = 0f64;
-                cur_width = ch_w;
-            }
+        let (ch_w, ch_h) = calc_size(measurer, child, font_size);
+        if is_line_break(child) {
+            height += row_height.max(ch_h);
+            width = width.max(row_width);
+            (row_width, row_height) = (0f64, 0f64);
+            continue;
         }
-        width = width.max(cur_width);
+        if child.box_type.is_inline() {
+            row_width += ch_w;
+            row_height = row_height.max(ch_h);
+        } else {
+            height += row_height + ch_h;
+            (row_width, row_height) = (0f64, 0f64);
+            width = width.max(ch_w);
+        }
+        width = width.max(row_width);
     }
-    height += max_height_in_row;
+    height += row_height;
     (width, height)
 }
 
+/// `is_line_break` tells whether `layout_box` is a `<br>`, which ends the row it is in.
+fn is_line_break(layout_box: &LayoutBox) -> bool {
+    matches!(
+        layout_box.box_type.get_props().map(|props| &*props.node_type),
+        Some(NodeType::Element(e)) if e.tag_name == "br"
+    )
+}
+
+/// `TextAlign` is the horizontal alignment of the rows in a block container.
+/// See https://www.w3.org/TR/css-text-3/#text-align-property
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum TextAlign {
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+impl TextAlign {
+    fn from_props(props: &BoxProps) -> Option<Self> {
+        match props.properties.get("text-align") {
+            Some(CSSValue::Keyword(s)) => match s.as_str() {
+                "left" => Some(TextAlign::Left),
+                "center" => Some(TextAlign::Center),
+                "right" => Some(TextAlign::Right),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// `offset` returns how far a row is shifted when `space` is left over in its container.
+    fn offset(&self, space: f64) -> f64 {
+        let offset = match self {
+            TextAlign::Left => 0.,
+            TextAlign::Center => space / 2.,
+            TextAlign::Right => space,
+        };
+        offset.max(0.)
+    }
+}
+
+/// `ContainingBlock` is what a box is laid out in.
+#[derive(Debug, Clone, Default)]
+struct ContainingBlock {
+    width: f64,
+    /// `text_align` and `nowrap` are used by the boxes which have no properties, i.e. anonymous boxes.
+    text_align: TextAlign,
+    /// `nowrap` is true under `white-space: nowrap`, where rows are never broken.
+    nowrap: bool,
+    /// `font_size` is the font size in pixels inherited from the containing block, which `em` is resolved against.
+    font_size: f64,
+}
+
+/// `layout` lays out `layout_box` as the root of a document shown in a viewport of `viewport_width`.
+pub fn layout(measurer: &impl TextMeasurer, layout_box: &mut LayoutBox, viewport_width: f64) {
+    layout_in(
+        measurer,
+        layout_box,
+        &Point { x: 0., y: 0. },
+        &ContainingBlock {
+            width: viewport_width,
+            font_size: DEFAULT_FONT_SIZE,
+            ..Default::default()
+        },
+    );
+}
+
+/// `layout_in` computes `dimensions` of `layout_box` placed at `pos` and its descendants.
+///
 /// BoxType
-/// - BlockBox, AnonymousBox: 改行(= x座標をpos.xに戻す)して描画する
-/// - InlineBox: x座標はそのままで描画する
+/// - BlockBox, AnonymousBox: 改行(= x座標をpos.xに戻す)して配置する. 幅はcontaining blockの幅
+/// - InlineBox, InlineBlockBox: x座標はそのままで配置する. 幅は中身の幅
+fn layout_in(
+    measurer: &impl TextMeasurer,
+    layout_box: &mut LayoutBox,
+    pos: &Point,
+    containing_block: &ContainingBlock,
+) {
+    let (content_width, content_height) = measure(measurer, layout_box, containing_block.font_size);
+    let props = layout_box.box_type.get_props();
+    let font_size = props.map_or(containing_block.font_size, |props| {
+        resolve_font_size(props, containing_block.font_size)
+    });
+    let specified_width =
+        props.and_then(|props| size_px(props, "width", containing_block.width, font_size));
+    // padding applies to block containers, i.e. not to inline boxes
+    let [padding_top, padding_right, padding_bottom, padding_left] = match &layout_box.box_type {
+        BoxType::BlockBox(props) | BoxType::InlineBlockBox(props) => {
+            ["top", "right", "bottom", "left"].map(|side| {
+                size_px(
+                    props,
+                    &format!("padding-{}", side),
+                    containing_block.width,
+                    font_size,
+                )
+                .unwrap_or(0.)
+            })
+        }
+        _ => [0.; 4],
+    };
+    let padding_x = padding_left + padding_right;
+    // `width`, `min-width` and `max-width` include the padding under `box-sizing: border-box`
+    // See https://www.w3.org/TR/css-sizing-3/#box-sizing
+    let border_box = props.is_some_and(|props| {
+        props.properties.get("box-sizing") == Some(&CSSValue::Keyword("border-box".to_string()))
+    });
+    let sizing_padding = if border_box { padding_x } else { 0. };
+    let width = match layout_box.box_type {
+        BoxType::BlockBox(_) => {
+            specified_width.unwrap_or(containing_block.width - padding_x + sizing_padding)
+        }
+        BoxType::InlineBlockBox(_) => specified_width.unwrap_or(content_width + sizing_padding),
+        BoxType::AnonymousBox => containing_block.width,
+        // `width` does not apply to inline boxes
+        BoxType::InlineBox(_) => content_width,
+    };
+    let width = match (&layout_box.box_type, props) {
+        (BoxType::BlockBox(_) | BoxType::InlineBlockBox(_), Some(props)) => {
+            clamp_width(props, width, containing_block.width, font_size)
+        }
+        _ => width,
+    };
+    // from here on, `width` is the width of the content box, which the children are laid out in
+    let width = (width - sizing_padding).max(0.);
+    let text_align = layout_box
+        .box_type
+        .get_props()
+        .and_then(TextAlign::from_props)
+        .unwrap_or(containing_block.text_align);
+    let nowrap = match layout_box.box_type.get_props() {
+        Some(props) => {
+            props.properties.get("white-space") == Some(&CSSValue::Keyword("nowrap".to_string()))
+        }
+        None => containing_block.nowrap,
+    };
+    // rows of an inline box are broken and aligned by the block container of the inline box
+    let is_block_container = !matches!(layout_box.box_type, BoxType::InlineBox(_));
+    let child_containing_block = ContainingBlock {
+        width,
+        text_align,
+        nowrap,
+        font_size,
+    };
+
+    let content_pos = Point {
+        x: pos.x + padding_left,
+        y: pos.y + padding_top,
+    };
+    let children = &mut layout_box.children;
+    let mut child_pos = content_pos.clone();
+    let mut i = 0;
+    while i < children.len() {
+        if !children[i].box_type.is_inline() {
+            layout_in(
+                measurer,
+                &mut children[i],
+                &child_pos,
+                &child_containing_block,
+            );
+            child_pos.y += children[i].dimensions.height;
+            i += 1;
+            continue;
+        }
+
+        // 連続するinline boxを一行に詰める. 幅を超える場合やbrの後は次の行に送る
+        let mut end = i;
+        let mut row_width = 0.;
+        while end < children.len() && children[end].box_type.is_inline() {
+            let (ch_w, _) = calc_size(measurer, &children[end], font_size);
+            if is_block_container && !nowrap && end > i && row_width + ch_w > width {
+                break;
+            }
+            row_width += ch_w;
+            end += 1;
+            if is_line_break(&children[end - 1]) {
+                break;
+            }
+        }
+        if is_block_container {
+            child_pos.x += text_align.offset(width - row_width);
+        }
+        let mut row_height = 0f64;
+        for child in &mut children[i..end] {
+            layout_in(measurer, child, &child_pos, &child_containing_block);
+            child_pos.x += child.dimensions.width;
+            row_height = row_height.max(child.dimensions.height);
+        }
+        child_pos.x = content_pos.x;
+        child_pos.y += row_height;
+        i = end;
+    }
+
+    let height = if children.is_empty() {
+        content_height
+    } else {
+        child_pos.y - content_pos.y
+    };
+    layout_box.dimensions = Dimensions {
+        x: pos.x,
+        y: pos.y,
+        width: width + padding_x,
+        height: height + padding_top + padding_bottom,
+    };
+
+    // a relatively positioned box is shifted after the layout, so that its siblings are not affected
+    if let Some(props) = layout_box.box_type.get_props() {
+        if props.properties.get("position") == Some(&CSSValue::Keyword("relative".to_string())) {
+            let dx = length_px(props, "left", font_size).unwrap_or(0.);
+            let dy = length_px(props, "top", font_size).unwrap_or(0.);
+            translate(layout_box, dx, dy);
+        }
+    }
+}
+
+/// `resolve_font_size` returns the font size of a box in pixels, where `em` and `%` are relative to `parent_font_size`.
+/// A keyword like `medium` keeps `parent_font_size`.
+/// See https://www.w3.org/TR/css-fonts-4/#font-size-prop
+fn resolve_font_size(props: &BoxProps, parent_font_size: f64) -> f64 {
+    match props.properties.get("font-size") {
+        Some(CSSValue::Length(l, unit)) => to_px(*l, *unit, parent_font_size),
+        Some(CSSValue::Percentage(p)) => parent_font_size * p / 100.,
+        _ => parent_font_size,
+    }
+}
+
+/// `box_font_size` returns the font size of `layout_box` in pixels.
+/// A text has no style of its own, so it takes `parent_font_size` as it is.
+fn box_font_size(layout_box: &LayoutBox, parent_font_size: f64) -> f64 {
+    match layout_box.box_type.get_props() {
+        Some(props) if matches!(*props.node_type, NodeType::Element(_)) => {
+            resolve_font_size(props, parent_font_size)
+        }
+        _ => parent_font_size,
+    }
+}
+
+/// `to_px` converts a length of `unit` to pixels, where `em` is relative to `font_size`.
+fn to_px(length: f64, unit: Unit, font_size: f64) -> f64 {
+    match unit {
+        Unit::Px => length,
+        Unit::Em => length * font_size,
+    }
+}
+
+/// `length_px` returns the value of the property `name` in pixels if it is a length.
+/// `font_size` is the font size of the box, which `em` is resolved against.
+fn length_px(props: &BoxProps, name: &str, font_size: f64) -> Option<f64> {
+    match props.properties.get(name) {
+        Some(CSSValue::Length(l, unit)) => Some(to_px(*l, *unit, font_size)),
+        _ => None,
+    }
+}
+
+/// `size_px` resolves a horizontal size like `width` against the width of the containing block, `containing_width`.
+/// `em` is resolved against `font_size`. It returns `None` for `auto`, `none` or an unsupported value.
+fn size_px(props: &BoxProps, name: &str, containing_width: f64, font_size: f64) -> Option<f64> {
+    match props.properties.get(name) {
+        Some(CSSValue::Length(l, unit)) => Some(to_px(*l, *unit, font_size)),
+        Some(CSSValue::Percentage(p)) => Some(containing_width * p / 100.),
+        _ => None,
+    }
+}
+
+/// `clamp_width` limits `width` by `max-width` and then by `min-width`, so that `min-width` wins when it is larger.
+/// See https://www.w3.org/TR/CSS2/visudet.html#min-max-widths
+fn clamp_width(props: &BoxProps, width: f64, containing_width: f64, font_size: f64) -> f64 {
+    let width = match size_px(props, "max-width", containing_width, font_size) {
+        Some(max) => width.min(max),
+        None => width,
+    };
+    match size_px(props, "min-width", containing_width, font_size) {
+        Some(min) => width.max(min),
+        None => width,
+    }
+}
+
+/// `translate` moves `layout_box` and its descendants by `(dx, dy)`.
+fn translate(layout_box: &mut LayoutBox, dx: f64, dy: f64) {
+    layout_box.dimensions.x += dx;
+    layout_box.dimensions.y += dy;
+    for child in &mut layout_box.children {
+        translate(child, dx, dy);
+    }
+}
+
+/// `paint` draws `layout_box` and its descendants at `dimensions` computed by `layout`.
+pub fn paint(painter: &impl Painter, layout_box: &LayoutBox) {
+    paint_in(painter, layout_box, false);
+}
+
+/// `is_underlined` tells whether the box declares `text-decoration: underline`.
+fn is_underlined(layout_box: &LayoutBox) -> bool {
+    layout_box.box_type.get_props().is_some_and(|props| {
+        props.properties.get("text-decoration") == Some(&CSSValue::Keyword("underline".to_string()))
+    })
+}
+
+/// `underline` returns the both ends of the line under a text drawn at `tl`.
+/// The line is put on the baseline, where `draw_text` puts the bottom of the glyphs.
+fn underline(tl: &Point, text_width: f64, glyph_height: f64) -> (Point, Point) {
+    let y = tl.y + glyph_height;
+    (
+        Point { x: tl.x, y },
+        Point {
+            x: tl.x + text_width,
+            y,
+        },
+    )
+}
+
+/// `paint_in` draws `layout_box` and its descendants.
+/// `underlined` is true when an ancestor is underlined, since a text decoration applies to all the texts in the box.
+/// See https://www.w3.org/TR/css-text-decor-3/#line-decoration
 ///
-/// 外側の箱の大きさは内側の箱の数とかで決まる
-/// - 再帰で出来そう?: 先に子の箱の大きさを計算
-/// - 基準座標(pos)を持っておいて各layout_boxの位置(箱の左上の座標, 幅と高さ)を計算する
-pub fn paint<'a>(pos: &Point, canvas: &CanvasAPI, layout_box: &LayoutBox<'a>) {
-    // 描画する
-    let mut child_pos = pos.clone();
-    let max_child_h = layout_box
-        .children
-        .iter()
-        .map(|c| calc_size(canvas, c).1)
-        .max_by(|a, b| a.total_cmp(b))
-        .unwrap_or(0.0);
-    for (i, child) in layout_box.children.iter().enumerate() {
-        // 大きさを計算
-        let (ch_w, _ch_h) = calc_size(canvas, child);
-        // log::info!(
-        //     "child {}, child_pos={}, {}x{}\n{}",
-        //     pos,
-        //     child_pos,
-        //     w.round(),
-        //     h.round(),
-        //     child.box_type
-        // );
-
-        // 先頭じゃなくblockだったら改行
-        if i != 0 && !child.box_type.is_inline() {
-            child_pos.x = pos.x;
-            child_pos.y += max_child_h;
-        }
-
-        // log::debug!("{}", layout_box.box_type);
-        paint(&child_pos, canvas, child);
-
-        child_pos.x += ch_w;
-    }
-
-    // 大きさを計算
-    // TODO: calc_sizeを呼ぶ回数をO(N)に減らせる
-    let (w, h) = calc_size(canvas, layout_box);
-    // log::debug!(
-    //     "{}, {}x{}\n{}",
-    //     pos,
-    //     w.round(),
-    //     h.round(),
-    //     layout_box.box_type
-    // );
-    if let Some(props) = &layout_box.box_type.get_props() {
-        match props.node_type {
+/// The background of a box is painted before its descendants so that it does not hide them.
+fn paint_in(painter: &impl Painter, layout_box: &LayoutBox, underlined: bool) {
+    let underlined = underlined || is_underlined(layout_box);
+    let Dimensions {
+        x,
+        y,
+        width,
+        height,
+    } = layout_box.dimensions;
+    let props = layout_box.box_type.get_props();
+    // `opacity` applies to the box and its contents as a whole
+    // See https://www.w3.org/TR/css-color-4/#transparency
+    let opacity = props.and_then(opacity);
+    if let Some(opacity) = opacity {
+        painter.push_opacity(opacity);
+    }
+    if let Some(background) = props.and_then(background_color) {
+        painter.fill_rect(&Point { x, y }, width, height, &background);
+    }
+    // the contents of `overflow: hidden` are clipped to the box
+    // See https://www.w3.org/TR/css-overflow-3/#overflow-properties
+    let clipped = props.is_some_and(|props| {
+        matches!(*props.node_type, NodeType::Element(_))
+            && props.properties.get("overflow") == Some(&CSSValue::Keyword("hidden".to_string()))
+    });
+    if clipped {
+        painter.push_clip(&Point { x, y }, width, height);
+    }
+    for child in &layout_box.children {
+        paint_in(painter, child, underlined);
+    }
+    if clipped {
+        painter.pop_clip();
+    }
+
+    if let Some(props) = props {
+        match &*props.node_type {
             NodeType::Text(text) => {
-                canvas.draw_text(&pos, text.data.as_str());
+                // 行の高さとglyphの高さの差は上下に半分ずつ配分する
+                let font_family = font_family(props);
+                let (text_width, glyph_height) =
+                    painter.measure_text(text.data.as_str(), &font_family);
+                let tl = Point {
+                    x,
+                    y: y + (height - glyph_height) / 2.,
+                };
+                painter.draw_text(&tl, text.data.as_str(), &font_family, &text_color(props));
+                if underlined {
+                    let (from, to) = underline(&tl, text_width, glyph_height);
+                    painter.draw_line(&from, &to);
+                }
             }
             NodeType::Element(_) => {
-                canvas.draw_rect(&pos, w, h);
+                painter.stroke_rect(&Point { x, y }, width, height);
+            }
+            NodeType::Comment(_) => {}
+        }
+    }
+    if opacity.is_some() {
+        painter.pop_opacity();
+    }
+}
+
+/// `FixedMeasurer` measures every character as an 8x16 box.
+#[cfg(test)]
+pub(crate) struct FixedMeasurer;
+
+#[cfg(test)]
+impl TextMeasurer for FixedMeasurer {
+    fn measure_text(&self, text: &str, _font_family: &str) -> (f64, f64) {
+        (text.chars().count() as f64 * 8., 16.)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dom::{css, html, style::to_styled_node};
+    use std::cell::{Cell, RefCell};
+
+    fn document_size(html: &str, css: &str) -> (f64, f64) {
+        let node = html::parse(html).unwrap();
+        let stylesheet = css::parse(css).unwrap();
+        let layout_box = LayoutBox::new(to_styled_node(&node, &stylesheet).unwrap());
+        calc_size(&FixedMeasurer, &layout_box, DEFAULT_FONT_SIZE)
+    }
+
+    #[test]
+    fn test_calc_size() {
+        let html = "<body><p>hello</p><p>world</p>foo<span>bar</span></body>";
+        let css = "body, p { display: block; }";
+        assert_eq!(document_size(html, css), (48., 48.));
+    }
+
+    #[test]
+    fn test_line_height() {
+        let html = "<body><p>hello</p><p>world</p></body>";
+        let (_, height) = document_size(html, "body, p { display: block; }");
+        assert_eq!(height, 32.);
+
+        let (_, taller) = document_size(html, "body, p { display: block; } p { line-height: 2; }");
+        assert_eq!(taller, 64.);
+
+        // `line-height` is inherited
+        let (_, taller) = document_size(
+            html,
+            "body { display: block; line-height: 20px; } p { display: block; }",
+        );
+        assert_eq!(taller, 40.);
+
+        // `em` is relative to the font size of the box
+        let (_, taller) = document_size(
+            html,
+            "body, p { display: block; } p { font-size: 20px; line-height: 1.5em; }",
+        );
+        assert_eq!(taller, 60.);
+    }
+
+    #[test]
+    fn test_text_align() {
+        let node = html::parse(
+            r#"<body><p>hello</p><p class="center">hi</p><p class="right">hi</p></body>"#,
+        )
+        .unwrap();
+        let stylesheet = css::parse(
+            "body, p { display: block; } .center { text-align: center; } .right { text-align: right; }",
+        )
+        .unwrap();
+        let mut layout_box = LayoutBox::new(to_styled_node(&node, &stylesheet).unwrap());
+        layout(&FixedMeasurer, &mut layout_box, 40.);
+
+        // body > p > text
+        let text = |i: usize| &layout_box.children[i].children[0].dimensions;
+        assert_eq!(
+            text(0),
+            &Dimensions {
+                x: 0.,
+                y: 0.,
+                width: 40.,
+                height: 16.
+            }
+        );
+        assert_eq!((text(1).x, text(1).y), (12., 16.));
+        assert_eq!((text(2).x, text(2).y), (24., 32.));
+        assert_eq!(layout_box.children[1].dimensions.width, 40.);
+    }
+
+    #[test]
+    fn test_font_family() {
+        let node = html::parse("<body><p>hello</p><span>world</span></body>").unwrap();
+        let stylesheet = css::parse(
+            "body { font-family: Helvetica, Arial, sans-serif; } p { font-family: monospace; }",
+        )
+        .unwrap();
+        let layout_box = LayoutBox::new(to_styled_node(&node, &stylesheet).unwrap());
+        // `font_family` of the text in `layout_box`
+        let text_font = |layout_box: &LayoutBox| {
+            let text = &layout_box.children[0];
+            font_family(text.box_type.get_props().unwrap())
+        };
+        assert_eq!(text_font(&layout_box.children[0]), "monospace");
+        assert_eq!(text_font(&layout_box.children[1]), "Helvetica");
+
+        let node = html::parse("<body>hello</body>").unwrap();
+        let layout_box = LayoutBox::new(to_styled_node(&node, &css::parse("").unwrap()).unwrap());
+        assert_eq!(text_font(&layout_box), "serif");
+
+        let stylesheet = css::parse(r#"body { font-family: "Times New Roman", serif; }"#).unwrap();
+        let layout_box = LayoutBox::new(to_styled_node(&node, &stylesheet).unwrap());
+        assert_eq!(text_font(&layout_box), r#""Times New Roman""#);
+    }
+
+    #[test]
+    fn test_layout_caches_content_size() {
+        /// `CountingMeasurer` is `FixedMeasurer` counting how many times it measures.
+        struct CountingMeasurer(Cell<usize>);
+
+        impl TextMeasurer for CountingMeasurer {
+            fn measure_text(&self, text: &str, font_family: &str) -> (f64, f64) {
+                self.0.set(self.0.get() + 1);
+                FixedMeasurer.measure_text(text, font_family)
+            }
+        }
+
+        let node = html::parse("<body><p>hello</p><p>world<span>!</span></p></body>").unwrap();
+        let stylesheet = css::parse("body, p { display: block; }").unwrap();
+        let mut layout_box = LayoutBox::new(to_styled_node(&node, &stylesheet).unwrap());
+        assert_eq!(layout_box.content_size, None);
+
+        let measurer = CountingMeasurer(Cell::new(0));
+        layout(&measurer, &mut layout_box, 100.);
+        // each of the 3 texts is measured once
+        assert_eq!(measurer.0.get(), 3);
+        assert_eq!(layout_box.content_size, Some((48., 32.)));
+        assert_eq!(layout_box.children[1].content_size, Some((48., 16.)));
+
+        assert_eq!(
+            calc_size(&measurer, &layout_box, DEFAULT_FONT_SIZE),
+            (48., 32.)
+        );
+        assert_eq!(
+            calc_size(&measurer, &layout_box, DEFAULT_FONT_SIZE),
+            (48., 32.)
+        );
+        assert_eq!(measurer.0.get(), 3);
+    }
+
+    #[test]
+    fn test_viewport_width() {
+        let node =
+            html::parse("<body><span>hello</span><span>world</span><p>!</p></body>").unwrap();
+        let stylesheet = css::parse("body, p { display: block; }").unwrap();
+        let layout_with = |viewport_width: f64| {
+            let mut layout_box = LayoutBox::new(to_styled_node(&node, &stylesheet).unwrap());
+            layout(&FixedMeasurer, &mut layout_box, viewport_width);
+            let dimensions = layout_box.dimensions.clone();
+            let p = layout_box.children[1].dimensions.clone();
+            (dimensions, p)
+        };
+
+        let (body, p) = layout_with(100.);
+        assert_eq!((body.width, body.height), (100., 32.));
+        assert_eq!((p.y, p.width), (16., 100.));
+
+        // `world` does not fit in the first row
+        let (body, p) = layout_with(60.);
+        assert_eq!((body.width, body.height), (60., 48.));
+        assert_eq!((p.y, p.width), (32., 60.));
+    }
+
+    #[test]
+    fn test_position_relative() {
+        let node = html::parse(
+            r#"<body><p>hello</p><p class="shifted">world<span>!</span></p><p>foo</p></body>"#,
+        )
+        .unwrap();
+        let stylesheet = css::parse(
+            "body, p { display: block; } .shifted { position: relative; top: 5px; left: -10px; }",
+        )
+        .unwrap();
+        let mut layout_box = LayoutBox::new(to_styled_node(&node, &stylesheet).unwrap());
+        layout(&FixedMeasurer, &mut layout_box, 100.);
+
+        let shifted = &layout_box.children[1];
+        assert_eq!((shifted.dimensions.x, shifted.dimensions.y), (-10., 21.));
+        // the descendants move together
+        let exclamation = &shifted.children[1].dimensions;
+        assert_eq!((exclamation.x, exclamation.y), (30., 21.));
+        // the following sibling stays in the flow
+        let foo = &layout_box.children[2].dimensions;
+        assert_eq!((foo.x, foo.y), (0., 32.));
+    }
+
+    #[test]
+    fn test_white_space_nowrap() {
+        let node = html::parse(
+            r#"<body><p>hello<span>world</span></p><p class="nowrap">hello<span>world</span></p></body>"#,
+        )
+        .unwrap();
+        let stylesheet =
+            css::parse("body, p { display: block; } .nowrap { white-space: nowrap; }").unwrap();
+        let mut layout_box = LayoutBox::new(to_styled_node(&node, &stylesheet).unwrap());
+        layout(&FixedMeasurer, &mut layout_box, 50.);
+
+        assert_eq!(layout_box.children[0].dimensions.height, 32.);
+        // the row overflows the viewport instead
+        let nowrap = &layout_box.children[1];
+        assert_eq!(nowrap.dimensions.height, 16.);
+        assert_eq!(nowrap.children[1].dimensions.x, 40.);
+    }
+
+    #[test]
+    fn test_line_break() {
+        let node = html::parse(r#"<body><p>hello<br>world</p><p>a<br><br>b</p></body>"#).unwrap();
+        let stylesheet = css::parse("body, p { display: block; }").unwrap();
+        let mut layout_box = LayoutBox::new(to_styled_node(&node, &stylesheet).unwrap());
+        layout(&FixedMeasurer, &mut layout_box, 1000.);
+
+        // the text after <br> starts a new row even though the row has room for it
+        let p = &layout_box.children[0];
+        assert_eq!(p.dimensions.height, 32.);
+        let world = &p.children[2];
+        assert_eq!((world.dimensions.x, world.dimensions.y), (0., 16.));
+        assert_eq!(calc_size(&FixedMeasurer, p, DEFAULT_FONT_SIZE), (40., 32.));
+
+        // consecutive line breaks leave an empty row
+        let p = &layout_box.children[1];
+        assert_eq!(p.dimensions.height, 48.);
+        assert_eq!(p.children[3].dimensions.y, 64.);
+        assert_eq!(calc_size(&FixedMeasurer, p, DEFAULT_FONT_SIZE), (8., 48.));
+    }
+
+    #[test]
+    fn test_underline() {
+        let node =
+            html::parse(r#"<body><p class="u">hello<span>world</span></p><p>foo</p></body>"#)
+                .unwrap();
+        let stylesheet =
+            css::parse("body, p { display: block; } .u { text-decoration: underline; }").unwrap();
+        let layout_box = LayoutBox::new(to_styled_node(&node, &stylesheet).unwrap());
+        assert!(is_underlined(&layout_box.children[0]));
+        assert!(!is_underlined(&layout_box.children[1]));
+
+        let (from, to) = underline(&Point { x: 10., y: 20. }, 40., 16.);
+        assert_eq!(from, Point { x: 10., y: 36. });
+        assert_eq!(to, Point { x: 50., y: 36. });
+    }
+
+    /// `RecordingPainter` records the draw calls instead of drawing.
+    struct RecordingPainter(RefCell<Vec<String>>);
+
+    impl TextMeasurer for RecordingPainter {
+        fn measure_text(&self, text: &str, font_family: &str) -> (f64, f64) {
+            FixedMeasurer.measure_text(text, font_family)
+        }
+    }
+
+    impl Painter for RecordingPainter {
+        fn fill_rect(&self, pos: &Point, w: f64, h: f64, color: &Color) {
+            self.0.borrow_mut().push(format!(
+                "fill_rect ({}, {}) {}x{} {}",
+                pos.x,
+                pos.y,
+                w,
+                h,
+                color.to_css_string()
+            ));
+        }
+
+        fn stroke_rect(&self, pos: &Point, w: f64, h: f64) {
+            self.0
+                .borrow_mut()
+                .push(format!("stroke_rect ({}, {}) {}x{}", pos.x, pos.y, w, h));
+        }
+
+        fn draw_text(&self, tl: &Point, text: &str, _font_family: &str, color: &Color) {
+            self.0.borrow_mut().push(format!(
+                "draw_text ({}, {}) {:?} {}",
+                tl.x,
+                tl.y,
+                text,
+                color.to_css_string()
+            ));
+        }
+
+        fn draw_line(&self, from: &Point, to: &Point) {
+            self.0.borrow_mut().push(format!(
+                "draw_line ({}, {}) ({}, {})",
+                from.x, from.y, to.x, to.y
+            ));
+        }
+
+        fn push_clip(&self, pos: &Point, w: f64, h: f64) {
+            self.0
+                .borrow_mut()
+                .push(format!("push_clip ({}, {}) {}x{}", pos.x, pos.y, w, h));
+        }
+
+        fn pop_clip(&self) {
+            self.0.borrow_mut().push("pop_clip".to_string());
+        }
+
+        fn push_opacity(&self, alpha: f64) {
+            self.0.borrow_mut().push(format!("push_opacity {}", alpha));
+        }
+
+        fn pop_opacity(&self) {
+            self.0.borrow_mut().push("pop_opacity".to_string());
+        }
+    }
+
+    /// `RasterPainter` rasterizes the drawings into a grid of 1x1 pixels, each of which is the coverage from 0 (nothing is drawn) to 1.
+    /// A text fills the box `FixedMeasurer` measures it as, and the outline of a rectangle is not drawn.
+    struct RasterPainter {
+        pixels: RefCell<Vec<Vec<f64>>>,
+        /// `clips` are the rectangles pushed by `push_clip` as `(left, top, right, bottom)`.
+        clips: RefCell<Vec<(f64, f64, f64, f64)>>,
+        /// `opacities` are the alphas pushed by `push_opacity`.
+        opacities: RefCell<Vec<f64>>,
+    }
+
+    impl RasterPainter {
+        fn new(width: usize, height: usize) -> Self {
+            Self {
+                pixels: RefCell::new(vec![vec![0.; width]; height]),
+                clips: RefCell::new(vec![]),
+                opacities: RefCell::new(vec![]),
             }
         }
+
+        fn fill(&self, pos: &Point, w: f64, h: f64, alpha: f64) {
+            let alpha = alpha * self.opacities.borrow().iter().product::<f64>();
+            let (mut left, mut top, mut right, mut bottom) = (pos.x, pos.y, pos.x + w, pos.y + h);
+            for &(l, t, r, b) in self.clips.borrow().iter() {
+                (left, top, right, bottom) = (left.max(l), top.max(t), right.min(r), bottom.min(b));
+            }
+            let mut pixels = self.pixels.borrow_mut();
+            let height = pixels.len();
+            for row in
+                pixels[top.max(0.) as usize..(bottom.max(0.) as usize).min(height)].iter_mut()
+            {
+                let width = row.len();
+                for pixel in
+                    row[left.max(0.) as usize..(right.max(0.) as usize).min(width)].iter_mut()
+                {
+                    // source-over compositing
+                    *pixel += alpha * (1. - *pixel);
+                }
+            }
+        }
+
+        fn coverage(&self, x: usize, y: usize) -> f64 {
+            self.pixels.borrow()[y][x]
+        }
+
+        fn is_drawn(&self, x: usize, y: usize) -> bool {
+            self.coverage(x, y) > 0.
+        }
+    }
+
+    impl TextMeasurer for RasterPainter {
+        fn measure_text(&self, text: &str, font_family: &str) -> (f64, f64) {
+            FixedMeasurer.measure_text(text, font_family)
+        }
+    }
+
+    impl Painter for RasterPainter {
+        fn fill_rect(&self, pos: &Point, w: f64, h: f64, color: &Color) {
+            self.fill(pos, w, h, color.a);
+        }
+
+        fn stroke_rect(&self, _pos: &Point, _w: f64, _h: f64) {}
+
+        fn draw_text(&self, tl: &Point, text: &str, font_family: &str, color: &Color) {
+            let (w, h) = self.measure_text(text, font_family);
+            self.fill(tl, w, h, color.a);
+        }
+
+        fn draw_line(&self, _from: &Point, _to: &Point) {}
+
+        fn push_clip(&self, pos: &Point, w: f64, h: f64) {
+            self.clips
+                .borrow_mut()
+                .push((pos.x, pos.y, pos.x + w, pos.y + h));
+        }
+
+        fn pop_clip(&self) {
+            self.clips.borrow_mut().pop();
+        }
+
+        fn push_opacity(&self, alpha: f64) {
+            self.opacities.borrow_mut().push(alpha);
+        }
+
+        fn pop_opacity(&self) {
+            self.opacities.borrow_mut().pop();
+        }
+    }
+
+    #[test]
+    fn test_opacity() {
+        let node = html::parse(
+            r#"<body><div class="half"><p>a</p></div><div>b</div><div class="over">c</div></body>"#,
+        )
+        .unwrap();
+        let stylesheet = css::parse(
+            "body, div, p { display: block; } div { background-color: black; } .half { opacity: 0.5; } .over { opacity: 2; }",
+        )
+        .unwrap();
+        let mut layout_box = LayoutBox::new(to_styled_node(&node, &stylesheet).unwrap());
+        layout(&FixedMeasurer, &mut layout_box, 100.);
+
+        let painter = RasterPainter::new(100, 48);
+        paint(&painter, &layout_box);
+        // the background is half transparent and the text on it is as well
+        assert_eq!(painter.coverage(50, 8), 0.5);
+        assert_eq!(painter.coverage(4, 8), 0.75);
+        assert_eq!(painter.coverage(50, 24), 1.);
+        // the opacity is clamped to 1
+        assert_eq!(painter.coverage(50, 40), 1.);
+        assert!(painter.opacities.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_overflow_hidden() {
+        let node = html::parse(
+            r#"<body><div class="hidden">hello world</div><div>hello world</div></body>"#,
+        )
+        .unwrap();
+        let stylesheet = css::parse(
+            "body, div { display: block; } div { width: 20px; white-space: nowrap; } .hidden { overflow: hidden; }",
+        )
+        .unwrap();
+        let mut layout_box = LayoutBox::new(to_styled_node(&node, &stylesheet).unwrap());
+        layout(&FixedMeasurer, &mut layout_box, 100.);
+
+        let painter = RasterPainter::new(100, 32);
+        paint(&painter, &layout_box);
+        // the text is 88px wide in a 20px wide box
+        assert!(painter.is_drawn(19, 8));
+        assert!(!painter.is_drawn(20, 8));
+        assert!(!painter.is_drawn(80, 8));
+        // without `overflow: hidden` the text overflows the box
+        assert!(painter.is_drawn(19, 24));
+        assert!(painter.is_drawn(80, 24));
+        assert!(painter.clips.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_paint_calls() {
+        let node = html::parse(r#"<div><p>hi</p><span>u</span></div>"#).unwrap();
+        let stylesheet = css::parse(
+            "div, p { display: block; } p { background-color: red; } span { text-decoration: underline; }",
+        )
+        .unwrap();
+        let mut layout_box = LayoutBox::new(to_styled_node(&node, &stylesheet).unwrap());
+        layout(&FixedMeasurer, &mut layout_box, 100.);
+
+        let painter = RecordingPainter(RefCell::new(vec![]));
+        paint(&painter, &layout_box);
+        assert_eq!(
+            painter.0.into_inner(),
+            vec![
+                "fill_rect (0, 0) 100x16 rgb(255, 0, 0)",
+                r#"draw_text (0, 0) "hi" rgb(0, 0, 0)"#,
+                "stroke_rect (0, 0) 100x16",
+                r#"draw_text (0, 16) "u" rgb(0, 0, 0)"#,
+                "draw_line (0, 32) (8, 32)",
+                "stroke_rect (0, 16) 8x16",
+                "stroke_rect (0, 0) 100x32",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_paint_list_item() {
+        let node = html::parse(r#"<ul><li>a</li></ul>"#).unwrap();
+        let stylesheet = css::parse("ul { display: block; } li { display: list-item; }").unwrap();
+        let mut layout_box = LayoutBox::new(to_styled_node(&node, &stylesheet).unwrap());
+        layout(&FixedMeasurer, &mut layout_box, 100.);
+
+        // the bullet is drawn to the left of the contents
+        let painter = RecordingPainter(RefCell::new(vec![]));
+        paint(&painter, &layout_box);
+        assert_eq!(
+            painter.0.into_inner(),
+            vec![
+                r#"draw_text (0, 0) "• " rgb(0, 0, 0)"#,
+                r#"draw_text (16, 0) "a" rgb(0, 0, 0)"#,
+                "stroke_rect (0, 0) 100x16",
+                "stroke_rect (0, 0) 100x16",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_text_color() {
+        let node =
+            html::parse(r##"<body><p class="red">a</p><p class="hex">b</p><p>c</p></body>"##)
+                .unwrap();
+        let stylesheet =
+            css::parse("body, p { display: block; } .red { color: red; } .hex { color: #00f; }")
+                .unwrap();
+        let layout_box = LayoutBox::new(to_styled_node(&node, &stylesheet).unwrap());
+        let text_style = |i: usize| {
+            let text = &layout_box.children[i].children[0];
+            text_color(text.box_type.get_props().unwrap()).to_canvas_style()
+        };
+        assert_eq!(text_style(0), "rgba(255, 0, 0, 1)");
+        assert_eq!(text_style(1), "rgba(0, 0, 255, 1)");
+        assert_eq!(text_style(2), "rgba(0, 0, 0, 1)");
+    }
+
+    #[test]
+    fn test_width() {
+        let node = html::parse(
+            r#"<body><div class="half"><p>hello</p></div><div class="fixed">a</div><span class="half">b</span></body>"#,
+        )
+        .unwrap();
+        let stylesheet = css::parse(
+            "body, div, p { display: block; } .half { width: 50%; } .fixed { width: 30px; }",
+        )
+        .unwrap();
+        let mut layout_box = LayoutBox::new(to_styled_node(&node, &stylesheet).unwrap());
+        layout(&FixedMeasurer, &mut layout_box, 200.);
+
+        let half = &layout_box.children[0];
+        assert_eq!(half.dimensions.width, 100.);
+        // percentages of the descendants are resolved against the resolved width
+        assert_eq!(half.children[0].dimensions.width, 100.);
+        assert_eq!(layout_box.children[1].dimensions.width, 30.);
+        // `width` does not apply to an inline box
+        assert_eq!(layout_box.children[2].children[0].dimensions.width, 8.);
+    }
+
+    #[test]
+    fn test_inline_block_width() {
+        let node = html::parse(
+            r#"<div><span class="fixed">a</span><span class="fixed">b</span><span class="auto">c</span></div>"#,
+        )
+        .unwrap();
+        let stylesheet = css::parse(
+            "div { display: block; } .fixed { display: inline-block; width: 50px; } .auto { display: inline-block; }",
+        )
+        .unwrap();
+        let mut layout_box = LayoutBox::new(to_styled_node(&node, &stylesheet).unwrap());
+        layout(&FixedMeasurer, &mut layout_box, 200.);
+
+        // inline-block boxes sit side by side, keeping their explicit widths
+        let dimensions = |i: usize| {
+            let Dimensions { x, y, width, .. } = layout_box.children[i].dimensions;
+            (x, y, width)
+        };
+        assert_eq!(dimensions(0), (0., 0., 50.));
+        assert_eq!(dimensions(1), (50., 0., 50.));
+        // without `width`, an inline-block box is as wide as its contents
+        assert_eq!(dimensions(2), (100., 0., 8.));
+    }
+
+    #[test]
+    fn test_min_max_width() {
+        let node = html::parse(
+            r#"<body><div class="max">a</div><div class="min">b</div><div class="both">c</div><div class="conflict">d</div><div class="ratio">e</div></body>"#,
+        )
+        .unwrap();
+        let stylesheet = css::parse(
+            "body, div { display: block; } .max { max-width: 50px; } .min { width: 30px; min-width: 60px; } .both { width: 10px; min-width: 20px; max-width: 40px; } .conflict { min-width: 80px; max-width: 40px; } .ratio { max-width: 25%; }",
+        )
+        .unwrap();
+        let mut layout_box = LayoutBox::new(to_styled_node(&node, &stylesheet).unwrap());
+        layout(&FixedMeasurer, &mut layout_box, 200.);
+
+        let widths: Vec<f64> = layout_box
+            .children
+            .iter()
+            .map(|child| child.dimensions.width)
+            .collect();
+        // `min-width` wins when it is larger than `max-width`
+        assert_eq!(widths, vec![50., 60., 20., 80., 50.]);
+    }
+
+    #[test]
+    fn test_box_sizing() {
+        let node = html::parse(
+            r#"<body><div class="content">hello</div><div class="border">hello</div></body>"#,
+        )
+        .unwrap();
+        let stylesheet = css::parse(
+            "body, div { display: block; } div { width: 100px; padding: 10px; } .border { box-sizing: border-box; }",
+        )
+        .unwrap();
+        let mut layout_box = LayoutBox::new(to_styled_node(&node, &stylesheet).unwrap());
+        layout(&FixedMeasurer, &mut layout_box, 200.);
+
+        // the padding is added to `width` under `box-sizing: content-box`
+        let content = &layout_box.children[0];
+        assert_eq!(
+            content.dimensions,
+            Dimensions {
+                x: 0.,
+                y: 0.,
+                width: 120.,
+                height: 36.
+            }
+        );
+        assert_eq!(content.children[0].dimensions.x, 10.);
+        assert_eq!(content.children[0].dimensions.y, 10.);
+
+        // the padding is included in `width` under `box-sizing: border-box`
+        let border = &layout_box.children[1];
+        assert_eq!(
+            border.dimensions,
+            Dimensions {
+                x: 0.,
+                y: 36.,
+                width: 100.,
+                height: 36.
+            }
+        );
+        assert_eq!(border.children[0].dimensions.x, 10.);
+        assert_eq!(border.children[0].dimensions.y, 46.);
+    }
+
+    #[test]
+    fn test_em() {
+        let node = html::parse(r#"<body><div>hello</div></body>"#).unwrap();
+        let stylesheet = css::parse(
+            "body, div { display: block; } body { padding: 1em; } div { margin: 2em; font-size: 10px; width: 10em; padding: 1em; }",
+        )
+        .unwrap();
+        let mut layout_box = LayoutBox::new(to_styled_node(&node, &stylesheet).unwrap());
+        layout(&FixedMeasurer, &mut layout_box, 200.);
+
+        // `em` of the root is relative to the default font size
+        let div = &layout_box.children[0];
+        assert_eq!((div.dimensions.x, div.dimensions.y), (16., 16.));
+
+        // `em` of the div is relative to its own `font-size`
+        let props = div.box_type.get_props().unwrap();
+        let font_size = resolve_font_size(props, DEFAULT_FONT_SIZE);
+        assert_eq!(font_size, 10.);
+        assert_eq!(length_px(props, "margin-top", font_size), Some(20.));
+        assert_eq!(div.dimensions.width, 120.);
+        assert_eq!(div.children[0].dimensions.x, 26.);
     }
 }
@@ -0,0 +1,169 @@
+//! This module includes the pipeline from a document to a laid-out box tree, shared by the entry points.
+
+use crate::paint::{layout, TextMeasurer};
+use anyhow::{anyhow, Result};
+use dom::{
+    css::{self, Stylesheet},
+    dom::{Node, NodeType},
+    html,
+    layout::{build_layout_tree, LayoutBox},
+};
+use engine::runtime::Runtime;
+use std::{cell::RefCell, rc::Rc};
+#[cfg(not(target_arch = "wasm32"))]
+use std::{fs, path::Path};
+
+fn collect_tag_inners(node: &Node, tag_name: &str) -> Vec<String> {
+    node.find_all(
+        |node| matches!(&node.node_type, NodeType::Element(element) if element.tag_name == tag_name),
+    )
+    .iter()
+    .map(|node| node.inner_text())
+    .collect()
+}
+
+/// `execute_inline_scripts` runs the inline scripts of `dom` as one script and returns the value of its last statement,
+/// or the error which stopped it.
+fn execute_inline_scripts(
+    runtime: &mut Runtime,
+    dom: &RefCell<Box<Node>>,
+) -> Result<String, String> {
+    let scripts = collect_tag_inners(&dom.borrow(), "script").join("\n");
+    runtime.execute("(inline)", scripts.as_str())
+}
+
+/// `load` parses `html` and runs its inline scripts.
+pub fn load(html: &str) -> Result<Rc<RefCell<Box<Node>>>> {
+    let dom = Rc::new(RefCell::new(html::parse(html)?));
+
+    let mut runtime = Runtime::new(dom.clone());
+    let result = execute_inline_scripts(&mut runtime, &dom).map_err(|e| anyhow!(e))?;
+    log::info!("Result: {}", result);
+
+    Ok(dom)
+}
+
+/// `render` styles and lays out `dom` in a viewport of `viewport_width`, then hands the box tree to `paint`.
+pub fn render<M: TextMeasurer>(
+    dom: &Node,
+    stylesheet: &Stylesheet,
+    measurer: &M,
+    viewport_width: f64,
+    paint: impl FnOnce(&LayoutBox),
+) -> Result<()> {
+    let mut layout_box = build_layout_tree(dom, stylesheet, Some(viewport_width))?;
+    layout(measurer, &mut layout_box, viewport_width);
+    paint(&layout_box);
+    Ok(())
+}
+
+/// `pipeline` runs the whole pipeline from `html` and `css` to `paint`, and returns the document after its inline scripts have run.
+///
+/// NOTE: stylesheets linked from the document are not loaded since fetching them is asynchronous.
+pub fn pipeline<M: TextMeasurer>(
+    html: &str,
+    css: &str,
+    measurer: &M,
+    viewport_width: f64,
+    paint: impl FnOnce(&LayoutBox),
+) -> Result<Rc<RefCell<Box<Node>>>> {
+    let dom = load(html)?;
+    let stylesheet = css::parse(css)?;
+    render(&dom.borrow(), &stylesheet, measurer, viewport_width, paint)?;
+    Ok(dom)
+}
+
+/// `debug_dump` runs `pipeline` and writes the artifacts into `out_dir` instead of painting:
+/// - `dom.txt`: the document after its inline scripts have run
+/// - `styles.json`: the computed properties and the dimensions of each box
+/// - `layout.txt`: the laid-out box tree
+#[cfg(not(target_arch = "wasm32"))]
+pub fn debug_dump<M: TextMeasurer>(
+    html: &str,
+    css: &str,
+    measurer: &M,
+    viewport_width: f64,
+    out_dir: &Path,
+) -> Result<()> {
+    let mut dumps = None;
+    let dom = pipeline(html, css, measurer, viewport_width, |layout_box| {
+        // the alternate form of `serde_json::Value` is pretty-printed
+        dumps = Some((
+            format!("{:#}", layout_box.to_json()),
+            layout_box.debug_layout(0),
+        ));
+    })?;
+    let (styles, layout) = dumps.expect("the box tree is not painted");
+
+    fs::create_dir_all(out_dir)?;
+    fs::write(out_dir.join("dom.txt"), dom.borrow().debug_tree(0))?;
+    fs::write(out_dir.join("styles.json"), styles)?;
+    fs::write(out_dir.join("layout.txt"), layout)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::paint::FixedMeasurer;
+    use dom::layout::Dimensions;
+
+    #[test]
+    fn test_pipeline() {
+        let html = r#"<body><script>document.getElementById("result").innerText = "fuga!";</script><p>hello</p><span id="result">hoge</span></body>"#;
+        let css = "script { display: none; } body, p { display: block; }";
+
+        let mut painted = None;
+        pipeline(html, css, &FixedMeasurer, 100., |layout_box| {
+            painted = Some(layout_box.debug(0));
+            // the text replaced by the script is laid out
+            let result = &layout_box.children[1].children[0].children[0];
+            assert_eq!(
+                result.dimensions,
+                Dimensions {
+                    x: 0.,
+                    y: 16.,
+                    width: 40.,
+                    height: 16.
+                }
+            );
+        })
+        .unwrap();
+        assert!(painted.unwrap().contains("text: fuga!"));
+
+        assert!(pipeline("<body>", css, &FixedMeasurer, 100., |_| {}).is_err());
+        assert!(pipeline(html, "body {", &FixedMeasurer, 100., |_| {}).is_err());
+    }
+
+    #[test]
+    fn test_debug_dump() {
+        let dir = std::env::temp_dir().join(format!("small_browser_dump_{}", std::process::id()));
+        let html = r#"<body><script>document.getElementById("result").innerText = "fuga!";</script><p id="result">hoge</p></body>"#;
+        let css = "script { display: none; } body, p { display: block; }";
+        debug_dump(html, css, &FixedMeasurer, 100., &dir).unwrap();
+
+        let read = |name: &str| fs::read_to_string(dir.join(name)).unwrap();
+        assert!(read("dom.txt").contains("Text(\"fuga!\")"));
+        // the boxes are laid out
+        assert!(read("layout.txt").contains("text: fuga! (0, 0) 40x16"));
+        assert!(read("styles.json").contains(r#""tag": "body""#));
+        assert!(read("styles.json").contains(r#""width": 40.0"#));
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(debug_dump("<body>", css, &FixedMeasurer, 100., &dir).is_err());
+    }
+
+    #[test]
+    fn test_execute_inline_scripts() {
+        let run = |html: &str| {
+            let dom = Rc::new(RefCell::new(html::parse(html).unwrap()));
+            execute_inline_scripts(&mut Runtime::new(dom.clone()), &dom)
+        };
+        // the value of the last statement of the last script
+        assert_eq!(
+            run(r#"<body><script>let a = 1;</script><p>hello</p><script>a + 1</script></body>"#),
+            Ok("2".to_string())
+        );
+        assert!(run("<body><script>undefinedFunction()</script></body>").is_err());
+    }
+}
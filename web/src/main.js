@@ -30,14 +30,14 @@ p, div {
     display: inline;
 }`
 
-const rerender = () => {
+const rerender = async () => {
     const html = document.getElementById("html").value;
     const css = document.getElementById("css").value;
-    render(html, css);
+    await render(html, css);
 }
 
 init().then(() => {
-    setup();
+    setup("info");
 
     const html = document.getElementById("html")
     html.value = DEFAULT_HTML;